@@ -11,35 +11,41 @@ pub trait Bits {
     fn reset_bit(&mut self, index: usize);
 }
 
-impl Bits for u8 {
-    const BIT_COUNT: usize = 8;
+macro_rules! impl_bits {
+    ($($ty:ty),*) => {$(
+        impl Bits for $ty {
+            const BIT_COUNT: usize = <$ty>::BITS as usize;
 
-    fn bit(&self, index: usize) -> bool {
-        assert!(index < Self::BIT_COUNT);
-        (self >> index) & 1 == 1
-    }
+            fn bit(&self, index: usize) -> bool {
+                assert!(index < Self::BIT_COUNT);
+                (self >> index) & 1 == 1
+            }
 
-    fn bits(&self, range: RangeInclusive<usize>) -> Self {
-        if range.is_empty() {
-            Self::default()
-        } else {
-            assert!(*range.end() < Self::BIT_COUNT);
-            (self << (Self::BIT_COUNT - 1 - range.end()))
-                >> (Self::BIT_COUNT - 1 - range.end() + range.start())
-        }
-    }
+            fn bits(&self, range: RangeInclusive<usize>) -> Self {
+                if range.is_empty() {
+                    Self::default()
+                } else {
+                    assert!(*range.end() < Self::BIT_COUNT);
+                    (self << (Self::BIT_COUNT - 1 - range.end()))
+                        >> (Self::BIT_COUNT - 1 - range.end() + range.start())
+                }
+            }
 
-    fn set_bit(&mut self, index: usize) {
-        assert!(index < Self::BIT_COUNT);
-        *self |= 1 << index;
-    }
+            fn set_bit(&mut self, index: usize) {
+                assert!(index < Self::BIT_COUNT);
+                *self |= 1 << index;
+            }
 
-    fn reset_bit(&mut self, index: usize) {
-        assert!(index < Self::BIT_COUNT);
-        *self &= !(1 << index);
-    }
+            fn reset_bit(&mut self, index: usize) {
+                assert!(index < Self::BIT_COUNT);
+                *self &= !(1 << index);
+            }
+        }
+    )*};
 }
 
+impl_bits!(u8, u16, u32);
+
 #[cfg(test)]
 mod test {
     use super::Bits;
@@ -99,4 +105,56 @@ mod test {
         byte.reset_bit(1);
         assert_eq!(byte, 0b0000_0000);
     }
+
+    #[test]
+    fn bit_wide() {
+        let word: u16 = 0b1000_0001_0000_0010;
+        assert!(!word.bit(0));
+        assert!(word.bit(1));
+        assert!(word.bit(8));
+        assert!(!word.bit(9));
+        assert!(word.bit(15));
+
+        let dword: u32 = 0x8001_0000;
+        assert!(!dword.bit(0));
+        assert!(dword.bit(16));
+        assert!(!dword.bit(17));
+        assert!(dword.bit(31));
+
+        assert_eq!(u16::BIT_COUNT, 16);
+        assert_eq!(u32::BIT_COUNT, 32);
+    }
+
+    #[test]
+    fn bits_wide() {
+        let word: u16 = 0b1010_1100_0011_0101;
+        assert_eq!(word.bits(0..=3), 0b0101);
+        assert_eq!(word.bits(12..=15), 0b1010);
+        // Range crossing the byte boundary
+        assert_eq!(word.bits(6..=9), 0b0000);
+        assert_eq!(word.bits(4..=11), 0b1100_0011);
+        assert_eq!(word.bits(0..=15), word);
+
+        let dword: u32 = 0x1234_5678;
+        assert_eq!(dword.bits(12..=19), 0x45);
+        assert_eq!(dword.bits(28..=31), 0x1);
+        assert_eq!(dword.bits(0..=31), dword);
+    }
+
+    #[test]
+    fn set_reset_bit_wide() {
+        let mut word: u16 = 0;
+        word.set_bit(15);
+        word.set_bit(8);
+        assert_eq!(word, 0b1000_0001_0000_0000);
+        word.reset_bit(15);
+        assert_eq!(word, 0b0000_0001_0000_0000);
+
+        let mut dword: u32 = u32::MAX;
+        dword.reset_bit(31);
+        dword.reset_bit(0);
+        assert_eq!(dword, 0x7FFF_FFFE);
+        dword.set_bit(0);
+        assert_eq!(dword, 0x7FFF_FFFF);
+    }
 }
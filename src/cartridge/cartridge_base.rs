@@ -1,6 +1,8 @@
 use crate::{
     components::mmu::{ROM_BANK0_END, ROM_BANK0_START, ROM_BANK1_END, ROM_BANK1_START},
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
     state::{CartridgeState, PollState},
+    SaveError, SnapshotError,
 };
 
 use super::{RAM_BANK_SIZE, ROM_BANK_SIZE};
@@ -27,13 +29,13 @@ pub struct CartridgeBase {
 }
 
 impl CartridgeBase {
-    pub fn new(rom: &[u8], header: super::Header) -> Self {
+    pub fn new(rom: Box<[u8]>, header: super::Header) -> Self {
         let ram = match header.ram_banks {
             0 => None,
             ram_banks => Some(vec![0; RAM_BANK_SIZE * ram_banks].into_boxed_slice()),
         };
         Self {
-            rom: rom.into(),
+            rom,
             rom_bank0: 0,
             rom_bank1: 1,
             ram,
@@ -42,6 +44,25 @@ impl CartridgeBase {
             header,
         }
     }
+
+    /// Return the index into `ram` of the external RAM address `addr` (relative to
+    /// 0xA000) in the current RAM bank, or `None` if it lies outside of the RAM.
+    ///
+    /// Addresses beyond the end of a RAM bank are wrapped around to the start of the
+    /// bank.
+    fn ram_index(&self, ram: &[u8], addr: u16) -> Option<usize> {
+        let mut addr = usize::from(addr);
+        if addr >= RAM_BANK_SIZE {
+            tracing::error!(target: "cartridge", "external RAM address {:#06X} is outside of a RAM bank", addr);
+            addr %= RAM_BANK_SIZE;
+        }
+        let index = RAM_BANK_SIZE * self.ram_bank + addr;
+        if index >= ram.len() {
+            tracing::error!(target: "cartridge", "external RAM bank {} (address {:#06X}) does not exist", self.ram_bank, addr);
+            return None;
+        }
+        Some(index)
+    }
 }
 
 impl Debug for CartridgeBase {
@@ -80,9 +101,10 @@ impl super::CartridgeInterface for CartridgeBase {
     fn read_ram(&self, addr: u16) -> u8 {
         if let Some(ram) = &self.ram {
             if self.ram_enabled {
-                assert!(self.ram_bank < self.header.ram_banks);
-                let addr: usize = addr.into();
-                ram[RAM_BANK_SIZE * self.ram_bank + addr]
+                match self.ram_index(ram, addr) {
+                    Some(index) => ram[index],
+                    None => DEFAULT_READ_VALUE,
+                }
             } else {
                 tracing::error!(target: "cartridge", "attempted to read from external RAM, but RAM is not enabled");
                 DEFAULT_READ_VALUE
@@ -94,11 +116,15 @@ impl super::CartridgeInterface for CartridgeBase {
     }
 
     fn write_ram(&mut self, addr: u16, value: u8) {
-        if let Some(ram) = &mut self.ram {
+        if self.ram.is_some() {
             if self.ram_enabled {
-                assert!(self.ram_bank < self.header.ram_banks);
-                let addr: usize = addr.into();
-                ram[RAM_BANK_SIZE * self.ram_bank + addr] = value;
+                let index = self
+                    .ram
+                    .as_deref()
+                    .and_then(|ram| self.ram_index(ram, addr));
+                if let (Some(ram), Some(index)) = (&mut self.ram, index) {
+                    ram[index] = value;
+                }
             } else {
                 tracing::error!(target: "cartridge", "attempted to write to external RAM, but RAM is not enabled");
             }
@@ -110,6 +136,36 @@ impl super::CartridgeInterface for CartridgeBase {
     fn header(&self) -> &super::Header {
         &self.header
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        self.ram.as_deref()
+    }
+
+    fn current_rom_bank(&self) -> (usize, usize) {
+        (self.rom_bank0, self.rom_bank1)
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn load_ram(&mut self, ram: &[u8]) -> Result<(), SaveError> {
+        match &mut self.ram {
+            Some(current) if current.len() == ram.len() => {
+                current.copy_from_slice(ram);
+                Ok(())
+            }
+            Some(current) => Err(SaveError::Size {
+                expected: current.len(),
+                found: ram.len(),
+            }),
+            None => Err(SaveError::NoBattery),
+        }
+    }
 }
 
 impl PollState for CartridgeBase {
@@ -137,3 +193,64 @@ impl PollState for CartridgeBase {
         }
     }
 }
+
+impl Snapshot for CartridgeBase {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u64(self.rom_bank0 as u64);
+        writer.write_u64(self.rom_bank1 as u64);
+        writer.write_u64(self.ram_bank as u64);
+        writer.write_bool(self.ram_enabled);
+        writer.write_bytes(self.ram.as_deref().unwrap_or_default());
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        let read_bank = |reader: &mut SnapshotReader, banks: usize| {
+            usize::try_from(reader.read_u64()?)
+                .ok()
+                .filter(|bank| *bank < banks.max(1))
+                .ok_or(SnapshotError::InvalidValue("bank"))
+        };
+        self.rom_bank0 = read_bank(reader, self.header.rom_banks)?;
+        self.rom_bank1 = read_bank(reader, self.header.rom_banks)?;
+        self.ram_bank = read_bank(reader, self.header.ram_banks)?;
+        self.ram_enabled = reader.read_bool()?;
+        match &mut self.ram {
+            Some(ram) => reader.read_bytes_into(ram)?,
+            None => reader.read_bytes_into(&mut [])?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cartridge::{CartridgeInterface, Header};
+
+    use super::*;
+
+    #[test]
+    fn ram_bounds() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0149] = 0x03;
+        let header = Header::parse(&rom).unwrap();
+        let mut cartridge = CartridgeBase::new(rom.clone().into(), header);
+        cartridge.ram_enabled = true;
+
+        // Writing to the top of a bank does not spill into the next bank
+        cartridge.write_ram(0x1FFF, 0xAB);
+        assert_eq!(cartridge.read_ram(0x1FFF), 0xAB);
+        cartridge.ram_bank = 1;
+        assert_eq!(cartridge.read_ram(0x0000), 0x00);
+
+        // Addresses beyond the end of a bank wrap around within the bank
+        cartridge.write_ram(0x2001, 0xCD);
+        assert_eq!(cartridge.read_ram(0x0001), 0xCD);
+        assert_eq!(cartridge.ram().unwrap()[RAM_BANK_SIZE + 1], 0xCD);
+        assert_eq!(cartridge.ram().unwrap()[2 * RAM_BANK_SIZE + 1], 0x00);
+
+        // Nonexistent banks are not accessed
+        cartridge.ram_bank = 4;
+        cartridge.write_ram(0x0000, 0xEF);
+        assert_eq!(cartridge.read_ram(0x0000), DEFAULT_READ_VALUE);
+    }
+}
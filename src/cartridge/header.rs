@@ -1,18 +1,42 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 use std::num::Wrapping;
 
+use super::CartridgeOptions;
 use crate::RomError;
 
 const MIN_CARTRIDGE_SIZE: usize = 32 * 1024;
 const CARTRIDGE_TITLE_START: usize = 0x0134;
 const CARTRIDGE_TITLE_END: usize = 0x0143;
+const CGB_FLAG: usize = 0x0143;
+/// CGB flag value of a cartridge which supports CGB functions, but also runs on a DMG
+const CGB_FLAG_ENHANCED: u8 = 0x80;
+/// CGB flag value of a cartridge which only runs on a CGB
+const CGB_FLAG_ONLY: u8 = 0xC0;
+const NEW_LICENSEE_CODE_START: usize = 0x0144;
+const NEW_LICENSEE_CODE_END: usize = 0x0145;
+const SGB_FLAG: usize = 0x0146;
 const CARTRIDGE_TYPE: usize = 0x0147;
 const ROM_SIZE: usize = 0x0148;
 const RAM_SIZE: usize = 0x0149;
+/// Largest number of external RAM banks a cartridge can have (128 KiB)
+const MAX_RAM_BANKS: usize = 16;
+const OLD_LICENSEE_CODE: usize = 0x014B;
 const HEADER_CHECKSUM: usize = 0x014D;
+const GLOBAL_CHECKSUM_START: usize = 0x014E;
+const GLOBAL_CHECKSUM_END: usize = 0x014F;
+/// Nintendo logo stored in the cartridge header (and in the boot ROM, which compares
+/// the two)
+pub(crate) const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+/// Old licensee code indicating that the new licensee code should be used instead
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
 
 /// Cartridge header
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     /// Cartridge title
     pub title: String,
@@ -26,10 +50,32 @@ pub struct Header {
     pub checksum: u8,
     /// Header checksum matches computed value
     pub checksum_passed: bool,
+    /// Global checksum
+    pub global_checksum: u16,
+    /// Global checksum matches the sum of all ROM bytes (excluding the global
+    /// checksum itself)
+    pub global_checksum_passed: bool,
+    /// CGB flag (0x80: CGB enhanced, 0xC0: CGB only)
+    pub cgb_flag: u8,
+    /// Game Boy models supported by the cartridge, as indicated by the CGB flag
+    pub cgb_mode: CgbMode,
+    /// SGB flag (0x03: SGB functions supported)
+    pub sgb_flag: u8,
+    /// Old licensee code
+    pub old_licensee_code: u8,
+    /// New licensee code (only used if the old licensee code is 0x33)
+    pub new_licensee_code: Option<String>,
 }
 
 impl Header {
+    /// Parse the cartridge header from the given ROM
     pub fn parse(rom: &[u8]) -> Result<Self, RomError> {
+        Self::parse_with_options(rom, CartridgeOptions::default())
+    }
+
+    /// Parse the cartridge header from the given ROM, replacing header values with
+    /// those given in `options`
+    pub fn parse_with_options(rom: &[u8], options: CartridgeOptions) -> Result<Self, RomError> {
         if rom.len() < MIN_CARTRIDGE_SIZE {
             return Err(RomError::Undersized {
                 expected: MIN_CARTRIDGE_SIZE,
@@ -37,8 +83,14 @@ impl Header {
             });
         }
 
+        // On CGB cartridges, the last byte of the title area holds the CGB flag
+        let cgb_mode = CgbMode::from(rom[CGB_FLAG]);
+        let title_end = match cgb_mode {
+            CgbMode::DmgOnly => CARTRIDGE_TITLE_END,
+            CgbMode::CgbEnhanced | CgbMode::CgbOnly => CARTRIDGE_TITLE_END - 1,
+        };
         let mut title = String::new();
-        for byte in &rom[CARTRIDGE_TITLE_START..=CARTRIDGE_TITLE_END] {
+        for byte in &rom[CARTRIDGE_TITLE_START..=title_end] {
             let ch = *byte as char;
             if *byte == 0 || !ch.is_ascii() {
                 break;
@@ -56,15 +108,22 @@ impl Header {
             }
         };
 
-        let ram_banks: usize = {
-            match rom[RAM_SIZE] {
+        let ram_banks: usize = match options.force_ram_banks {
+            Some(ram_banks) => {
+                if !(1..=MAX_RAM_BANKS).contains(&ram_banks) {
+                    return Err(RomError::InvalidRamBanks(ram_banks));
+                }
+                tracing::info!(target: "cartridge", "using {} RAM banks instead of the header RAM size ${:02X}", ram_banks, rom[RAM_SIZE]);
+                ram_banks
+            }
+            None => match rom[RAM_SIZE] {
                 0x00 => 0,
                 0x02 => 1,
                 0x03 => 4,
                 0x04 => 16,
                 0x05 => 8,
                 _ => return Err(RomError::UnrecognizedRamSize(rom[RAM_SIZE])),
-            }
+            },
         };
 
         let checksum = rom[HEADER_CHECKSUM];
@@ -73,6 +132,23 @@ impl Header {
             computed_checksum = computed_checksum - Wrapping(*byte) - Wrapping(1);
         }
 
+        let global_checksum =
+            u16::from_be_bytes([rom[GLOBAL_CHECKSUM_START], rom[GLOBAL_CHECKSUM_END]]);
+        let mut computed_global_checksum: Wrapping<u16> = Wrapping(0);
+        for (i, byte) in rom.iter().enumerate() {
+            if !(GLOBAL_CHECKSUM_START..=GLOBAL_CHECKSUM_END).contains(&i) {
+                computed_global_checksum += u16::from(*byte);
+            }
+        }
+
+        let old_licensee_code = rom[OLD_LICENSEE_CODE];
+        let new_licensee_code = (old_licensee_code == USE_NEW_LICENSEE_CODE).then(|| {
+            rom[NEW_LICENSEE_CODE_START..=NEW_LICENSEE_CODE_END]
+                .iter()
+                .map(|byte| *byte as char)
+                .collect()
+        });
+
         Ok(Self {
             title,
             cartridge_type,
@@ -80,11 +156,41 @@ impl Header {
             ram_banks,
             checksum,
             checksum_passed: checksum == computed_checksum.0,
+            global_checksum,
+            global_checksum_passed: global_checksum == computed_global_checksum.0,
+            cgb_flag: rom[CGB_FLAG],
+            cgb_mode,
+            sgb_flag: rom[SGB_FLAG],
+            old_licensee_code,
+            new_licensee_code,
         })
     }
 }
 
+/// Game Boy models supported by a cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CgbMode {
+    /// The cartridge does not use any CGB functions
+    DmgOnly,
+    /// The cartridge supports CGB functions, but also runs on a DMG
+    CgbEnhanced,
+    /// The cartridge only runs on a CGB
+    CgbOnly,
+}
+
+impl From<u8> for CgbMode {
+    fn from(cgb_flag: u8) -> Self {
+        match cgb_flag {
+            CGB_FLAG_ENHANCED => Self::CgbEnhanced,
+            CGB_FLAG_ONLY => Self::CgbOnly,
+            _ => Self::DmgOnly,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CartridgeType {
     RomOnly,
     Mbc1,
@@ -116,6 +222,62 @@ pub enum CartridgeType {
     HuC1RamBattery,
 }
 
+impl CartridgeType {
+    /// Returns `true` if the cartridge has battery-backed external RAM
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            Self::Mbc1RamBattery
+                | Self::Mbc2Battery
+                | Self::RomRamBattery
+                | Self::Mmm01RamBattery
+                | Self::Mbc3TimerBattery
+                | Self::Mbc3TimerRamBattery
+                | Self::Mbc3RamBattery
+                | Self::Mbc5RamBattery
+                | Self::Mbc5RumbleRamBattery
+                | Self::Mbc7SensorRumbleRamBattery
+                | Self::HuC1RamBattery
+        )
+    }
+}
+
+impl Display for CartridgeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::RomOnly => "ROM Only",
+            Self::Mbc1 => "MBC1",
+            Self::Mbc1Ram => "MBC1+RAM",
+            Self::Mbc1RamBattery => "MBC1+RAM+Battery",
+            Self::Mbc2 => "MBC2",
+            Self::Mbc2Battery => "MBC2+Battery",
+            Self::RomRam => "ROM+RAM",
+            Self::RomRamBattery => "ROM+RAM+Battery",
+            Self::Mmm01 => "MMM01",
+            Self::Mmm01Ram => "MMM01+RAM",
+            Self::Mmm01RamBattery => "MMM01+RAM+Battery",
+            Self::Mbc3TimerBattery => "MBC3+Timer+Battery",
+            Self::Mbc3TimerRamBattery => "MBC3+Timer+RAM+Battery",
+            Self::Mbc3 => "MBC3",
+            Self::Mbc3Ram => "MBC3+RAM",
+            Self::Mbc3RamBattery => "MBC3+RAM+Battery",
+            Self::Mbc5 => "MBC5",
+            Self::Mbc5Ram => "MBC5+RAM",
+            Self::Mbc5RamBattery => "MBC5+RAM+Battery",
+            Self::Mbc5Rumble => "MBC5+Rumble",
+            Self::Mbc5RumbleRam => "MBC5+Rumble+RAM",
+            Self::Mbc5RumbleRamBattery => "MBC5+Rumble+RAM+Battery",
+            Self::Mbc6 => "MBC6",
+            Self::Mbc7SensorRumbleRamBattery => "MBC7+Sensor+Rumble+RAM+Battery",
+            Self::PocketCamera => "Pocket Camera",
+            Self::BandaiTama5 => "Bandai TAMA5",
+            Self::HuC3 => "HuC3",
+            Self::HuC1RamBattery => "HuC1+RAM+Battery",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl TryFrom<u8> for CartridgeType {
     type Error = RomError;
 
@@ -153,3 +315,147 @@ impl TryFrom<u8> for CartridgeType {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commercial_header() {
+        // Header layout of Pokemon Red (CGB flag and title share 0x0143)
+        let mut rom = vec![0; MIN_CARTRIDGE_SIZE];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0134..0x013F].copy_from_slice(b"POKEMON RED");
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x0147] = 0x13;
+        rom[0x0148] = 0x05;
+        rom[0x0149] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x33;
+        rom[0x014D] = 0x20;
+        rom[0x3FFF] = 0xFF;
+        let sum = rom
+            .iter()
+            .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        rom[0x014E..=0x014F].copy_from_slice(&sum.to_be_bytes());
+
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON RED");
+        assert!(matches!(
+            header.cartridge_type,
+            CartridgeType::Mbc3RamBattery
+        ));
+        assert_eq!(header.rom_banks, 64);
+        assert_eq!(header.ram_banks, 4);
+        assert!(header.checksum_passed);
+        assert_eq!(header.global_checksum, sum);
+        assert!(header.global_checksum_passed);
+        assert_eq!(header.cgb_flag, 0x00);
+        assert_eq!(header.sgb_flag, 0x03);
+        assert_eq!(header.old_licensee_code, 0x33);
+        assert_eq!(header.new_licensee_code.as_deref(), Some("01"));
+
+        rom[0x3FFF] = 0x00;
+        let header = Header::parse(&rom).unwrap();
+        assert!(!header.global_checksum_passed);
+        assert!(header.checksum_passed);
+
+        rom[0x014B] = 0x01;
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.old_licensee_code, 0x01);
+        assert_eq!(header.new_licensee_code, None);
+    }
+
+    #[test]
+    fn title() {
+        let mut rom = vec![0; MIN_CARTRIDGE_SIZE];
+        rom[0x0147] = 0x01;
+
+        // Older cartridges use all 16 bytes for the title
+        rom[0x0134..=0x0143].copy_from_slice(b"SIXTEEN CHAR NAM");
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "SIXTEEN CHAR NAM");
+
+        // On CGB cartridges, 0x0143 holds the CGB flag
+        for cgb_flag in [CGB_FLAG_ENHANCED, CGB_FLAG_ONLY] {
+            rom[0x0134..=0x0142].copy_from_slice(b"FIFTEEN CHAR NA");
+            rom[0x0143] = cgb_flag;
+            let header = Header::parse(&rom).unwrap();
+            assert_eq!(header.title, "FIFTEEN CHAR NA");
+            assert_eq!(header.cgb_flag, cgb_flag);
+        }
+
+        // Shorter titles are padded with zeros
+        rom[0x0134..=0x0143].fill(0);
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom[0x0143] = 0xC0;
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "TEST");
+    }
+
+    #[test]
+    fn cgb_mode() {
+        assert_eq!(CgbMode::from(0x00), CgbMode::DmgOnly);
+        assert_eq!(CgbMode::from(0x80), CgbMode::CgbEnhanced);
+        assert_eq!(CgbMode::from(0xC0), CgbMode::CgbOnly);
+        // Title characters in older cartridges
+        assert_eq!(CgbMode::from(b'A'), CgbMode::DmgOnly);
+
+        let mut rom = vec![0; MIN_CARTRIDGE_SIZE];
+        rom[0x0147] = 0x01;
+        for (cgb_flag, cgb_mode) in [
+            (0x00, CgbMode::DmgOnly),
+            (0x80, CgbMode::CgbEnhanced),
+            (0xC0, CgbMode::CgbOnly),
+        ] {
+            rom[0x0143] = cgb_flag;
+            assert_eq!(Header::parse(&rom).unwrap().cgb_mode, cgb_mode);
+        }
+    }
+
+    #[test]
+    fn clone_header() {
+        let mut rom = vec![0; MIN_CARTRIDGE_SIZE];
+        rom[0x0134..0x0138].copy_from_slice(b"COPY");
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        let header = Header::parse(&rom).unwrap();
+
+        let cloned = header.clone();
+        assert_eq!(cloned.title, header.title);
+        assert_eq!(cloned.rom_banks, header.rom_banks);
+        assert_eq!(cloned.ram_banks, header.ram_banks);
+        assert_eq!(cloned.checksum, header.checksum);
+        assert_eq!(cloned.global_checksum, header.global_checksum);
+
+        // `CartridgeType` is `Copy`
+        let cartridge_type = header.cartridge_type;
+        assert!(matches!(cartridge_type, CartridgeType::Mbc1RamBattery));
+        assert!(matches!(
+            header.cartridge_type,
+            CartridgeType::Mbc1RamBattery
+        ));
+    }
+
+    #[test]
+    fn cartridge_type_display() {
+        assert_eq!(CartridgeType::RomOnly.to_string(), "ROM Only");
+        assert_eq!(
+            CartridgeType::Mbc1RamBattery.to_string(),
+            "MBC1+RAM+Battery"
+        );
+        assert_eq!(
+            CartridgeType::Mbc3TimerRamBattery.to_string(),
+            "MBC3+Timer+RAM+Battery"
+        );
+        assert_eq!(
+            CartridgeType::HuC1RamBattery.to_string(),
+            "HuC1+RAM+Battery"
+        );
+        assert_eq!(
+            format!("{:?}", CartridgeType::Mbc1RamBattery),
+            "Mbc1RamBattery"
+        );
+    }
+}
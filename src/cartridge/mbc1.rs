@@ -1,8 +1,13 @@
 use std::fmt::Debug;
 
-use crate::{cartridge::ROM_BANK_SIZE, state::PollState};
+use crate::{
+    cartridge::ROM_BANK_SIZE,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    state::PollState,
+    SnapshotError,
+};
 
-use super::{cartridge_base::CartridgeBase, CartridgeInterface, Header};
+use super::{cartridge_base::CartridgeBase, CartridgeInterface, Header, NINTENDO_LOGO};
 
 const RAM_ENABLE_REG_START: u16 = 0x0000;
 const RAM_ENABLE_REG_END: u16 = 0x1FFF;
@@ -12,6 +17,9 @@ const RAM_BANK_REG_START: u16 = 0x4000;
 const RAM_BANK_REG_END: u16 = 0x5FFF;
 const BANK_MODE_SELECT_REG_START: u16 = 0x6000;
 const BANK_MODE_SELECT_REG_END: u16 = 0x7FFF;
+const LOGO_START: usize = 0x0104;
+/// Size of each game in an MBC1M multi-cart
+const MULTICART_GAME_SIZE: usize = 16 * ROM_BANK_SIZE;
 
 /// 1-bit banking mode register
 #[derive(Debug)]
@@ -39,10 +47,31 @@ pub struct Mbc1 {
     large_rom: bool,
     /// Indicates 32 KiB of external RAM
     large_ram: bool,
+    /// Indicates an MBC1M multi-cart, where the upper bits of the ROM bank come after
+    /// only 4 bits of the ROM bank register
+    multicart: bool,
+}
+
+/// Returns `true` if `rom` appears to be an MBC1M multi-cart.
+///
+/// Multi-carts are 1 MiB ROMs consisting of four 256 KiB games, each with its own
+/// header.  They are detected by looking for the Nintendo logo in the headers of the
+/// games after the first one.
+pub fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 4 * MULTICART_GAME_SIZE {
+        return false;
+    }
+    (1..4)
+        .map(|game| game * MULTICART_GAME_SIZE + LOGO_START)
+        .any(|start| rom[start..start + NINTENDO_LOGO.len()] == NINTENDO_LOGO)
 }
 
 impl Mbc1 {
-    pub fn new(rom: &[u8], mut header: Header) -> Result<Self, crate::RomError> {
+    pub fn new(
+        rom: Box<[u8]>,
+        mut header: Header,
+        multicart: bool,
+    ) -> Result<Self, crate::RomError> {
         if header.rom_banks > 128 {
             return Err(crate::RomError::Oversized {
                 cartridge_type: header.cartridge_type,
@@ -79,6 +108,7 @@ impl Mbc1 {
             4 => 0b0000_0011,
             8 => 0b0000_0111,
             16 => 0b0000_1111,
+            _ if multicart => 0b0000_1111,
             _ => 0b0001_1111,
         };
 
@@ -90,6 +120,7 @@ impl Mbc1 {
             bank_mode: BankMode::Simple,
             large_rom,
             large_ram,
+            multicart,
         })
     }
 
@@ -100,15 +131,23 @@ impl Mbc1 {
         self.update_ram_bank();
     }
 
+    /// Ignore the bank number bits which are not connected to the ROM (the number of
+    /// ROM banks is always a power of two)
+    fn mask_rom_bank(&self, bank: usize) -> usize {
+        bank & (self.cartridge_base.header().rom_banks - 1)
+    }
+
     fn update_rom_bank0(&mut self) {
-        self.cartridge_base.rom_bank0 = if let BankMode::Simple = self.bank_mode {
+        let bank = if let BankMode::Simple = self.bank_mode {
             0
         } else if self.large_ram {
             0
+        } else if self.multicart {
+            usize::from(self.ram_bank_reg) << 4
         } else {
-            (self.rom_bank_reg << 5).into()
+            usize::from(self.ram_bank_reg) << 5
         };
-        assert!(self.cartridge_base.rom_bank0 < self.cartridge_base.header().rom_banks);
+        self.cartridge_base.rom_bank0 = self.mask_rom_bank(bank);
     }
 
     fn update_rom_bank1(&mut self) {
@@ -116,20 +155,21 @@ impl Mbc1 {
         if self.rom_bank_reg == 0 {
             bank += 1;
         }
-        if self.large_rom {
-            bank &= usize::from(self.ram_bank_reg << 5);
+        if self.multicart {
+            bank |= usize::from(self.ram_bank_reg) << 4;
+        } else if self.large_rom {
+            bank |= usize::from(self.ram_bank_reg) << 5;
         }
-        self.cartridge_base.rom_bank1 = bank;
-        assert!(self.cartridge_base.rom_bank1 < self.cartridge_base.header().rom_banks);
+        self.cartridge_base.rom_bank1 = self.mask_rom_bank(bank);
     }
 
     fn update_ram_bank(&mut self) {
         self.cartridge_base.ram_bank = if let BankMode::Simple = self.bank_mode {
             0
-        } else if self.large_rom {
-            0
+        } else if self.large_ram {
+            self.ram_bank_reg.into()
         } else {
-            self.rom_bank_reg.into()
+            0
         };
         assert!(
             self.cartridge_base.ram_bank < self.cartridge_base.header().ram_banks
@@ -191,6 +231,26 @@ impl CartridgeInterface for Mbc1 {
     fn header(&self) -> &super::Header {
         self.cartridge_base.header()
     }
+
+    fn rom(&self) -> &[u8] {
+        self.cartridge_base.rom()
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        self.cartridge_base.ram()
+    }
+
+    fn current_rom_bank(&self) -> (usize, usize) {
+        self.cartridge_base.current_rom_bank()
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.cartridge_base.current_ram_bank()
+    }
+
+    fn load_ram(&mut self, ram: &[u8]) -> Result<(), crate::SaveError> {
+        self.cartridge_base.load_ram(ram)
+    }
 }
 
 impl Debug for Mbc1 {
@@ -203,6 +263,7 @@ impl Debug for Mbc1 {
             .field("bank_mode", &self.bank_mode)
             .field("large_rom", &self.large_rom)
             .field("large_ram", &self.large_ram)
+            .field("multicart", &self.multicart)
             .finish()
     }
 }
@@ -229,6 +290,187 @@ impl PollState for Mbc1 {
             cart_state
                 .mbc_state
                 .insert("large_ram".into(), self.large_ram.to_string());
+            cart_state
+                .mbc_state
+                .insert("multicart".into(), self.multicart.to_string());
+        }
+    }
+}
+
+impl Snapshot for Mbc1 {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.cartridge_base.save_snapshot(writer);
+        writer.write_u8(self.rom_bank_reg);
+        writer.write_u8(self.ram_bank_reg);
+        writer.write_bool(matches!(self.bank_mode, BankMode::Advanced));
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.cartridge_base.restore_snapshot(reader)?;
+        self.rom_bank_reg = reader.read_u8()? & 0b0001_1111;
+        self.ram_bank_reg = reader.read_u8()? & 0b0000_0011;
+        self.bank_mode = if reader.read_bool()? {
+            BankMode::Advanced
+        } else {
+            BankMode::Simple
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Create a 1 MiB MBC1 ROM, where the first byte of each ROM bank holds the bank
+    /// number
+    fn large_rom(multicart: bool) -> Vec<u8> {
+        let mut rom = vec![0; 64 * ROM_BANK_SIZE];
+        for bank in 0..64 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x05;
+        let games = if multicart { 4 } else { 1 };
+        for game in 0..games {
+            let start = game * MULTICART_GAME_SIZE + LOGO_START;
+            rom[start..start + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        rom
+    }
+
+    #[test]
+    fn multicart_detection() {
+        assert!(is_multicart(&large_rom(true)));
+        assert!(!is_multicart(&large_rom(false)));
+        assert!(!is_multicart(&large_rom(true)[..32 * ROM_BANK_SIZE]));
+    }
+
+    #[test]
+    fn multicart_banking() {
+        let rom = large_rom(true);
+        let header = Header::parse(&rom).unwrap();
+        let mut mbc = Mbc1::new(rom.clone().into(), header, true).unwrap();
+
+        // Bank = (upper 2 bits << 4) | lower 4 bits
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        // Bit 4 of the ROM bank register is ignored
+        mbc.write_rom(0x2000, 0x13);
+        assert_eq!(mbc.read_rom(0x4000), 0x13);
+
+        // The bank 0 check is done on all 5 bits of the ROM bank register
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x10);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x11);
+
+        // In advanced banking mode, 0x0000..=0x3FFF maps to the first bank of a game
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x0000), 0x30);
+        assert_eq!(mbc.read_rom(0x4000), 0x31);
+    }
+
+    #[test]
+    fn current_bank() {
+        let mut rom = vec![0; 8 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x03;
+        rom[0x0148] = 0x02;
+        rom[0x0149] = 0x02;
+        let header = Header::parse(&rom).unwrap();
+        let mbc: &mut dyn CartridgeInterface =
+            &mut Mbc1::new(rom.clone().into(), header, false).unwrap();
+        assert_eq!(mbc.current_rom_bank(), (0, 1));
+        assert_eq!(mbc.current_ram_bank(), 0);
+
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.current_rom_bank(), (0, 5));
+        // Writing 0 to the ROM bank register selects bank 1
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.current_rom_bank(), (0, 1));
+        assert_eq!(mbc.current_ram_bank(), 0);
+    }
+
+    #[test]
+    fn large_ram_banking() {
+        let mut rom = vec![0; 8 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x02;
+        rom[0x0148] = 0x02;
+        rom[0x0149] = 0x03;
+        let header = Header::parse(&rom).unwrap();
+        let mut mbc = Mbc1::new(rom.clone().into(), header, false).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+
+        // Select RAM bank 2 in advanced banking mode
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_rom(0x2000, 0x05);
+        mbc.write_ram(0x0000, 0x12);
+        mbc.write_ram(0x1FFF, 0x34);
+        assert_eq!(mbc.current_ram_bank(), 2);
+
+        let mut state = crate::State::default();
+        mbc.poll_state(&mut state);
+        let cart_state = state.cartridge.unwrap();
+        let range = cart_state.ram_bank_range.unwrap();
+        assert_eq!(range, 0x4000..0x6000);
+        let ram = cart_state.ram.unwrap();
+        assert_eq!(ram[range.start], mbc.read_ram(0x0000));
+        assert_eq!(ram[range.end - 1], mbc.read_ram(0x1FFF));
+        assert_eq!(ram[range.start], 0x12);
+        assert_eq!(ram[range.end - 1], 0x34);
+
+        // Simple banking mode always uses RAM bank 0
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.current_ram_bank(), 0);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+    }
+
+    #[test]
+    fn large_rom_banking() {
+        let rom = large_rom(false);
+        let header = Header::parse(&rom).unwrap();
+        let mut mbc = Mbc1::new(rom.clone().into(), header, false).unwrap();
+
+        // Banks 0x01 through 0x1F only use the ROM bank register
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 0x05);
+
+        // Bank = (upper 2 bits << 5) | lower 5 bits
+        mbc.write_rom(0x2000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x3F);
+
+        // In advanced banking mode, the upper bits also apply to 0x0000..=0x3FFF
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn rom_bank_wraparound() {
+        let rom = large_rom(false);
+        let header = Header::parse(&rom).unwrap();
+        let mut mbc = Mbc1::new(rom.clone().into(), header, false).unwrap();
+        mbc.write_rom(0x6000, 0x01);
+
+        // The bank number lines above the size of the ROM are ignored
+        for (upper_bits, bank0) in [(0x02, 0x00), (0x03, 0x20)] {
+            mbc.write_rom(0x4000, upper_bits);
+            mbc.write_rom(0x2000, 0x05);
+            assert_eq!(mbc.current_rom_bank(), (bank0, bank0 + 0x05));
+            assert_eq!(mbc.read_rom(0x0000), bank0 as u8);
+            assert_eq!(mbc.read_rom(0x4000), bank0 as u8 + 0x05);
         }
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.current_rom_bank(), (0x00, 0x25));
     }
 }
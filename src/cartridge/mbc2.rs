@@ -0,0 +1,208 @@
+use crate::{
+    cartridge::ROM_BANK_SIZE,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    state::PollState,
+    SnapshotError,
+};
+
+use super::{cartridge_base::CartridgeBase, CartridgeInterface, Header};
+
+const REG_START: u16 = 0x0000;
+const REG_END: u16 = 0x3FFF;
+/// Size of the built-in RAM (in half-bytes)
+const RAM_SIZE: usize = 512;
+const DEFAULT_READ_VALUE: u8 = 0xFF;
+
+/// MBC2 game cartridge
+#[derive(Debug)]
+pub struct Mbc2 {
+    /// Cartridge base to control the reading from ROM
+    cartridge_base: CartridgeBase,
+    /// 4-bit ROM bank register
+    rom_bank_reg: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Box<[u8]>, mut header: Header) -> Result<Self, crate::RomError> {
+        if header.rom_banks > 16 {
+            return Err(crate::RomError::Oversized {
+                cartridge_type: header.cartridge_type,
+                found: ROM_BANK_SIZE * header.rom_banks,
+            });
+        }
+
+        if header.ram_banks != 0 {
+            tracing::error!(target: "cartridge", "'{:?}' cartridge type does not support external RAM banks (found {} banks)", header.cartridge_type, header.ram_banks);
+            header.ram_banks = 0;
+        }
+
+        // The built-in RAM is stored in the cartridge base, but only the lower 4 bits of
+        // each byte are used
+        let mut cartridge_base = CartridgeBase::new(rom, header);
+        cartridge_base.ram = Some(vec![0; RAM_SIZE].into_boxed_slice());
+
+        Ok(Self {
+            cartridge_base,
+            rom_bank_reg: 0,
+        })
+    }
+}
+
+impl CartridgeInterface for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.cartridge_base.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            // Bit 8 of the address determines whether the RAM enable register or the
+            // ROM bank register is written to
+            REG_START..=REG_END if addr & 0x0100 == 0 => {
+                self.cartridge_base.ram_enabled = (value & 0x0F) == 0x0A;
+                tracing::debug!(target: "cartridge", "external RAM enabled: {}", self.cartridge_base.ram_enabled);
+            }
+            REG_START..=REG_END => {
+                self.rom_bank_reg = value & 0b0000_1111;
+                let mut bank = usize::from(self.rom_bank_reg);
+                if bank == 0 {
+                    bank = 1;
+                }
+                self.cartridge_base.rom_bank1 = bank % self.cartridge_base.header().rom_banks;
+            }
+            _ => self.cartridge_base.write_rom(addr, value),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match &self.cartridge_base.ram {
+            Some(ram) if self.cartridge_base.ram_enabled => {
+                // Only the lower 9 bits of the address are used, and the upper 4 bits of
+                // each byte are undefined (read as 1s)
+                ram[usize::from(addr) % RAM_SIZE] | 0xF0
+            }
+            _ => {
+                tracing::error!(target: "cartridge", "attempted to read from external RAM, but RAM is not enabled");
+                DEFAULT_READ_VALUE
+            }
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match &mut self.cartridge_base.ram {
+            Some(ram) if self.cartridge_base.ram_enabled => {
+                ram[usize::from(addr) % RAM_SIZE] = value & 0x0F;
+            }
+            _ => {
+                tracing::error!(target: "cartridge", "attempted to write to external RAM, but RAM is not enabled");
+            }
+        }
+    }
+
+    fn header(&self) -> &Header {
+        self.cartridge_base.header()
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.cartridge_base.rom()
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        self.cartridge_base.ram()
+    }
+
+    fn current_rom_bank(&self) -> (usize, usize) {
+        self.cartridge_base.current_rom_bank()
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.cartridge_base.current_ram_bank()
+    }
+
+    fn load_ram(&mut self, ram: &[u8]) -> Result<(), crate::SaveError> {
+        self.cartridge_base.load_ram(ram)
+    }
+}
+
+impl PollState for Mbc2 {
+    fn poll_state(&self, state: &mut crate::State) {
+        self.cartridge_base.poll_state(state);
+        if let Some(cart_state) = &mut state.cartridge {
+            cart_state.ram_bank_range = Some(0..RAM_SIZE);
+            cart_state
+                .mbc_state
+                .insert("rom_bank_reg".into(), self.rom_bank_reg.to_string());
+        }
+    }
+}
+
+impl Snapshot for Mbc2 {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.cartridge_base.save_snapshot(writer);
+        writer.write_u8(self.rom_bank_reg);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.cartridge_base.restore_snapshot(reader)?;
+        self.rom_bank_reg = reader.read_u8()? & 0b0000_1111;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Create an MBC2 cartridge with 16 ROM banks, where the first byte of each ROM
+    /// bank holds the bank number
+    fn new_mbc2() -> Mbc2 {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        for bank in 0..16 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[0x0147] = 0x06;
+        rom[0x0148] = 0x03;
+        let header = Header::parse(&rom).unwrap();
+        Mbc2::new(rom.clone().into(), header).unwrap()
+    }
+
+    #[test]
+    fn register_select() {
+        let mut mbc = new_mbc2();
+
+        // Address bit 8 clear: RAM enable register
+        mbc.write_rom(0x0000, 0x0A);
+        assert!(mbc.cartridge_base.ram_enabled);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x3EFF, 0x00);
+        assert!(!mbc.cartridge_base.ram_enabled);
+
+        // Address bit 8 set: ROM bank register
+        mbc.write_rom(0x0100, 0x0A);
+        assert!(!mbc.cartridge_base.ram_enabled);
+        assert_eq!(mbc.read_rom(0x4000), 10);
+        mbc.write_rom(0x3FFF, 0xF3);
+        assert_eq!(mbc.read_rom(0x4000), 3);
+
+        // Bank 0 is remapped to bank 1
+        mbc.write_rom(0x2100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn ram_nibbles() {
+        let mut mbc = new_mbc2();
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_ram(0x0000, 0xAB);
+        assert_eq!(mbc.read_ram(0x0000), 0xFB);
+        assert_eq!(mbc.cartridge_base.ram.as_ref().unwrap()[0], 0x0B);
+
+        // The RAM is echoed throughout 0xA000..=0xBFFF
+        mbc.write_ram(0x01FF, 0x05);
+        assert_eq!(mbc.read_ram(0x03FF), 0xF5);
+        assert_eq!(mbc.read_ram(0x1FFF), 0xF5);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), DEFAULT_READ_VALUE);
+    }
+}
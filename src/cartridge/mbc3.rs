@@ -0,0 +1,509 @@
+use std::fmt::Display;
+
+use crate::{
+    bits::Bits,
+    cartridge::ROM_BANK_SIZE,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    state::PollState,
+    SnapshotError, TCycles,
+};
+
+use super::{cartridge_base::CartridgeBase, CartridgeInterface, CartridgeType, Header};
+
+const RAM_ENABLE_REG_START: u16 = 0x0000;
+const RAM_ENABLE_REG_END: u16 = 0x1FFF;
+const ROM_BANK_REG_START: u16 = 0x2000;
+const ROM_BANK_REG_END: u16 = 0x3FFF;
+const RAM_BANK_REG_START: u16 = 0x4000;
+const RAM_BANK_REG_END: u16 = 0x5FFF;
+const LATCH_CLOCK_DATA_REG_START: u16 = 0x6000;
+const LATCH_CLOCK_DATA_REG_END: u16 = 0x7FFF;
+const RTC_SECONDS: u8 = 0x08;
+const RTC_MINUTES: u8 = 0x09;
+const RTC_HOURS: u8 = 0x0A;
+const RTC_DAY_LOW: u8 = 0x0B;
+const RTC_DAY_HIGH: u8 = 0x0C;
+/// Number of T-cycles in one second of real time
+const RTC_CYCLES_PER_SECOND: TCycles = crate::CLOCK_HZ as TCycles;
+
+/// MBC3 game cartridge
+#[derive(Debug)]
+pub struct Mbc3 {
+    /// Cartridge base to control the reading from ROM/RAM and the writing to RAM
+    cartridge_base: CartridgeBase,
+    /// 7-bit ROM bank register
+    rom_bank_reg: u8,
+    /// RAM bank/RTC register select
+    ram_bank_reg: u8,
+    /// Real time clock (only present for the timer cartridge types)
+    rtc: Option<Rtc>,
+    /// Last value written to the latch clock data register
+    latch_reg: u8,
+}
+
+/// MBC3 real time clock registers
+#[derive(Debug, Default, Clone, Copy)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    /// 9-bit day counter
+    days: u16,
+    /// Clock halted
+    halt: bool,
+    /// Day counter overflowed
+    day_carry: bool,
+}
+
+impl RtcRegisters {
+    fn read(&self, reg: u8) -> u8 {
+        match reg {
+            RTC_SECONDS => self.seconds,
+            RTC_MINUTES => self.minutes,
+            RTC_HOURS => self.hours,
+            RTC_DAY_LOW => self.days.to_le_bytes()[0],
+            RTC_DAY_HIGH => {
+                let mut value = self.days.to_le_bytes()[1] & 0b0000_0001;
+                if self.halt {
+                    value.set_bit(6);
+                }
+                if self.day_carry {
+                    value.set_bit(7);
+                }
+                value
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            RTC_SECONDS => self.seconds = value & 0b0011_1111,
+            RTC_MINUTES => self.minutes = value & 0b0011_1111,
+            RTC_HOURS => self.hours = value & 0b0001_1111,
+            RTC_DAY_LOW => self.days = (self.days & 0x0100) | u16::from(value),
+            RTC_DAY_HIGH => {
+                self.days = (self.days & 0x00FF) | (u16::from(value & 0b0000_0001) << 8);
+                self.halt = value.bit(6);
+                self.day_carry = value.bit(7);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Advance the clock by one second
+    fn increment(&mut self) {
+        self.seconds = (self.seconds + 1) & 0b0011_1111;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0b0011_1111;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0b0001_1111;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x01FF {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+}
+
+impl Display for RtcRegisters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}d {:02}:{:02}:{:02}",
+            self.days, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+/// MBC3 real time clock
+#[derive(Debug, Default)]
+struct Rtc {
+    /// Registers of the running clock
+    clock: RtcRegisters,
+    /// Registers visible to the CPU, copied from `clock` when the clock is latched
+    latched: RtcRegisters,
+    /// Cycles accumulated towards the next second
+    cycles: TCycles,
+}
+
+impl Rtc {
+    fn tick(&mut self, cycles: TCycles) {
+        if self.clock.halt {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= RTC_CYCLES_PER_SECOND {
+            self.cycles -= RTC_CYCLES_PER_SECOND;
+            self.clock.increment();
+        }
+    }
+
+    fn latch(&mut self) {
+        self.latched = self.clock;
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        if reg == RTC_SECONDS {
+            // Writing to the seconds register resets the sub-second counter
+            self.cycles = 0;
+        }
+        self.clock.write(reg, value);
+        self.latched.write(reg, value);
+    }
+}
+
+impl Mbc3 {
+    pub fn new(rom: Box<[u8]>, mut header: Header) -> Result<Self, crate::RomError> {
+        if header.rom_banks > 128 {
+            return Err(crate::RomError::Oversized {
+                cartridge_type: header.cartridge_type,
+                found: ROM_BANK_SIZE * header.rom_banks,
+            });
+        }
+
+        if header.ram_banks > 4 {
+            tracing::error!(
+                target = "cartridge",
+                "'{:?}' cartridge type does not support more than 4 RAM banks (found {})",
+                header.cartridge_type,
+                header.ram_banks
+            );
+            header.ram_banks = 4;
+        }
+
+        let rtc = match header.cartridge_type {
+            CartridgeType::Mbc3TimerBattery | CartridgeType::Mbc3TimerRamBattery => {
+                Some(Rtc::default())
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            cartridge_base: CartridgeBase::new(rom, header),
+            rom_bank_reg: 0,
+            ram_bank_reg: 0,
+            rtc,
+            latch_reg: 0xFF,
+        })
+    }
+
+    /// Return the RTC register currently mapped to 0xA000..=0xBFFF (if any)
+    fn selected_rtc_reg(&self) -> Option<u8> {
+        match self.ram_bank_reg {
+            RTC_SECONDS..=RTC_DAY_HIGH if self.rtc.is_some() => Some(self.ram_bank_reg),
+            _ => None,
+        }
+    }
+
+    /// Update the ROM and RAM banks based on the cartridge register values
+    fn update_banks(&mut self) {
+        let rom_banks = self.cartridge_base.header().rom_banks;
+        let mut rom_bank = usize::from(self.rom_bank_reg);
+        if rom_bank == 0 {
+            rom_bank = 1;
+        }
+        self.cartridge_base.rom_bank1 = rom_bank % rom_banks;
+
+        let ram_banks = self.cartridge_base.header().ram_banks;
+        if ram_banks != 0 && self.ram_bank_reg <= 0x03 {
+            self.cartridge_base.ram_bank = usize::from(self.ram_bank_reg) % ram_banks;
+        }
+    }
+}
+
+impl CartridgeInterface for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.cartridge_base.read_rom(addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            RAM_ENABLE_REG_START..=RAM_ENABLE_REG_END => {
+                let enable = (value & 0x0F) == 0x0A;
+                if enable && (self.cartridge_base.header().ram_banks != 0 || self.rtc.is_some()) {
+                    self.cartridge_base.ram_enabled = true;
+                    tracing::debug!(target: "cartridge", "external RAM enabled");
+                } else {
+                    self.cartridge_base.ram_enabled = false;
+                    tracing::debug!(target: "cartridge", "external RAM disabled");
+                }
+            }
+            ROM_BANK_REG_START..=ROM_BANK_REG_END => {
+                self.rom_bank_reg = value & 0b0111_1111;
+            }
+            RAM_BANK_REG_START..=RAM_BANK_REG_END => match value {
+                0x00..=0x03 => self.ram_bank_reg = value,
+                RTC_SECONDS..=RTC_DAY_HIGH if self.rtc.is_some() => self.ram_bank_reg = value,
+                _ => {
+                    tracing::warn!(target: "cartridge", "attempted to select unsupported RAM bank/RTC register ${:02X}", value);
+                }
+            },
+            LATCH_CLOCK_DATA_REG_START..=LATCH_CLOCK_DATA_REG_END => {
+                if let Some(rtc) = &mut self.rtc {
+                    if self.latch_reg == 0x00 && value == 0x01 {
+                        rtc.latch();
+                    }
+                } else {
+                    tracing::warn!(target: "cartridge", "attempted to latch clock data: cartridge does not have a real time clock");
+                }
+                self.latch_reg = value;
+            }
+            _ => unreachable!(),
+        }
+        self.update_banks();
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match (self.selected_rtc_reg(), &self.rtc) {
+            (Some(reg), Some(rtc)) if self.cartridge_base.ram_enabled => rtc.read(reg),
+            _ => self.cartridge_base.read_ram(addr),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match (self.selected_rtc_reg(), &mut self.rtc) {
+            (Some(reg), Some(rtc)) if self.cartridge_base.ram_enabled => rtc.write(reg, value),
+            _ => self.cartridge_base.write_ram(addr, value),
+        }
+    }
+
+    fn header(&self) -> &Header {
+        self.cartridge_base.header()
+    }
+
+    fn rom(&self) -> &[u8] {
+        self.cartridge_base.rom()
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        self.cartridge_base.ram()
+    }
+
+    fn current_rom_bank(&self) -> (usize, usize) {
+        self.cartridge_base.current_rom_bank()
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.cartridge_base.current_ram_bank()
+    }
+
+    fn load_ram(&mut self, ram: &[u8]) -> Result<(), crate::SaveError> {
+        self.cartridge_base.load_ram(ram)
+    }
+
+    fn tick(&mut self, cycles: TCycles) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+}
+
+impl PollState for Mbc3 {
+    fn poll_state(&self, state: &mut crate::State) {
+        self.cartridge_base.poll_state(state);
+        if let Some(cart_state) = &mut state.cartridge {
+            cart_state
+                .mbc_state
+                .insert("rom_bank_reg".into(), self.rom_bank_reg.to_string());
+            cart_state
+                .mbc_state
+                .insert("ram_bank_reg".into(), self.ram_bank_reg.to_string());
+            if let Some(rtc) = &self.rtc {
+                cart_state
+                    .mbc_state
+                    .insert("rtc".into(), rtc.clock.to_string());
+                cart_state
+                    .mbc_state
+                    .insert("rtc_latched".into(), rtc.latched.to_string());
+                cart_state
+                    .mbc_state
+                    .insert("rtc_halt".into(), rtc.clock.halt.to_string());
+                cart_state
+                    .mbc_state
+                    .insert("rtc_day_carry".into(), rtc.clock.day_carry.to_string());
+            }
+        }
+    }
+}
+
+impl Snapshot for RtcRegisters {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        for reg in RTC_SECONDS..=RTC_DAY_HIGH {
+            writer.write_u8(self.read(reg));
+        }
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for reg in RTC_SECONDS..=RTC_DAY_HIGH {
+            self.write(reg, reader.read_u8()?);
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Mbc3 {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.cartridge_base.save_snapshot(writer);
+        writer.write_u8(self.rom_bank_reg);
+        writer.write_u8(self.ram_bank_reg);
+        writer.write_u8(self.latch_reg);
+        if let Some(rtc) = &self.rtc {
+            rtc.clock.save_snapshot(writer);
+            rtc.latched.save_snapshot(writer);
+            writer.write_i64(rtc.cycles);
+        }
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.cartridge_base.restore_snapshot(reader)?;
+        self.rom_bank_reg = reader.read_u8()? & 0b0111_1111;
+        self.ram_bank_reg = reader.read_u8()?;
+        self.latch_reg = reader.read_u8()?;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.clock.restore_snapshot(reader)?;
+            rtc.latched.restore_snapshot(reader)?;
+            rtc.cycles = reader.read_i64()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{cartridge::RAM_BANK_SIZE, State};
+
+    use super::*;
+
+    /// Create an MBC3 cartridge with 8 ROM banks and 4 RAM banks, where the first
+    /// byte of each ROM bank holds the bank number
+    fn new_mbc3() -> Mbc3 {
+        let mut rom = vec![0; 8 * ROM_BANK_SIZE];
+        for bank in 0..8 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[0x0147] = 0x13;
+        rom[0x0148] = 0x02;
+        rom[0x0149] = 0x03;
+        let header = Header::parse(&rom).unwrap();
+        Mbc3::new(rom.clone().into(), header).unwrap()
+    }
+
+    #[test]
+    fn rom_banking() {
+        let mut mbc = new_mbc3();
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 5);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+
+        let mut state = State::default();
+        mbc.poll_state(&mut state);
+        assert_eq!(state.cartridge.unwrap().rom_bank1, 5);
+
+        // Bank 0 is remapped to bank 1
+        mbc.write_rom(0x3FFF, 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // Bank numbers wrap around the size of the ROM
+        mbc.write_rom(0x2000, 0x0D);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut mbc = new_mbc3();
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0x0000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mbc.write_rom(0x5FFF, bank);
+            assert_eq!(mbc.read_ram(0x0000), 0x10 + bank);
+        }
+
+        let mut state = State::default();
+        mbc.poll_state(&mut state);
+        let cart_state = state.cartridge.unwrap();
+        assert_eq!(
+            cart_state.ram_bank_range,
+            Some(3 * RAM_BANK_SIZE..4 * RAM_BANK_SIZE)
+        );
+        assert_eq!(cart_state.mbc_state["ram_bank_reg"], "3");
+    }
+
+    #[test]
+    fn rtc() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x10;
+        rom[0x0149] = 0x03;
+        let header = Header::parse(&rom).unwrap();
+        let mut mbc = Mbc3::new(rom.clone().into(), header).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+
+        // Set the clock to 0d 23:59:58
+        mbc.write_rom(0x4000, RTC_HOURS);
+        mbc.write_ram(0x0000, 23);
+        mbc.write_rom(0x4000, RTC_MINUTES);
+        mbc.write_ram(0x0000, 59);
+        mbc.write_rom(0x4000, RTC_SECONDS);
+        mbc.write_ram(0x0000, 58);
+
+        mbc.tick(RTC_CYCLES_PER_SECOND - 1);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 58);
+
+        // The latched registers do not change until the clock is latched again
+        mbc.tick(1 + RTC_CYCLES_PER_SECOND);
+        assert_eq!(mbc.read_ram(0x0000), 58);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 58);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0);
+        mbc.write_rom(0x4000, RTC_MINUTES);
+        assert_eq!(mbc.read_ram(0x0000), 0);
+        mbc.write_rom(0x4000, RTC_HOURS);
+        assert_eq!(mbc.read_ram(0x0000), 0);
+        mbc.write_rom(0x4000, RTC_DAY_LOW);
+        assert_eq!(mbc.read_ram(0x0000), 1);
+
+        // RAM banks are still accessible
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+
+        // Halting the clock stops it from advancing
+        mbc.write_rom(0x4000, RTC_DAY_HIGH);
+        mbc.write_ram(0x0000, 0b0100_0000);
+        mbc.tick(2 * RTC_CYCLES_PER_SECOND);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, RTC_SECONDS);
+        assert_eq!(mbc.read_ram(0x0000), 0);
+
+        let mut state = State::default();
+        mbc.poll_state(&mut state);
+        let cart_state = state.cartridge.unwrap();
+        assert_eq!(cart_state.mbc_state["rtc"], "1d 00:00:00");
+        assert_eq!(cart_state.mbc_state["rtc_halt"], "true");
+    }
+}
@@ -1,19 +1,21 @@
 mod cartridge_base;
 mod header;
 mod mbc1;
+mod mbc2;
+mod mbc3;
 mod rom_only;
 
 use std::fmt::Debug;
 
 pub use header::*;
 
-use crate::{state::PollState, RomError};
+use crate::{gb::snapshot::Snapshot, state::PollState, RomError, SaveError, TCycles};
 
 const ROM_BANK_SIZE: usize = 16 * 1024;
 const RAM_BANK_SIZE: usize = 8 * 1024;
 
-pub trait CartridgeTraits: CartridgeInterface + PollState {}
-impl<T> CartridgeTraits for T where T: CartridgeInterface + PollState {}
+pub trait CartridgeTraits: CartridgeInterface + PollState + Snapshot {}
+impl<T> CartridgeTraits for T where T: CartridgeInterface + PollState + Snapshot {}
 
 /// Game cartridge
 pub type Cartridge = Box<dyn CartridgeTraits + Send + Sync>;
@@ -26,25 +28,206 @@ pub trait CartridgeInterface: Debug {
     fn read_ram(&self, addr: u16) -> u8;
     fn write_ram(&mut self, addr: u16, value: u8);
     fn header(&self) -> &Header;
+
+    /// Return the entire contents of the cartridge ROM (all banks)
+    fn rom(&self) -> &[u8];
+
+    /// Return the entire contents of the external RAM (all banks), if any
+    fn ram(&self) -> Option<&[u8]>;
+
+    /// Return the indices of the ROM banks currently mapped to 0x0000..=0x3FFF and
+    /// 0x4000..=0x7FFF
+    fn current_rom_bank(&self) -> (usize, usize);
+
+    /// Return the index of the external RAM bank currently mapped to 0xA000..=0xBFFF
+    fn current_ram_bank(&self) -> usize;
+
+    /// Notify the cartridge that the CPU has executed the given number of cycles
+    /// (e.g., to advance a real time clock)
+    fn tick(&mut self, _cycles: TCycles) {}
+
+    /// Replace the contents of the external RAM (e.g., when loading a save file)
+    fn load_ram(&mut self, _ram: &[u8]) -> Result<(), SaveError> {
+        Ok(())
+    }
+}
+
+/// Settings which override the values given in the cartridge header
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeOptions {
+    /// Number of external RAM banks to use instead of the RAM size byte of the header.
+    ///
+    /// This allows homebrew ROMs with an incorrect (or unrecognized) RAM size to run
+    /// without patching the header.  The number of banks must be between 1 and 16.
+    pub force_ram_banks: Option<usize>,
 }
 
-pub fn new_cartridge(rom: &[u8]) -> Result<Cartridge, RomError> {
-    let header = Header::parse(rom)?;
+/// Size of the ROM (in bytes) given the number of ROM banks
+fn rom_size(rom_banks: usize) -> Result<usize, RomError> {
+    rom_banks
+        .checked_mul(ROM_BANK_SIZE)
+        .ok_or(RomError::SizeOverflow(rom_banks))
+}
+
+pub fn new_cartridge(rom: &[u8], options: CartridgeOptions) -> Result<Cartridge, RomError> {
+    new_cartridge_owned(rom.to_vec(), options)
+}
+
+/// Create a cartridge which takes ownership of the ROM buffer, instead of copying it.
+///
+/// The buffer is only reallocated if its capacity exceeds its length.
+pub fn new_cartridge_owned(rom: Vec<u8>, options: CartridgeOptions) -> Result<Cartridge, RomError> {
+    let header = Header::parse_with_options(&rom, options)?;
     tracing::debug!(target: "boot", cartridge_header = ?header);
 
-    if header.rom_banks * ROM_BANK_SIZE != rom.len() {
+    let expected = rom_size(header.rom_banks)?;
+    if expected != rom.len() {
         return Err(RomError::Size {
-            expected: header.rom_banks * ROM_BANK_SIZE,
+            expected,
             found: rom.len(),
         });
     }
+    let rom = rom.into_boxed_slice();
 
     match header.cartridge_type {
         CartridgeType::RomOnly => Ok(Box::new(rom_only::RomOnly::new(rom, header)?)),
         CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
-            // TODO: detect MBC1M multi-cart
-            Ok(Box::new(mbc1::Mbc1::new(rom, header)?))
+            let multicart = mbc1::is_multicart(&rom);
+            if multicart {
+                tracing::debug!(target: "boot", "MBC1M multi-cart detected");
+            }
+            Ok(Box::new(mbc1::Mbc1::new(rom, header, multicart)?))
+        }
+        CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => {
+            Ok(Box::new(mbc2::Mbc2::new(rom, header)?))
         }
+        CartridgeType::Mbc3
+        | CartridgeType::Mbc3Ram
+        | CartridgeType::Mbc3RamBattery
+        | CartridgeType::Mbc3TimerBattery
+        | CartridgeType::Mbc3TimerRamBattery => Ok(Box::new(mbc3::Mbc3::new(rom, header)?)),
         _ => Err(RomError::UnsupportedCartridgeType(header.cartridge_type)),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rom_size_overflow() {
+        assert_eq!(rom_size(2).unwrap(), 32 * 1024);
+        assert!(matches!(
+            rom_size(usize::MAX),
+            Err(RomError::SizeOverflow(usize::MAX))
+        ));
+
+        // A header claiming the maximum ROM size is rejected cleanly
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0148] = 0x08;
+        assert!(matches!(
+            new_cartridge(&rom, CartridgeOptions::default()),
+            Err(RomError::Size {
+                expected: 0x0080_0000,
+                found: 0x8000
+            })
+        ));
+    }
+
+    #[test]
+    fn rom_and_ram_contents() {
+        for (cartridge_type, ram_size, ram_len) in [
+            (0x00, 0x00, None),
+            (0x03, 0x03, Some(4 * RAM_BANK_SIZE)),
+            (0x06, 0x00, Some(512)),
+            (0x13, 0x02, Some(RAM_BANK_SIZE)),
+        ] {
+            let rom_banks = if cartridge_type == 0x00 { 2 } else { 8 };
+            let mut rom: Vec<u8> = (0..rom_banks * ROM_BANK_SIZE)
+                .map(|i| (i / ROM_BANK_SIZE + i) as u8)
+                .collect();
+            rom[0x0147] = cartridge_type;
+            rom[0x0148] = if cartridge_type == 0x00 { 0x00 } else { 0x02 };
+            rom[0x0149] = ram_size;
+            let cartridge = new_cartridge(&rom, CartridgeOptions::default()).unwrap();
+
+            assert_eq!(cartridge.rom(), &rom[..]);
+            assert_eq!(cartridge.ram().map(<[u8]>::len), ram_len);
+        }
+    }
+
+    #[test]
+    fn poll_header() {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        rom[0x0134..0x0140].copy_from_slice(b"POLLED TITLE");
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x01;
+        let cartridge = new_cartridge(&rom, CartridgeOptions::default()).unwrap();
+
+        let mut state = crate::State::default();
+        cartridge.poll_state(&mut state);
+        let cart_state = state.cartridge.unwrap();
+        assert_eq!(cart_state.header.title, "POLLED TITLE");
+        assert_eq!(cart_state.header.title, cartridge.header().title);
+        assert_eq!(cart_state.header.rom_banks, 4);
+        assert!(matches!(
+            cart_state.header.cartridge_type,
+            CartridgeType::Mbc1
+        ));
+    }
+
+    #[test]
+    fn owned_rom() {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x01;
+        rom[ROM_BANK_SIZE] = 0x12;
+        assert_eq!(rom.capacity(), rom.len());
+        let ptr = rom.as_ptr();
+
+        // The ROM buffer is moved into the cartridge without being copied
+        let cartridge = new_cartridge_owned(rom, CartridgeOptions::default()).unwrap();
+        assert_eq!(cartridge.rom().as_ptr(), ptr);
+        assert_eq!(cartridge.rom().len(), 4 * ROM_BANK_SIZE);
+        assert_eq!(cartridge.read_rom(0x4000), 0x12);
+
+        // Borrowed ROMs are copied
+        let rom = cartridge.rom().to_vec();
+        let copy = new_cartridge(&rom, CartridgeOptions::default()).unwrap();
+        assert_ne!(copy.rom().as_ptr(), rom.as_ptr());
+        assert_eq!(copy.rom(), rom);
+    }
+
+    #[test]
+    fn force_ram_banks() {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x02;
+        rom[0x0148] = 0x01;
+        // Unofficial RAM size (2 KiB)
+        rom[0x0149] = 0x01;
+        assert!(matches!(
+            new_cartridge(&rom, CartridgeOptions::default()),
+            Err(RomError::UnrecognizedRamSize(0x01))
+        ));
+
+        let options = CartridgeOptions {
+            force_ram_banks: Some(1),
+        };
+        let mut cartridge = new_cartridge(&rom, options).unwrap();
+        assert_eq!(cartridge.header().ram_banks, 1);
+        assert_eq!(cartridge.ram().map(<[u8]>::len), Some(RAM_BANK_SIZE));
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0x0123, 0x45);
+        assert_eq!(cartridge.read_ram(0x0123), 0x45);
+
+        for ram_banks in [0, 17, usize::MAX / 1024] {
+            let options = CartridgeOptions {
+                force_ram_banks: Some(ram_banks),
+            };
+            assert!(matches!(
+                new_cartridge(&rom, options),
+                Err(RomError::InvalidRamBanks(banks)) if banks == ram_banks
+            ));
+        }
+    }
+}
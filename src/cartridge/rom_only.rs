@@ -1,4 +1,8 @@
-use crate::state::PollState;
+use crate::{
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    state::PollState,
+    SnapshotError,
+};
 
 use super::{cartridge_base::CartridgeBase, Header, ROM_BANK_SIZE};
 
@@ -9,7 +13,7 @@ pub struct RomOnly {
 }
 
 impl RomOnly {
-    pub fn new(rom: &[u8], header: Header) -> Result<Self, crate::RomError> {
+    pub fn new(rom: Box<[u8]>, header: Header) -> Result<Self, crate::RomError> {
         if header.rom_banks != 2 {
             return Err(crate::RomError::Oversized {
                 cartridge_type: header.cartridge_type,
@@ -49,6 +53,22 @@ impl super::CartridgeInterface for RomOnly {
     fn header(&self) -> &Header {
         self.cartridge_base.header()
     }
+
+    fn rom(&self) -> &[u8] {
+        self.cartridge_base.rom()
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        self.cartridge_base.ram()
+    }
+
+    fn current_rom_bank(&self) -> (usize, usize) {
+        self.cartridge_base.current_rom_bank()
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.cartridge_base.current_ram_bank()
+    }
 }
 
 impl PollState for RomOnly {
@@ -56,3 +76,13 @@ impl PollState for RomOnly {
         self.cartridge_base.poll_state(state);
     }
 }
+
+impl Snapshot for RomOnly {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.cartridge_base.save_snapshot(writer);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.cartridge_base.restore_snapshot(reader)
+    }
+}
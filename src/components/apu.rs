@@ -0,0 +1,1044 @@
+//! Audio processing unit
+//!
+//! The APU has four channels: two pulse channels (the first with a frequency sweep), a
+//! wave channel that plays back the samples stored in wave RAM, and a noise channel.
+//! Length counters, volume envelopes and the frequency sweep are clocked by the frame
+//! sequencer, which runs at 512 Hz.
+//!
+//! The output of the APU is sampled at a configurable rate into a buffer of
+//! interleaved stereo samples.
+use std::collections::VecDeque;
+
+use crate::{
+    bits::Bits,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    SnapshotError, TCycles,
+};
+
+use super::mmu::{
+    APU_CHANNEL1_PERIOD_HIGH, APU_CHANNEL1_SWEEP, APU_CHANNEL3_PERIOD_HIGH, APU_SOUND_ON_OFF,
+    APU_STORAGE_END, APU_STORAGE_START,
+};
+
+const CHANNEL1_START: u16 = APU_CHANNEL1_SWEEP;
+const CHANNEL1_END: u16 = APU_CHANNEL1_PERIOD_HIGH;
+const CHANNEL2_START: u16 = 0xFF15;
+const CHANNEL2_END: u16 = 0xFF19;
+const CHANNEL3_START: u16 = 0xFF1A;
+const CHANNEL3_END: u16 = APU_CHANNEL3_PERIOD_HIGH;
+const CHANNEL4_START: u16 = 0xFF1F;
+const CHANNEL4_END: u16 = 0xFF23;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = APU_SOUND_ON_OFF;
+const WAVE_RAM_SIZE: usize = (APU_STORAGE_END - APU_STORAGE_START + 1) as usize;
+/// Bits that always read as 1 for each register from NR10 through NR52
+const READ_MASKS: [u8; 23] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+/// Number of T-cycles between each step of the frame sequencer (512 Hz)
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+/// Number of T-cycles per second
+const CYCLES_PER_SECOND: u64 = crate::CLOCK_HZ as u64;
+/// Number of audio channels in the sample buffer (left and right)
+pub const AUDIO_CHANNELS: usize = 2;
+/// Default audio output sample rate (in Hz)
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+/// Maximum duration (in seconds) of audio held by the sample buffer before the oldest
+/// samples are discarded
+const MAX_BUFFERED_SECONDS: usize = 1;
+/// Waveforms for each pulse channel duty cycle (12.5%, 25%, 50% and 75%)
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+#[derive(Debug)]
+pub struct Apu {
+    /// Sound on/off (bit 7 of NR52)
+    enabled: bool,
+    /// Master volume and VIN panning
+    nr50: u8,
+    /// Sound panning
+    nr51: u8,
+    channel1: PulseChannel,
+    channel2: PulseChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    /// Cycles accumulated towards the next frame sequencer step
+    frame_sequencer_cycles: u32,
+    /// Next step of the frame sequencer (0 through 7)
+    frame_sequencer_step: u8,
+    /// Output sample rate (in Hz)
+    sample_rate: u32,
+    /// Accumulates `sample_rate` each T-cycle; a sample is taken each time it reaches
+    /// `CYCLES_PER_SECOND`
+    sample_counter: u64,
+    /// Interleaved stereo samples waiting to be drained
+    samples: VecDeque<f32>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            nr50: 0,
+            nr51: 0,
+            channel1: PulseChannel::new(true),
+            channel2: PulseChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            frame_sequencer_cycles: 0,
+            frame_sequencer_step: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_counter: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Reset the APU to its power-on state, keeping the output sample rate
+    pub fn reset(&mut self) {
+        *self = Self {
+            sample_rate: self.sample_rate,
+            ..Self::new()
+        };
+    }
+
+    /// Set the output sample rate (in Hz)
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_counter = 0;
+        self.samples.clear();
+    }
+
+    /// Remove and return every buffered sample
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    pub fn reg_read(&self, addr: u16) -> u8 {
+        let value = match addr {
+            CHANNEL1_START..=CHANNEL1_END => self.channel1.regs[usize::from(addr - CHANNEL1_START)],
+            CHANNEL2_START..=CHANNEL2_END => self.channel2.regs[usize::from(addr - CHANNEL2_START)],
+            CHANNEL3_START..=CHANNEL3_END => self.channel3.regs[usize::from(addr - CHANNEL3_START)],
+            CHANNEL4_START..=CHANNEL4_END => self.channel4.regs[usize::from(addr - CHANNEL4_START)],
+            NR50 => self.nr50,
+            NR51 => self.nr51,
+            NR52 => {
+                (u8::from(self.enabled) << 7)
+                    | (u8::from(self.channel4.enabled) << 3)
+                    | (u8::from(self.channel3.enabled) << 2)
+                    | (u8::from(self.channel2.enabled) << 1)
+                    | u8::from(self.channel1.enabled)
+            }
+            APU_STORAGE_START..=APU_STORAGE_END => {
+                return self.channel3.wave_ram[usize::from(addr - APU_STORAGE_START)];
+            }
+            _ => unreachable!(),
+        };
+        value | READ_MASKS[usize::from(addr - CHANNEL1_START)]
+    }
+
+    pub fn reg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            // Wave RAM and NR52 remain accessible while the APU is off
+            APU_STORAGE_START..=APU_STORAGE_END => {
+                self.channel3.wave_ram[usize::from(addr - APU_STORAGE_START)] = value;
+            }
+            NR52 => {
+                let enabled = value.bit(7);
+                if self.enabled && !enabled {
+                    self.power_off();
+                } else if !self.enabled && enabled {
+                    self.frame_sequencer_cycles = 0;
+                    self.frame_sequencer_step = 0;
+                }
+                self.enabled = enabled;
+            }
+            _ if !self.enabled => {
+                tracing::debug!(target: "apu", "ignored write to ${:04X} while the APU is off", addr);
+            }
+            CHANNEL1_START..=CHANNEL1_END => self.channel1.write(addr - CHANNEL1_START, value),
+            CHANNEL2_START..=CHANNEL2_END => self.channel2.write(addr - CHANNEL2_START, value),
+            CHANNEL3_START..=CHANNEL3_END => self.channel3.write(addr - CHANNEL3_START, value),
+            CHANNEL4_START..=CHANNEL4_END => self.channel4.write(addr - CHANNEL4_START, value),
+            NR50 => self.nr50 = value,
+            NR51 => self.nr51 = value,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Turning the APU off clears every register (except for wave RAM)
+    fn power_off(&mut self) {
+        let wave_ram = self.channel3.wave_ram;
+        self.nr50 = 0;
+        self.nr51 = 0;
+        self.channel1 = PulseChannel::new(true);
+        self.channel2 = PulseChannel::new(false);
+        self.channel3 = WaveChannel::new();
+        self.channel3.wave_ram = wave_ram;
+        self.channel4 = NoiseChannel::new();
+    }
+
+    pub fn tick(&mut self, cycles: TCycles) {
+        for _ in 0..cycles {
+            if self.enabled {
+                self.channel1.tick();
+                self.channel2.tick();
+                self.channel3.tick();
+                self.channel4.tick();
+
+                self.frame_sequencer_cycles += 1;
+                if self.frame_sequencer_cycles == FRAME_SEQUENCER_PERIOD {
+                    self.frame_sequencer_cycles = 0;
+                    self.clock_frame_sequencer();
+                }
+            }
+
+            self.sample_counter += u64::from(self.sample_rate);
+            if self.sample_counter >= CYCLES_PER_SECOND {
+                self.sample_counter -= CYCLES_PER_SECOND;
+                self.push_sample();
+            }
+        }
+    }
+
+    /// Append the current output to the sample buffer, applying the master volume
+    fn push_sample(&mut self) {
+        let (left, right) = self.sample();
+        let left_volume = f32::from(self.nr50.bits(4..=6) + 1) / 8.0;
+        let right_volume = f32::from(self.nr50.bits(0..=2) + 1) / 8.0;
+
+        let max_samples = AUDIO_CHANNELS * self.sample_rate as usize * MAX_BUFFERED_SECONDS;
+        while self.samples.len() + AUDIO_CHANNELS > max_samples && !self.samples.is_empty() {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(left * left_volume);
+        self.samples.push_back(right * right_volume);
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        if step.is_multiple_of(2) {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if step == 7 {
+            self.channel1.envelope.clock(self.channel1.regs[2]);
+            self.channel2.envelope.clock(self.channel2.regs[2]);
+            self.channel4.envelope.clock(self.channel4.regs[2]);
+        }
+        self.frame_sequencer_step = (step + 1) % 8;
+    }
+
+    /// Return the current output of the APU as a `(left, right)` pair of samples, each
+    /// in the range `-1.0..=1.0`.
+    ///
+    /// Each channel is routed to the left and/or right output according to NR51.
+    pub fn sample(&self) -> (f32, f32) {
+        if !self.enabled {
+            return (0.0, 0.0);
+        }
+        let outputs = [
+            self.channel1.dac_output(),
+            self.channel2.dac_output(),
+            self.channel3.dac_output(),
+            self.channel4.dac_output(),
+        ];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (channel, output) in outputs.into_iter().enumerate() {
+            if self.nr51.bit(channel + 4) {
+                left += output;
+            }
+            if self.nr51.bit(channel) {
+                right += output;
+            }
+        }
+        (left / 4.0, right / 4.0)
+    }
+}
+
+/// Convert the digital output (0 through 15) of an enabled channel to an analog value
+/// in the range `-1.0..=1.0`
+fn dac_output(enabled: bool, digital: u8) -> f32 {
+    if enabled {
+        f32::from(digital) / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Disables a channel once it has played for the configured length
+#[derive(Debug)]
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        Self {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    fn load(&mut self, value: u8) {
+        self.counter = self.max - u16::from(value);
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Returns `true` if the counter has expired
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+}
+
+/// Volume envelope, configured by the NRx2 register
+#[derive(Debug, Default)]
+struct Envelope {
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn trigger(&mut self, reg: u8) {
+        self.volume = reg.bits(4..=7);
+        self.timer = reg.bits(0..=2);
+    }
+
+    fn clock(&mut self, reg: u8) {
+        let period = reg.bits(0..=2);
+        if period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = period;
+            if reg.bit(3) && self.volume < 15 {
+                self.volume += 1;
+            } else if !reg.bit(3) && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/// Frequency sweep of channel 1, configured by the NR10 register
+#[derive(Debug, Default)]
+struct Sweep {
+    enabled: bool,
+    shadow_frequency: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn reload_timer(&mut self, reg: u8) {
+        self.timer = match reg.bits(4..=6) {
+            0 => 8,
+            period => period,
+        };
+    }
+
+    /// Calculate the next frequency, returning `None` if it overflows
+    fn next_frequency(&self, reg: u8) -> Option<u16> {
+        let delta = self.shadow_frequency >> reg.bits(0..=2);
+        if reg.bit(3) {
+            Some(self.shadow_frequency - delta)
+        } else {
+            Some(self.shadow_frequency + delta).filter(|frequency| *frequency <= 0x07FF)
+        }
+    }
+}
+
+/// Pulse (square wave) channel
+#[derive(Debug)]
+struct PulseChannel {
+    /// Registers NRx0 through NRx4
+    regs: [u8; 5],
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    /// Frequency sweep (channel 1 only)
+    sweep: Option<Sweep>,
+    /// Cycles remaining until the next duty step
+    timer: u32,
+    duty_step: u8,
+}
+
+impl PulseChannel {
+    fn new(sweep: bool) -> Self {
+        Self {
+            regs: [0; 5],
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Default::default(),
+            sweep: sweep.then(Sweep::default),
+            timer: 0,
+            duty_step: 0,
+        }
+    }
+
+    fn frequency(&self) -> u16 {
+        u16::from(self.regs[3]) | (u16::from(self.regs[4].bits(0..=2)) << 8)
+    }
+
+    fn set_frequency(&mut self, frequency: u16) {
+        let [low, high] = frequency.to_le_bytes();
+        self.regs[3] = low;
+        self.regs[4] = (self.regs[4] & 0b1111_1000) | high.bits(0..=2);
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - u32::from(self.frequency())) * 4
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.regs[2] & 0b1111_1000 != 0
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.regs[usize::from(offset)] = value;
+        match offset {
+            1 => self.length.load(value.bits(0..=5)),
+            2 if !self.dac_enabled() => self.enabled = false,
+            4 => {
+                self.length.enabled = value.bit(6);
+                if value.bit(7) {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger(self.regs[2]);
+        self.timer = self.period();
+
+        let reg = self.regs[0];
+        let frequency = self.frequency();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow_frequency = frequency;
+            sweep.reload_timer(reg);
+            sweep.enabled = reg.bits(4..=6) != 0 || reg.bits(0..=2) != 0;
+            if reg.bits(0..=2) != 0 && sweep.next_frequency(reg).is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        let reg = self.regs[0];
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer(reg);
+        if !sweep.enabled || reg.bits(4..=6) == 0 {
+            return;
+        }
+
+        match sweep.next_frequency(reg) {
+            Some(frequency) if reg.bits(0..=2) != 0 => {
+                sweep.shadow_frequency = frequency;
+                // The overflow check is performed a second time with the new frequency
+                let overflow = sweep.next_frequency(reg).is_none();
+                self.set_frequency(frequency);
+                if overflow {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    fn output(&self) -> u8 {
+        let pattern = DUTY_PATTERNS[usize::from(self.regs[1].bits(6..=7))];
+        if self.enabled && pattern.bit(usize::from(7 - self.duty_step)) {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn dac_output(&self) -> f32 {
+        dac_output(self.enabled && self.dac_enabled(), self.output())
+    }
+}
+
+/// Wave channel, which plays back the 4-bit samples stored in wave RAM
+#[derive(Debug)]
+struct WaveChannel {
+    /// Registers NR30 through NR34
+    regs: [u8; 5],
+    enabled: bool,
+    length: LengthCounter,
+    /// Cycles remaining until the next sample
+    timer: u32,
+    /// Index of the current sample in wave RAM
+    position: u8,
+    wave_ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    fn new() -> Self {
+        Self {
+            regs: [0; 5],
+            enabled: false,
+            length: LengthCounter::new(256),
+            timer: 0,
+            position: 0,
+            wave_ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    fn frequency(&self) -> u16 {
+        u16::from(self.regs[3]) | (u16::from(self.regs[4].bits(0..=2)) << 8)
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - u32::from(self.frequency())) * 2
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.regs[0].bit(7)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.regs[usize::from(offset)] = value;
+        match offset {
+            0 if !self.dac_enabled() => self.enabled = false,
+            1 => self.length.load(value),
+            4 => {
+                self.length.enabled = value.bit(6);
+                if value.bit(7) {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.position = (self.position + 1) % (2 * WAVE_RAM_SIZE as u8);
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte = self.wave_ram[usize::from(self.position / 2)];
+        let sample = if self.position.is_multiple_of(2) {
+            byte.bits(4..=7)
+        } else {
+            byte.bits(0..=3)
+        };
+        match self.regs[2].bits(5..=6) {
+            0 => 0,
+            code => sample >> (code - 1),
+        }
+    }
+
+    fn dac_output(&self) -> f32 {
+        dac_output(self.enabled && self.dac_enabled(), self.output())
+    }
+}
+
+/// Noise channel, driven by a linear-feedback shift register
+#[derive(Debug)]
+struct NoiseChannel {
+    /// Registers NR40 (unused) through NR44
+    regs: [u8; 5],
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    /// Cycles remaining until the LFSR is next shifted
+    timer: u32,
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    fn new() -> Self {
+        Self {
+            regs: [0; 5],
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Default::default(),
+            timer: 0,
+            lfsr: 0,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        let divisor = match self.regs[3].bits(0..=2) {
+            0 => 8,
+            code => u32::from(code) * 16,
+        };
+        divisor << self.regs[3].bits(4..=7)
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.regs[2] & 0b1111_1000 != 0
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.regs[usize::from(offset)] = value;
+        match offset {
+            1 => self.length.load(value.bits(0..=5)),
+            2 if !self.dac_enabled() => self.enabled = false,
+            4 => {
+                self.length.enabled = value.bit(6);
+                if value.bit(7) {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger(self.regs[2]);
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            let feedback = self.lfsr.bit(0) ^ self.lfsr.bit(1);
+            self.lfsr = (self.lfsr >> 1) | (u16::from(feedback) << 14);
+            // In 7-bit mode, the feedback is also written to bit 6
+            if self.regs[3].bit(3) {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (u16::from(feedback) << 6);
+            }
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && !self.lfsr.bit(0) {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn dac_output(&self) -> f32 {
+        dac_output(self.enabled && self.dac_enabled(), self.output())
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.counter);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.enabled = reader.read_bool()?;
+        self.counter = reader.read_u16()?;
+        if self.counter > self.max {
+            return Err(SnapshotError::InvalidValue("APU length counter"));
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.volume = reader.read_u8()? & 0x0F;
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for PulseChannel {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bytes(&self.regs);
+        writer.write_bool(self.enabled);
+        self.length.save_snapshot(writer);
+        self.envelope.save_snapshot(writer);
+        if let Some(sweep) = &self.sweep {
+            writer.write_bool(sweep.enabled);
+            writer.write_u16(sweep.shadow_frequency);
+            writer.write_u8(sweep.timer);
+        }
+        writer.write_u64(self.timer.into());
+        writer.write_u8(self.duty_step);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.read_bytes_into(&mut self.regs)?;
+        self.enabled = reader.read_bool()?;
+        self.length.restore_snapshot(reader)?;
+        self.envelope.restore_snapshot(reader)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.enabled = reader.read_bool()?;
+            sweep.shadow_frequency = reader.read_u16()? & 0x07FF;
+            sweep.timer = reader.read_u8()?;
+        }
+        self.timer = read_timer(reader)?;
+        self.duty_step = reader.read_u8()? % 8;
+        Ok(())
+    }
+}
+
+impl Snapshot for WaveChannel {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bytes(&self.regs);
+        writer.write_bool(self.enabled);
+        self.length.save_snapshot(writer);
+        writer.write_u64(self.timer.into());
+        writer.write_u8(self.position);
+        writer.write_bytes(&self.wave_ram);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.read_bytes_into(&mut self.regs)?;
+        self.enabled = reader.read_bool()?;
+        self.length.restore_snapshot(reader)?;
+        self.timer = read_timer(reader)?;
+        self.position = reader.read_u8()? % (2 * WAVE_RAM_SIZE as u8);
+        reader.read_bytes_into(&mut self.wave_ram)
+    }
+}
+
+impl Snapshot for NoiseChannel {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bytes(&self.regs);
+        writer.write_bool(self.enabled);
+        self.length.save_snapshot(writer);
+        self.envelope.save_snapshot(writer);
+        writer.write_u64(self.timer.into());
+        writer.write_u16(self.lfsr);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.read_bytes_into(&mut self.regs)?;
+        self.enabled = reader.read_bool()?;
+        self.length.restore_snapshot(reader)?;
+        self.envelope.restore_snapshot(reader)?;
+        self.timer = read_timer(reader)?;
+        self.lfsr = reader.read_u16()? & 0x7FFF;
+        Ok(())
+    }
+}
+
+fn read_timer(reader: &mut SnapshotReader) -> Result<u32, SnapshotError> {
+    u32::try_from(reader.read_u64()?).map_err(|_| SnapshotError::InvalidValue("APU timer"))
+}
+
+impl Snapshot for Apu {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.nr50);
+        writer.write_u8(self.nr51);
+        self.channel1.save_snapshot(writer);
+        self.channel2.save_snapshot(writer);
+        self.channel3.save_snapshot(writer);
+        self.channel4.save_snapshot(writer);
+        writer.write_u64(self.frame_sequencer_cycles.into());
+        writer.write_u8(self.frame_sequencer_step);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.enabled = reader.read_bool()?;
+        self.nr50 = reader.read_u8()?;
+        self.nr51 = reader.read_u8()?;
+        self.channel1.restore_snapshot(reader)?;
+        self.channel2.restore_snapshot(reader)?;
+        self.channel3.restore_snapshot(reader)?;
+        self.channel4.restore_snapshot(reader)?;
+        self.frame_sequencer_cycles = read_timer(reader)?;
+        if self.frame_sequencer_cycles >= FRAME_SEQUENCER_PERIOD {
+            return Err(SnapshotError::InvalidValue("APU frame sequencer"));
+        }
+        self.frame_sequencer_step = reader.read_u8()? % 8;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NR10: u16 = 0xFF10;
+    const NR11: u16 = 0xFF11;
+    const NR12: u16 = 0xFF12;
+    const NR13: u16 = 0xFF13;
+    const NR14: u16 = 0xFF14;
+    const NR30: u16 = 0xFF1A;
+    const NR32: u16 = 0xFF1C;
+    const NR34: u16 = 0xFF1E;
+    const NR42: u16 = 0xFF21;
+    const NR43: u16 = 0xFF22;
+    const NR44: u16 = 0xFF23;
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.reg_write(NR52, 0x80);
+        apu
+    }
+
+    /// Advance the APU by the given number of frame sequencer steps
+    fn tick_frame_sequencer(apu: &mut Apu, steps: u32) {
+        apu.tick(TCycles::from(steps * FRAME_SEQUENCER_PERIOD));
+    }
+
+    #[test]
+    fn register_reads() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.reg_read(NR52), 0x70);
+
+        // Writes are ignored while the APU is off (except for wave RAM)
+        apu.reg_write(NR12, 0xF3);
+        apu.reg_write(APU_STORAGE_START, 0x12);
+        assert_eq!(apu.reg_read(NR12), 0x00);
+        assert_eq!(apu.reg_read(APU_STORAGE_START), 0x12);
+
+        apu.reg_write(NR52, 0x80);
+        assert_eq!(apu.reg_read(NR52), 0xF0);
+        apu.reg_write(NR10, 0x00);
+        apu.reg_write(NR11, 0x80);
+        apu.reg_write(NR12, 0xF3);
+        apu.reg_write(NR13, 0x12);
+        apu.reg_write(NR14, 0x07);
+        assert_eq!(apu.reg_read(NR10), 0x80);
+        assert_eq!(apu.reg_read(NR11), 0xBF);
+        assert_eq!(apu.reg_read(NR12), 0xF3);
+        assert_eq!(apu.reg_read(NR13), 0xFF);
+        assert_eq!(apu.reg_read(NR14), 0xBF);
+
+        // Turning the APU off clears the registers
+        apu.reg_write(NR52, 0x00);
+        assert_eq!(apu.reg_read(NR12), 0x00);
+        assert_eq!(apu.reg_read(APU_STORAGE_START), 0x12);
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut apu = powered_apu();
+        apu.reg_write(NR12, 0xF0);
+        // Length of 64 - 62 = 2 frame sequencer length clocks
+        apu.reg_write(NR11, 62);
+        apu.reg_write(NR14, 0xC0);
+        assert_eq!(apu.reg_read(NR52) & 0x0F, 0b0001);
+
+        tick_frame_sequencer(&mut apu, 1);
+        assert_eq!(apu.reg_read(NR52) & 0x0F, 0b0001);
+        tick_frame_sequencer(&mut apu, 2);
+        assert_eq!(apu.reg_read(NR52) & 0x0F, 0b0000);
+
+        // Disabling the DAC disables the channel
+        apu.reg_write(NR14, 0x80);
+        assert_eq!(apu.reg_read(NR52) & 0x0F, 0b0001);
+        apu.reg_write(NR12, 0x00);
+        assert_eq!(apu.reg_read(NR52) & 0x0F, 0b0000);
+    }
+
+    #[test]
+    fn envelope() {
+        let mut apu = powered_apu();
+        // Initial volume 2, decreasing, period 1
+        apu.reg_write(NR12, 0x21);
+        apu.reg_write(NR14, 0x80);
+        assert_eq!(apu.channel1.envelope.volume, 2);
+
+        // The envelope is clocked on step 7 of the frame sequencer
+        tick_frame_sequencer(&mut apu, 8);
+        assert_eq!(apu.channel1.envelope.volume, 1);
+        tick_frame_sequencer(&mut apu, 8);
+        assert_eq!(apu.channel1.envelope.volume, 0);
+        tick_frame_sequencer(&mut apu, 8);
+        assert_eq!(apu.channel1.envelope.volume, 0);
+    }
+
+    #[test]
+    fn sweep_overflow() {
+        let mut apu = powered_apu();
+        // Period 1, increasing, shift 1
+        apu.reg_write(NR10, 0x11);
+        apu.reg_write(NR12, 0xF0);
+        apu.reg_write(NR13, 0x00);
+        apu.reg_write(NR14, 0x84);
+        assert!(apu.channel1.enabled);
+
+        // 0x400 + (0x400 >> 1) = 0x600, and the next step would overflow
+        tick_frame_sequencer(&mut apu, 3);
+        assert_eq!(apu.channel1.frequency(), 0x600);
+        assert!(!apu.channel1.enabled);
+    }
+
+    #[test]
+    fn wave_channel() {
+        let mut apu = powered_apu();
+        apu.reg_write(APU_STORAGE_START, 0xA5);
+        apu.reg_write(NR30, 0x80);
+        apu.reg_write(NR32, 0x20);
+        apu.reg_write(NR34, 0x87);
+        assert_eq!(apu.reg_read(NR52) & 0x0F, 0b0100);
+
+        // Frequency 0x700 advances one sample every (2048 - 0x700) * 2 cycles
+        let period = TCycles::from(apu.channel3.period());
+        assert_eq!(apu.channel3.output(), 0x0A);
+        apu.tick(period);
+        assert_eq!(apu.channel3.output(), 0x05);
+
+        // Volume code 2 shifts each sample right once
+        apu.reg_write(NR32, 0x40);
+        assert_eq!(apu.channel3.output(), 0x02);
+        apu.reg_write(NR32, 0x00);
+        assert_eq!(apu.channel3.output(), 0x00);
+    }
+
+    #[test]
+    fn noise_channel() {
+        let mut apu = powered_apu();
+        apu.reg_write(NR42, 0xF0);
+        apu.reg_write(NR43, 0x00);
+        apu.reg_write(NR44, 0x80);
+        assert_eq!(apu.channel4.lfsr, 0x7FFF);
+
+        // 0x7FFF shifts in zeros, so the output eventually switches on
+        apu.tick(8);
+        assert_eq!(apu.channel4.lfsr, 0x3FFF);
+        apu.tick(8 * 13);
+        assert_eq!(apu.channel4.lfsr, 0x0001);
+        assert_eq!(apu.channel4.output(), 0);
+        apu.tick(8);
+        assert_eq!(apu.channel4.output(), 15);
+    }
+
+    #[test]
+    fn panning() {
+        let mut apu = powered_apu();
+        apu.reg_write(NR12, 0xF0);
+        apu.reg_write(NR11, 0x80);
+        apu.reg_write(NR14, 0x80);
+        assert_eq!(apu.channel1.output(), 15);
+
+        apu.reg_write(NR51, 0x10);
+        let (left, right) = apu.sample();
+        assert_eq!(left, 0.25);
+        assert_eq!(right, 0.0);
+
+        apu.reg_write(NR51, 0x01);
+        let (left, right) = apu.sample();
+        assert_eq!(left, 0.0);
+        assert_eq!(right, 0.25);
+    }
+
+    #[test]
+    fn sample_buffer() {
+        let mut apu = Apu::new();
+        // One sample every 4096 cycles
+        apu.set_sample_rate(1024);
+        apu.tick(100 * 4096);
+        let samples = apu.drain_samples();
+        assert_eq!(samples.len(), 100 * AUDIO_CHANNELS);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+        assert!(apu.drain_samples().is_empty());
+
+        // At most one second of samples is buffered
+        apu.tick(2 * CYCLES_PER_SECOND as TCycles);
+        assert_eq!(apu.drain_samples().len(), 1024 * AUDIO_CHANNELS);
+    }
+
+    #[test]
+    fn master_volume() {
+        let mut apu = powered_apu();
+        apu.set_sample_rate(1024);
+        apu.reg_write(NR12, 0xF0);
+        apu.reg_write(NR11, 0x80);
+        apu.reg_write(NR14, 0x80);
+        apu.reg_write(NR51, 0x11);
+        // Left volume 8/8, right volume 4/8
+        apu.reg_write(NR50, 0x73);
+
+        apu.tick(4096);
+        let samples = apu.drain_samples();
+        assert_eq!(samples.len(), AUDIO_CHANNELS);
+        assert_eq!(samples[0], 0.25);
+        assert_eq!(samples[1], 0.125);
+    }
+}
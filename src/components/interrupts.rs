@@ -1,5 +1,10 @@
 use bitflags::bitflags;
 
+use crate::{
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    SnapshotError,
+};
+
 use super::mmu::{InterruptManager, INTERRUPT_ENABLE_REG, INTERRUPT_FLAG};
 
 /// For managing the IE (0xFFFF) and IF (0xFF0F) registers
@@ -33,6 +38,12 @@ impl InterruptRegisters {
             _ => unreachable!(),
         }
     }
+
+    /// Return every interrupt which is both requested (IF) and enabled (IE), without
+    /// selecting the one to handle next
+    pub fn pending_interrupts(&self) -> InterruptFlag {
+        self.reg_ie & self.reg_if
+    }
 }
 
 impl InterruptManager for InterruptRegisters {
@@ -45,7 +56,7 @@ impl InterruptManager for InterruptRegisters {
     }
 
     fn priority_interrupt(&mut self) -> Option<Interrupt> {
-        let flag = self.reg_ie & self.reg_if;
+        let flag = self.pending_interrupts();
         if flag.contains(InterruptFlag::VBlank) {
             Some(Interrupt::VBlank)
         } else if flag.contains(InterruptFlag::LcdStat) {
@@ -62,6 +73,19 @@ impl InterruptManager for InterruptRegisters {
     }
 }
 
+impl Snapshot for InterruptRegisters {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u8(self.reg_ie.bits());
+        writer.write_u8(self.reg_if.bits());
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.reg_ie = InterruptFlag::from_bits_truncate(reader.read_u8()?);
+        self.reg_if = InterruptFlag::from_bits_truncate(reader.read_u8()?);
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Interrupt {
     VBlank,
@@ -95,7 +119,7 @@ impl Interrupt {
 
 bitflags! {
     /// Flags for the IE and IF registers
-    #[derive(Debug, Default, Clone, Copy)]
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
     pub struct InterruptFlag: u8 {
         const VBlank = 0b0000_0001;
         const LcdStat = 0b0000_0010;
@@ -104,3 +128,35 @@ bitflags! {
         const Joypad = 0b0001_0000;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pending_interrupts() {
+        let mut interrupts = InterruptRegisters::new();
+        assert!(interrupts.pending_interrupts().is_empty());
+
+        interrupts.write(INTERRUPT_ENABLE_REG, 0x1F);
+        interrupts.if_set(Interrupt::Timer);
+        interrupts.if_set(Interrupt::VBlank);
+        assert!(matches!(
+            interrupts.priority_interrupt(),
+            Some(Interrupt::VBlank)
+        ));
+        assert_eq!(
+            interrupts.pending_interrupts(),
+            InterruptFlag::VBlank | InterruptFlag::Timer
+        );
+
+        // Requested interrupts which are not enabled are not pending
+        interrupts.write(INTERRUPT_ENABLE_REG, 0x04);
+        interrupts.if_set(Interrupt::Joypad);
+        assert_eq!(interrupts.pending_interrupts(), InterruptFlag::Timer);
+        assert!(matches!(
+            interrupts.priority_interrupt(),
+            Some(Interrupt::Timer)
+        ));
+    }
+}
@@ -1,12 +1,26 @@
 //! Joypad and serial transfer input/output handler
 //!
-//! Todo: Implement interrupts
-use crate::{bits::Bits, state::PollState, TCycles};
+//! Todo: Implement the joypad interrupt
+use std::collections::VecDeque;
+
+use bitflags::bitflags;
+
+use crate::{
+    bits::Bits,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    state::PollState,
+    SnapshotError, TCycles,
+};
 
 use super::mmu::InterruptManager;
 
+/// Maximum number of sent serial bytes kept in the buffer (older bytes are discarded)
+const MAX_SENT_BYTES: usize = 64 * 1024;
+
+/// Callback which receives each byte sent through the serial port
+pub type SerialSink = Box<dyn FnMut(u8) + Send>;
+
 /// Joypad and serial transfer handler
-#[derive(Debug)]
 pub struct IoHandler {
     /// 0xFF00 - P1/JOYP - Joypad regiseter
     ///
@@ -17,15 +31,38 @@ pub struct IoHandler {
     sb: u8,
     /// 0xFF02 - SC - serial transfer control
     sc: u8,
-    /// Bytes sent via serial transfer
-    sent_bytes: Vec<u8>,
+    /// Bytes sent via serial transfer (at most `MAX_SENT_BYTES`)
+    sent_bytes: VecDeque<u8>,
+    /// Total number of bytes sent via serial transfer, including those discarded from
+    /// `sent_bytes`
+    sent_byte_count: u64,
+    /// Callback notified of each byte sent via serial transfer
+    serial_sink: Option<SerialSink>,
+    /// If `true`, serial transfers are completed by a link partner (see
+    /// `serial_exchange()`) rather than automatically
+    link_connected: bool,
     /// Remaining cycle count
     remaining_cycles: TCycles,
     /// Joypad to track which keys have been pressed
     joypad: Joypad,
 }
 
-#[derive(Debug)]
+impl std::fmt::Debug for IoHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoHandler")
+            .field("joy", &self.joy)
+            .field("sb", &self.sb)
+            .field("sc", &self.sc)
+            .field("sent_bytes", &self.sent_bytes)
+            .field("sent_byte_count", &self.sent_byte_count)
+            .field("link_connected", &self.link_connected)
+            .field("remaining_cycles", &self.remaining_cycles)
+            .field("joypad", &self.joypad)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum JoypadButton {
     Up,
     Down,
@@ -37,6 +74,56 @@ pub enum JoypadButton {
     Select,
 }
 
+impl JoypadButton {
+    /// Return every joypad button
+    pub fn all() -> [JoypadButton; 8] {
+        [
+            JoypadButton::Up,
+            JoypadButton::Down,
+            JoypadButton::Left,
+            JoypadButton::Right,
+            JoypadButton::A,
+            JoypadButton::B,
+            JoypadButton::Start,
+            JoypadButton::Select,
+        ]
+    }
+}
+
+bitflags! {
+    /// State of all eight joypad buttons, where a set bit means the button is held down.
+    ///
+    /// The low nibble holds the action buttons and the high nibble the direction
+    /// buttons, in the same order as the P1 register, so the state of each frame of a
+    /// recorded input log fits in a single byte.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct JoypadState: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const Select = 0b0000_0100;
+        const Start = 0b0000_1000;
+        const Right = 0b0001_0000;
+        const Left = 0b0010_0000;
+        const Up = 0b0100_0000;
+        const Down = 0b1000_0000;
+    }
+}
+
+impl From<JoypadButton> for JoypadState {
+    fn from(button: JoypadButton) -> Self {
+        match button {
+            JoypadButton::Up => JoypadState::Up,
+            JoypadButton::Down => JoypadState::Down,
+            JoypadButton::Left => JoypadState::Left,
+            JoypadButton::Right => JoypadState::Right,
+            JoypadButton::A => JoypadState::A,
+            JoypadButton::B => JoypadState::B,
+            JoypadButton::Start => JoypadState::Start,
+            JoypadButton::Select => JoypadState::Select,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Joypad {
     up: bool,
@@ -47,6 +134,13 @@ struct Joypad {
     b: bool,
     start: bool,
     select: bool,
+    /// If `true`, only the most recently pressed button of Left/Right (and of
+    /// Up/Down) is reported as held down
+    suppress_opposite_directions: bool,
+    /// Most recently pressed button of Left and Right
+    last_horizontal: JoypadButton,
+    /// Most recently pressed button of Up and Down
+    last_vertical: JoypadButton,
 }
 
 impl Joypad {
@@ -60,10 +154,18 @@ impl Joypad {
             b: false,
             start: false,
             select: false,
+            suppress_opposite_directions: false,
+            last_horizontal: JoypadButton::Right,
+            last_vertical: JoypadButton::Down,
         }
     }
 
     fn button_pressed(&mut self, button: JoypadButton) {
+        match button {
+            JoypadButton::Left | JoypadButton::Right => self.last_horizontal = button,
+            JoypadButton::Up | JoypadButton::Down => self.last_vertical = button,
+            _ => {}
+        }
         match button {
             JoypadButton::Up => self.up = true,
             JoypadButton::Down => self.down = true,
@@ -88,6 +190,50 @@ impl Joypad {
             JoypadButton::Select => self.select = false,
         }
     }
+
+    /// Return the buttons which are held down (ignoring opposite direction suppression)
+    fn state(&self) -> JoypadState {
+        let mut state = JoypadState::empty();
+        for (held, button) in [
+            (self.up, JoypadState::Up),
+            (self.down, JoypadState::Down),
+            (self.left, JoypadState::Left),
+            (self.right, JoypadState::Right),
+            (self.a, JoypadState::A),
+            (self.b, JoypadState::B),
+            (self.start, JoypadState::Start),
+            (self.select, JoypadState::Select),
+        ] {
+            state.set(button, held);
+        }
+        state
+    }
+
+    /// Press every button in `state` and release all others.  Buttons which are not
+    /// already held down are pressed in the order given by `JoypadButton::all()`.
+    fn set_state(&mut self, state: JoypadState) {
+        let current = self.state();
+        for button in JoypadButton::all() {
+            let flag = JoypadState::from(button);
+            if !state.contains(flag) {
+                self.button_released(button);
+            } else if !current.contains(flag) {
+                self.button_pressed(button);
+            }
+        }
+    }
+
+    /// Returns `true` if the direction `button` should be reported as held down
+    fn direction_held(&self, button: JoypadButton) -> bool {
+        let (held, opposite_held, last) = match button {
+            JoypadButton::Up => (self.up, self.down, self.last_vertical),
+            JoypadButton::Down => (self.down, self.up, self.last_vertical),
+            JoypadButton::Left => (self.left, self.right, self.last_horizontal),
+            JoypadButton::Right => (self.right, self.left, self.last_horizontal),
+            _ => unreachable!(),
+        };
+        held && !(self.suppress_opposite_directions && opposite_held && last != button)
+    }
 }
 
 impl IoHandler {
@@ -96,7 +242,10 @@ impl IoHandler {
             joy: 0,
             sb: 0,
             sc: 0,
-            sent_bytes: Vec::new(),
+            sent_bytes: VecDeque::new(),
+            sent_byte_count: 0,
+            serial_sink: None,
+            link_connected: false,
             remaining_cycles: 0,
             joypad: Joypad::new(),
         }
@@ -121,7 +270,8 @@ impl IoHandler {
     }
 
     fn compute_joy(&self) -> u8 {
-        let mut value = self.joy;
+        // Bits 6 and 7 are unused and always read as 1
+        let mut value = 0b1100_0000 | self.joy;
         if !self.joy.bit(5) {
             // Action buttons
             set_bit_condition(&mut value, 3, self.joypad.start);
@@ -130,10 +280,11 @@ impl IoHandler {
             set_bit_condition(&mut value, 0, self.joypad.a);
         } else if !self.joy.bit(4) {
             // Direction buttons
-            set_bit_condition(&mut value, 3, self.joypad.down);
-            set_bit_condition(&mut value, 2, self.joypad.up);
-            set_bit_condition(&mut value, 1, self.joypad.left);
-            set_bit_condition(&mut value, 0, self.joypad.right);
+            let held = |button| self.joypad.direction_held(button);
+            set_bit_condition(&mut value, 3, held(JoypadButton::Down));
+            set_bit_condition(&mut value, 2, held(JoypadButton::Up));
+            set_bit_condition(&mut value, 1, held(JoypadButton::Left));
+            set_bit_condition(&mut value, 0, held(JoypadButton::Right));
         }
         value
     }
@@ -142,31 +293,126 @@ impl IoHandler {
     where
         T: InterruptManager,
     {
-        if self.sc == 0x81 {
+        if self.sc == 0x81 && !self.link_connected {
             self.remaining_cycles += cycles;
             if self.remaining_cycles >= 4 {
                 self.remaining_cycles = 0;
                 self.serial_transfer_byte();
                 interrupt_manager.if_set(super::interrupts::Interrupt::Serial);
-            } else {
-                self.remaining_cycles = 0;
             }
         }
     }
 
     fn serial_transfer_byte(&mut self) {
-        self.sent_bytes.push(self.sb);
+        self.record_sent_byte(self.sb);
         self.sb = 0;
         self.sc.reset_bit(7);
     }
 
+    fn record_sent_byte(&mut self, byte: u8) {
+        if let Some(sink) = &mut self.serial_sink {
+            sink(byte);
+        }
+        if self.sent_bytes.len() == MAX_SENT_BYTES {
+            self.sent_bytes.pop_front();
+        }
+        self.sent_bytes.push_back(byte);
+        self.sent_byte_count += 1;
+    }
+
+    /// Connect or disconnect a serial link partner.
+    ///
+    /// While connected, a transfer started using the internal clock is not completed
+    /// automatically.  Instead, the driver should check `serial_outgoing()` and swap
+    /// bytes with the partner using `serial_exchange()`.
+    pub fn set_link_connected(&mut self, connected: bool) {
+        self.link_connected = connected;
+    }
+
+    /// Return the byte to be sent if this side has started a transfer using the
+    /// internal clock (i.e., it is the link master), or `None` otherwise
+    pub fn serial_outgoing(&self) -> Option<u8> {
+        (self.sc == 0x81).then_some(self.sb)
+    }
+
+    /// Shift the byte `incoming` from the link partner into SB, returning the byte that
+    /// was shifted out.
+    ///
+    /// If a transfer was requested (SC bit 7 is set, using either clock source), it
+    /// is completed and a serial interrupt is requested.
+    pub fn serial_exchange<T>(&mut self, incoming: u8, interrupt_manager: &mut T) -> u8
+    where
+        T: InterruptManager,
+    {
+        let outgoing = self.sb;
+        self.sb = incoming;
+        if self.sc.bit(7) {
+            self.record_sent_byte(outgoing);
+            self.sc.reset_bit(7);
+            self.remaining_cycles = 0;
+            interrupt_manager.if_set(super::interrupts::Interrupt::Serial);
+        }
+        outgoing
+    }
+
     pub fn button_pressed(&mut self, button: JoypadButton) {
         self.joypad.button_pressed(button);
     }
 
+    /// Returns `true` if a button in one of the groups selected by P1 is held down
+    pub fn button_held(&self) -> bool {
+        self.compute_joy().bits(0..=3) != 0x0F
+    }
+
     pub fn button_released(&mut self, button: JoypadButton) {
         self.joypad.button_released(button);
     }
+
+    /// Return the buttons which are held down
+    pub fn joypad(&self) -> JoypadState {
+        self.joypad.state()
+    }
+
+    /// Hold down every button in `state` and release all others
+    pub fn set_joypad(&mut self, state: JoypadState) {
+        self.joypad.set_state(state);
+    }
+
+    /// Reset the registers and serial buffer to their power-on state.  The joypad
+    /// state, serial sink and serial link connection are kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            serial_sink: self.serial_sink.take(),
+            link_connected: self.link_connected,
+            joypad: std::mem::replace(&mut self.joypad, Joypad::new()),
+            ..Self::new()
+        };
+    }
+
+    /// Return the bytes sent through the serial port (up to the most recent
+    /// `MAX_SENT_BYTES`)
+    pub fn sent_bytes(&self) -> impl ExactSizeIterator<Item = u8> + '_ {
+        self.sent_bytes.iter().copied()
+    }
+
+    /// Total number of bytes sent through the serial port, including bytes which no
+    /// longer fit in the buffer
+    pub fn sent_byte_count(&self) -> u64 {
+        self.sent_byte_count
+    }
+
+    /// Set a callback which is invoked with each byte sent through the serial port,
+    /// or remove it with `None`
+    pub fn set_serial_sink(&mut self, sink: Option<SerialSink>) {
+        self.serial_sink = sink;
+    }
+
+    /// If `suppress` is `true`, opposite directions (Left and Right, or Up and Down)
+    /// are never reported as held down at the same time; the most recently pressed
+    /// one takes precedence.  This is disabled by default.
+    pub fn set_suppress_opposite_directions(&mut self, suppress: bool) {
+        self.joypad.suppress_opposite_directions = suppress;
+    }
 }
 
 /// Reset bit `index` of `value` if `cond` is `true`; sets the bit if `cond`
@@ -201,6 +447,29 @@ mod test {
         assert_eq!(interrupt_manager.read(0xFF0F), 0b1110_1000);
     }
 
+    #[test]
+    fn serial_interrupt_on_completion() {
+        let mut io = IoHandler::new();
+        let mut interrupt_manager = InterruptRegisters::new();
+
+        // No transfer takes place when using an external clock
+        io.write(0xFF02, 0x80);
+        io.tick(16, &mut interrupt_manager);
+        assert_eq!(interrupt_manager.read(0xFF0F), 0b1110_0000);
+        assert!(io.sent_bytes.is_empty());
+
+        // The interrupt is only requested once the transfer completes, even if the
+        // cycles are split across several ticks
+        io.write(0xFF02, 0x81);
+        for _ in 0..3 {
+            io.tick(1, &mut interrupt_manager);
+            assert_eq!(interrupt_manager.read(0xFF0F), 0b1110_0000);
+        }
+        io.tick(1, &mut interrupt_manager);
+        assert_eq!(io.sent_bytes.len(), 1);
+        assert_eq!(interrupt_manager.read(0xFF0F), 0b1110_1000);
+    }
+
     #[test]
     fn joypad() {
         let mut io = IoHandler::new();
@@ -208,10 +477,177 @@ mod test {
         io.button_pressed(JoypadButton::Down);
 
         io.write(0xFF00, 0b0001_0000);
-        assert_eq!(io.read(0xFF00), 0b0001_1110);
+        assert_eq!(io.read(0xFF00), 0b1101_1110);
 
         io.write(0xFF00, 0b0010_0000);
-        assert_eq!(io.read(0xFF00), 0b0010_0111);
+        assert_eq!(io.read(0xFF00), 0b1110_0111);
+    }
+
+    #[test]
+    fn joypad_unused_bits() {
+        let mut io = IoHandler::new();
+        for select in [0x00, 0x10, 0x20, 0x30, 0xCF, 0xFF] {
+            io.write(0xFF00, select);
+            assert_eq!(io.read(0xFF00) & 0b1100_0000, 0b1100_0000);
+            assert_eq!(io.read(0xFF00) & 0b0011_0000, select & 0b0011_0000);
+        }
+        io.button_pressed(JoypadButton::Start);
+        io.write(0xFF00, 0x10);
+        assert_eq!(io.read(0xFF00), 0b1101_0111);
+    }
+
+    #[test]
+    fn suppress_opposite_directions() {
+        let mut io = IoHandler::new();
+        io.write(0xFF00, 0b0010_0000);
+        io.button_pressed(JoypadButton::Left);
+        io.button_pressed(JoypadButton::Right);
+        // By default, both directions are reported
+        assert_eq!(io.read(0xFF00), 0b1110_1100);
+
+        io.set_suppress_opposite_directions(true);
+        assert_eq!(io.read(0xFF00), 0b1110_1110);
+        io.button_pressed(JoypadButton::Up);
+        io.button_pressed(JoypadButton::Down);
+        assert_eq!(io.read(0xFF00), 0b1110_0110);
+        io.button_pressed(JoypadButton::Up);
+        assert_eq!(io.read(0xFF00), 0b1110_1010);
+
+        // Releasing the most recent direction reveals the one still held
+        io.button_released(JoypadButton::Right);
+        assert_eq!(io.read(0xFF00), 0b1110_1001);
+    }
+
+    #[test]
+    fn serial_sink() {
+        let mut io = IoHandler::new();
+        let mut interrupt_manager = InterruptRegisters::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        io.set_serial_sink(Some(Box::new(move |byte| sender.send(byte).unwrap())));
+
+        for byte in [0x12, 0x34] {
+            io.write(0xFF01, byte);
+            io.write(0xFF02, 0x81);
+            io.tick(4, &mut interrupt_manager);
+        }
+        assert_eq!(receiver.try_iter().collect::<Vec<u8>>(), vec![0x12, 0x34]);
+        assert!(io.sent_bytes().eq([0x12, 0x34]));
+
+        // The buffer only keeps the most recent bytes
+        io.set_serial_sink(None);
+        for i in 0..MAX_SENT_BYTES {
+            io.write(0xFF01, i as u8);
+            io.write(0xFF02, 0x81);
+            io.tick(4, &mut interrupt_manager);
+        }
+        assert_eq!(io.sent_bytes().len(), MAX_SENT_BYTES);
+        assert_eq!(io.sent_byte_count(), MAX_SENT_BYTES as u64 + 2);
+        assert_eq!(io.sent_bytes().next(), Some(0x00));
+        assert_eq!(io.sent_bytes().last(), Some(0xFF));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn serial_link() {
+        let mut master = IoHandler::new();
+        let mut slave = IoHandler::new();
+        let mut master_interrupts = InterruptRegisters::new();
+        let mut slave_interrupts = InterruptRegisters::new();
+        master.set_link_connected(true);
+        slave.set_link_connected(true);
+
+        slave.write(0xFF01, 0x34);
+        slave.write(0xFF02, 0x80);
+        master.write(0xFF01, 0x12);
+        master.write(0xFF02, 0x81);
+
+        // The transfer waits for the link partner
+        master.tick(4096, &mut master_interrupts);
+        assert_eq!(master.read(0xFF02), 0x81);
+        assert_eq!(slave.serial_outgoing(), None);
+
+        let outgoing = master.serial_outgoing().unwrap();
+        assert_eq!(outgoing, 0x12);
+        let incoming = slave.serial_exchange(outgoing, &mut slave_interrupts);
+        master.serial_exchange(incoming, &mut master_interrupts);
+
+        assert_eq!(master.read(0xFF01), 0x34);
+        assert_eq!(slave.read(0xFF01), 0x12);
+        for (io, interrupts) in [(&master, &master_interrupts), (&slave, &slave_interrupts)] {
+            assert!(!io.read(0xFF02).bit(7));
+            assert!(interrupts.read(0xFF0F).bit(3));
+        }
+        assert!(master.sent_bytes().eq([0x12]));
+        assert!(slave.sent_bytes().eq([0x34]));
+        assert_eq!(master.serial_outgoing(), None);
+    }
+
+    #[test]
+    fn all_joypad_buttons() {
+        let buttons = JoypadButton::all();
+        for (i, button) in buttons.iter().enumerate() {
+            assert!(!buttons[..i].contains(button));
+        }
+
+        // Each button controls a different bit of P1
+        let mut io = IoHandler::new();
+        let mut bits = 0;
+        for button in buttons {
+            io.button_pressed(button);
+            for select in [0x10, 0x20] {
+                io.write(0xFF00, select);
+                bits |= u16::from(!io.read(0xFF00) & 0x0F) << (select >> 2);
+            }
+            io.button_released(button);
+        }
+        assert_eq!(bits, 0x0FF0);
+    }
+
+    #[test]
+    fn set_joypad() {
+        let mut io = IoHandler::new();
+        io.button_pressed(JoypadButton::B);
+        io.set_joypad(JoypadState::from_bits_retain(0b0110_1001));
+        assert_eq!(
+            io.joypad(),
+            JoypadState::A | JoypadState::Start | JoypadState::Left | JoypadState::Up
+        );
+
+        // Action buttons
+        io.write(0xFF00, 0x10);
+        assert_eq!(io.compute_joy() & 0x0F, 0b0110);
+        // Direction buttons
+        io.write(0xFF00, 0x20);
+        assert_eq!(io.compute_joy() & 0x0F, 0b1001);
+
+        io.set_joypad(JoypadState::empty());
+        assert_eq!(io.joypad(), JoypadState::empty());
+        assert_eq!(io.compute_joy() & 0x0F, 0x0F);
+        io.write(0xFF00, 0x10);
+        assert_eq!(io.compute_joy() & 0x0F, 0x0F);
+    }
+
+    #[test]
+    fn poll_serial_state() {
+        let mut io = IoHandler::new();
+        let mut interrupt_manager = InterruptRegisters::new();
+        io.write(0xFF01, 0x5A);
+        io.write(0xFF02, 0x80);
+
+        let mut state = crate::State::default();
+        io.poll_state(&mut state);
+        let io_state = state.io.as_ref().unwrap();
+        assert_eq!(io_state.registers["SB"], 0x5A);
+        assert_eq!(io_state.registers["SC"], 0x80);
+        assert!(io_state.transmitted_bytes.is_empty());
+
+        io.write(0xFF02, 0x81);
+        io.tick(4, &mut interrupt_manager);
+        io.poll_state(&mut state);
+        let io_state = state.io.as_ref().unwrap();
+        assert_eq!(io_state.registers["SB"], 0x00);
+        assert_eq!(io_state.registers["SC"], 0x01);
+        assert_eq!(io_state.transmitted_bytes, [0x5A]);
     }
 }
 
@@ -221,10 +657,49 @@ impl PollState for IoHandler {
             state.io = Some(Default::default());
         }
         if let Some(io_state) = &mut state.io {
-            io_state.transmitted_bytes = self.sent_bytes.clone();
+            io_state.transmitted_bytes = self.sent_bytes().collect();
             io_state.registers.insert("P1".into(), self.compute_joy());
             io_state.registers.insert("SB".into(), self.sb);
             io_state.registers.insert("SC".into(), self.sc);
         }
     }
 }
+
+impl Snapshot for IoHandler {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u8(self.joy);
+        writer.write_u8(self.sb);
+        writer.write_u8(self.sc);
+        writer.write_bytes(&self.sent_bytes().collect::<Vec<u8>>());
+        writer.write_i64(self.remaining_cycles);
+        for pressed in [
+            self.joypad.up,
+            self.joypad.down,
+            self.joypad.left,
+            self.joypad.right,
+            self.joypad.a,
+            self.joypad.b,
+            self.joypad.start,
+            self.joypad.select,
+        ] {
+            writer.write_bool(pressed);
+        }
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.joy = reader.read_u8()? & 0b0011_0000;
+        self.sb = reader.read_u8()?;
+        self.sc = reader.read_u8()? & 0b1000_0001;
+        self.sent_bytes = reader.read_bytes()?.iter().copied().collect();
+        self.remaining_cycles = reader.read_i64()?;
+        self.joypad.up = reader.read_bool()?;
+        self.joypad.down = reader.read_bool()?;
+        self.joypad.left = reader.read_bool()?;
+        self.joypad.right = reader.read_bool()?;
+        self.joypad.a = reader.read_bool()?;
+        self.joypad.b = reader.read_bool()?;
+        self.joypad.start = reader.read_bool()?;
+        self.joypad.select = reader.read_bool()?;
+        Ok(())
+    }
+}
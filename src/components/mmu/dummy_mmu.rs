@@ -7,6 +7,8 @@ use std::fmt::Debug;
 
 use super::*;
 
+use crate::state::PollState;
+
 const MEMORY_SIZE: usize = 64 * 1024;
 
 pub struct DummyMmu {
@@ -56,3 +58,15 @@ impl InterruptManager for DummyMmu {
         None
     }
 }
+
+// DummyMmu does not have a joypad
+impl JoypadInput for DummyMmu {
+    fn button_held(&self) -> bool {
+        false
+    }
+}
+
+// DummyMmu does not have any state to report
+impl PollState for DummyMmu {
+    fn poll_state(&self, _state: &mut crate::State) {}
+}
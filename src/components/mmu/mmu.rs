@@ -1,16 +1,29 @@
-use std::fmt::Debug;
+use std::{cell::Cell, collections::HashSet, fmt::Debug};
 
 use super::*;
 
 use crate::{
-    cartridge,
-    components::{interrupts::InterruptRegisters, io::IoHandler, ppu::Ppu, timers::Timers},
+    bits::Bits,
+    cartridge::{self, CartridgeOptions, NINTENDO_LOGO},
+    components::{
+        apu::Apu,
+        interrupts::{InterruptFlag, InterruptRegisters},
+        io::{IoHandler, SerialSink},
+        ppu::Ppu,
+        timers::Timers,
+    },
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
     state::PollState,
-    Color, JoypadButton, TCycles,
+    Color, JoypadButton, JoypadState, SnapshotError, TCycles,
 };
 
 const BOOT_ROM_SIZE: usize = 0x0100;
 
+/// Number of bytes copied to OAM during an OAM DMA transfer
+const OAM_DMA_LENGTH: u16 = OAM_SIZE as u16;
+/// Number of cycles required to copy a single byte during an OAM DMA transfer
+const OAM_DMA_CYCLES_PER_BYTE: TCycles = 4;
+
 const DEFAULT_READ_VALUE: u8 = 0xFF;
 
 pub const ROM_BANK0_START: u16 = 0x0000;
@@ -61,12 +74,62 @@ pub const PPU_OBP1: u16 = 0xFF49;
 pub const PPU_WY: u16 = 0xFF4A;
 pub const PPU_WX: u16 = 0xFF4B;
 pub const PPU_REG_END: u16 = 0xFF4B;
+/// CGB speed switch register
+const KEY1_REG: u16 = 0xFF4D;
 const BANK_REG: u16 = 0xFF50;
+/// Registers in the I/O region which are not otherwise mapped (e.g., unused or CGB
+/// only registers)
+const UNUSED_IO_START: u16 = 0xFF00;
+const UNUSED_IO_END: u16 = 0xFF7F;
 pub const HRAM_START: u16 = 0xFF80;
 const HRAM_END: u16 = 0xFFFE;
 const HRAM_SIZE: usize = (HRAM_END - HRAM_START + 1) as usize;
 pub const INTERRUPT_ENABLE_REG: u16 = 0xFFFF;
 
+/// Values of the hardware registers after the DMG boot ROM has finished, in the order
+/// they are written.  Registers whose post-boot value cannot be written directly (e.g.,
+/// DIV and LY) or would start an operation (e.g., DMA and the NRx4 trigger bits) are
+/// handled separately or omitted.
+const POST_BOOT_REGISTERS: [(u16, u8); 26] = [
+    (0xFF02, 0x7E), // SC
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF26, 0xF1), // NR52 (the APU must be powered on before writing to it)
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0x3F), // NR14 (without the trigger bit)
+    (0xFF16, 0x3F), // NR21
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0x3F), // NR24 (without the trigger bit)
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0x3F), // NR34 (without the trigger bit)
+    (0xFF20, 0xFF), // NR41
+    (0xFF23, 0x3F), // NR44 (without the trigger bit)
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF50, 0x01), // Boot ROM bank register
+];
+
+/// Value of the system clock (the upper byte of which is DIV) after the DMG boot ROM
+/// has finished
+const POST_BOOT_SYSTEM_CLOCK: u16 = 0xABCC;
+
+/// Offset of the Nintendo logo within the DMG boot ROM
+const BOOT_ROM_LOGO_START: usize = 0x00A8;
+/// Offset of the final instruction of the DMG boot ROM (`LDH ($50), A`), which unmaps
+/// the boot ROM
+const BOOT_ROM_DISABLE_START: usize = 0x00FC;
+const BOOT_ROM_DISABLE_INSTRUCTION: [u8; 2] = [0xE0, 0x50];
+
 /// Memory management unit
 pub struct Mmu {
     /// Boot ROM
@@ -75,6 +138,8 @@ pub struct Mmu {
     boot_mode: bool,
     /// Game cartridge
     cartridge: cartridge::Cartridge,
+    /// Header overrides used to create the cartridge (and to recreate it on reset)
+    cartridge_options: CartridgeOptions,
     /// High RAM
     hram: [u8; HRAM_SIZE],
     /// Work RAM
@@ -83,33 +148,206 @@ pub struct Mmu {
     io: IoHandler,
     /// Pixel processing unit
     ppu: Ppu,
+    /// Audio processing unit
+    apu: Apu,
     /// Interrupt manager
     interrupt_reg: InterruptRegisters,
     /// Timers
     timers: Timers,
+    /// Active OAM DMA transfer (if any)
+    oam_dma: Option<OamDma>,
+    /// Addresses which are watched for writes
+    watchpoints: HashSet<u16>,
+    /// First write to a watched address (address and value) since the last call to
+    /// `take_watchpoint_hit()`
+    watchpoint_hit: Option<(u16, u8)>,
+    /// If `true`, the CPU cannot access VRAM during PPU mode 3 or OAM during PPU modes
+    /// 2 and 3
+    strict_vram_timing: bool,
+    /// Last value transferred on the data bus
+    open_bus: Cell<u8>,
+    /// If `true`, reading from an unmapped address returns the last value on the data
+    /// bus instead of `DEFAULT_READ_VALUE`
+    open_bus_emulation: bool,
+    /// If `true`, CGB registers supported by the emulator (currently only KEY1) are
+    /// mapped
+    cgb_enabled: bool,
+    /// CGB double speed mode, where the CPU, timers, serial port and OAM DMA run twice
+    /// as fast as the PPU and APU
+    double_speed: bool,
+    /// KEY1 bit 0: a speed switch is performed by the next STOP instruction
+    speed_switch_armed: bool,
+    /// In double speed mode, a single CPU cycle which has not yet been passed on to
+    /// the PPU and APU
+    half_cycle: bool,
+}
+
+/// OAM DMA transfer in progress
+#[derive(Debug)]
+struct OamDma {
+    /// Start address of the transfer source
+    source: u16,
+    /// Number of bytes copied so far
+    index: u16,
+    /// Cycles accumulated towards copying the next byte
+    cycles: TCycles,
 }
 
 impl Mmu {
     pub fn new(rom: &[u8], boot_rom: &[u8]) -> Result<Self, crate::BootError> {
-        if boot_rom.len() != BOOT_ROM_SIZE {
-            return Err(crate::BootRomError::Size {
-                expected: BOOT_ROM_SIZE,
-                found: boot_rom.len(),
+        Self::new_with_options(rom, boot_rom, CartridgeOptions::default())
+    }
+
+    /// Create an MMU whose cartridge overrides values of its header with
+    /// `cartridge_options`
+    pub fn new_with_options(
+        rom: &[u8],
+        boot_rom: &[u8],
+        cartridge_options: CartridgeOptions,
+    ) -> Result<Self, crate::BootError> {
+        Self::new_owned(rom.to_vec(), Some(boot_rom), cartridge_options)
+    }
+
+    /// Create an MMU which takes ownership of the ROM buffer, instead of copying it.
+    ///
+    /// Without a boot ROM, the hardware registers are initialized to the values they
+    /// hold after the DMG boot ROM has finished.  The cartridge overrides values of its
+    /// header with `cartridge_options`.
+    pub fn new_owned(
+        rom: Vec<u8>,
+        boot_rom: Option<&[u8]>,
+        cartridge_options: CartridgeOptions,
+    ) -> Result<Self, crate::BootError> {
+        if let Some(boot_rom) = boot_rom {
+            if boot_rom.len() != BOOT_ROM_SIZE {
+                return Err(crate::BootRomError::Size {
+                    expected: BOOT_ROM_SIZE,
+                    found: boot_rom.len(),
+                }
+                .into());
             }
-            .into());
         }
-        Ok(Self {
-            boot_rom: boot_rom.into(),
+        let cartridge = cartridge::new_cartridge_owned(rom, cartridge_options)?;
+        if cartridge.header().cgb_mode == cartridge::CgbMode::CgbOnly {
+            tracing::warn!(target: "boot", "'{}' is a CGB only cartridge, which is not supported and will likely not run correctly", cartridge.header().title);
+        }
+        let mut mmu = Self {
+            boot_rom: boot_rom.unwrap_or_default().into(),
             boot_mode: true,
-            cartridge: cartridge::new_cartridge(rom)?,
+            cartridge,
+            cartridge_options,
             hram: [0; HRAM_SIZE],
             wram: [0; WRAM_SIZE],
             io: IoHandler::new(),
             ppu: Ppu::new(),
+            apu: Apu::new(),
             interrupt_reg: InterruptRegisters::new(),
             timers: Timers::new(),
-        })
+            oam_dma: None,
+            watchpoints: HashSet::new(),
+            watchpoint_hit: None,
+            strict_vram_timing: true,
+            open_bus: Cell::new(DEFAULT_READ_VALUE),
+            open_bus_emulation: false,
+            cgb_enabled: false,
+            double_speed: false,
+            speed_switch_armed: false,
+            half_cycle: false,
+        };
+        if boot_rom.is_none() {
+            mmu.init_post_boot();
+        }
+        Ok(mmu)
+    }
+
+    /// Create an MMU after checking that `boot_rom` looks like a DMG boot ROM (see
+    /// `verify_boot_rom()`)
+    pub fn new_verified(rom: &[u8], boot_rom: &[u8]) -> Result<Self, crate::BootError> {
+        verify_boot_rom(boot_rom)?;
+        Self::new(rom, boot_rom)
+    }
+
+    /// Set the hardware registers to the values they hold after the DMG boot ROM has
+    /// finished
+    fn init_post_boot(&mut self) {
+        for (addr, value) in POST_BOOT_REGISTERS {
+            self.write(addr, value);
+        }
+        self.timers.set_system_clock(POST_BOOT_SYSTEM_CLOCK);
+    }
+
+    /// Returns `true` if the MMU was created with a boot ROM
+    pub fn has_boot_rom(&self) -> bool {
+        !self.boot_rom.is_empty()
     }
+
+    /// Returns `true` while the boot ROM is mapped over the start of the cartridge ROM
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_mode
+    }
+
+    /// Reset the MMU and its components to their power-on state, as if the emulator
+    /// had just been created.
+    ///
+    /// The cartridge is reloaded from its ROM, keeping the contents of battery-backed
+    /// RAM.  Settings (such as watchpoints and the audio sample rate) are kept.
+    pub fn reset(&mut self) {
+        let saved_ram = if self.has_battery() {
+            self.cartridge.ram().map(<[u8]>::to_vec)
+        } else {
+            None
+        };
+        self.cartridge = cartridge::new_cartridge(self.cartridge.rom(), self.cartridge_options)
+            .expect("the cartridge ROM was accepted when the MMU was created");
+        if let Some(ram) = saved_ram {
+            if let Err(e) = self.cartridge.load_ram(&ram) {
+                tracing::error!(target: "mmu", "failed to restore cartridge RAM after reset: {}", e);
+            }
+        }
+
+        self.boot_mode = true;
+        self.hram = [0; HRAM_SIZE];
+        self.wram = [0; WRAM_SIZE];
+        self.io.reset();
+        self.ppu = Ppu::new();
+        self.apu.reset();
+        self.interrupt_reg = InterruptRegisters::new();
+        self.timers = Timers::new();
+        self.oam_dma = None;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.half_cycle = false;
+        self.watchpoint_hit = None;
+        self.open_bus.set(DEFAULT_READ_VALUE);
+        if !self.has_boot_rom() {
+            self.init_post_boot();
+        }
+    }
+}
+
+/// Check that `boot_rom` has the size of the DMG boot ROM, contains the Nintendo logo
+/// and ends by unmapping itself
+pub fn verify_boot_rom(boot_rom: &[u8]) -> Result<(), crate::BootRomError> {
+    if boot_rom.len() != BOOT_ROM_SIZE {
+        return Err(crate::BootRomError::Size {
+            expected: BOOT_ROM_SIZE,
+            found: boot_rom.len(),
+        });
+    }
+    let logo = &boot_rom[BOOT_ROM_LOGO_START..BOOT_ROM_LOGO_START + NINTENDO_LOGO.len()];
+    if logo != NINTENDO_LOGO {
+        return Err(crate::BootRomError::InvalidContents(
+            "missing Nintendo logo",
+        ));
+    }
+    let disable = &boot_rom
+        [BOOT_ROM_DISABLE_START..BOOT_ROM_DISABLE_START + BOOT_ROM_DISABLE_INSTRUCTION.len()];
+    if disable != BOOT_ROM_DISABLE_INSTRUCTION {
+        return Err(crate::BootRomError::InvalidContents(
+            "boot ROM is never unmapped",
+        ));
+    }
+    Ok(())
 }
 
 impl Debug for Mmu {
@@ -132,7 +370,7 @@ enum MappedAddress {
     ExternalRam(u16),
     /// Work RAM
     WRam(u16),
-    /// Mirrored work RAM
+    /// Mirrored work RAM (echo of 0xC000..=0xDDFF)
     MirrorRam(u16),
     /// Object attribute memory
     Oam(u16),
@@ -146,10 +384,14 @@ enum MappedAddress {
     PpuReg,
     /// Bank register, used to indicate when the boot ROM should be unmapped
     BankReg,
+    /// CGB speed switch register (unused unless CGB support is enabled)
+    Key1,
     /// High RAM
     HRam(u16),
     /// Interrupt flag and register
     Interrupt,
+    /// Unused I/O registers (reads return 0xFF and writes are ignored)
+    UnusedIo,
 }
 
 impl TryFrom<u16> for MappedAddress {
@@ -173,8 +415,10 @@ impl TryFrom<u16> for MappedAddress {
             | APU_STORAGE_START..=APU_STORAGE_END => Ok(Self::ApuReg),
             PPU_REG_START..=PPU_REG_END => Ok(Self::PpuReg),
             BANK_REG => Ok(Self::BankReg),
+            KEY1_REG => Ok(Self::Key1),
             HRAM_START..=HRAM_END => Ok(Self::HRam(addr - HRAM_START)),
             INTERRUPT_ENABLE_REG => Ok(Self::Interrupt),
+            _ if (UNUSED_IO_START..=UNUSED_IO_END).contains(&addr) => Ok(Self::UnusedIo),
             _ => Err(()),
         }
     }
@@ -227,16 +471,17 @@ impl Mmu {
             MappedAddress::VRam(addr) => self.ppu.vram_read(addr),
             MappedAddress::ExternalRam(addr) => self.cartridge.read_ram(addr),
             MappedAddress::WRam(addr) => self.wram[usize::from(addr)],
-            // MappedAddress::MirrorRam(_) => todo!(),
+            MappedAddress::MirrorRam(addr) => self.wram[usize::from(addr)],
             MappedAddress::Oam(addr) => self.ppu.oam_read(addr),
             MappedAddress::IoReg => self.io.read(addr),
             MappedAddress::TimerReg => self.timers.read(addr),
-            // MappedAddress::ApuReg => todo!(),
+            MappedAddress::ApuReg => self.apu.reg_read(addr),
             MappedAddress::PpuReg => self.ppu.reg_read(addr),
             MappedAddress::BankReg => unreachable!(),
+            MappedAddress::Key1 => self.key1(),
             MappedAddress::HRam(addr) => self.hram[usize::from(addr)],
             MappedAddress::Interrupt => self.interrupt_reg.read(addr),
-            _ => return None,
+            MappedAddress::UnusedIo => DEFAULT_READ_VALUE,
         };
 
         Some(ReadInfo {
@@ -265,21 +510,31 @@ impl Mmu {
             MappedAddress::VRam(addr) => self.ppu.vram_write(addr, value),
             MappedAddress::ExternalRam(addr) => self.cartridge.write_ram(addr, value),
             MappedAddress::WRam(addr) => self.wram[usize::from(addr)] = value,
-            // MappedAddress::MirrorRam(_) => todo!(),
+            MappedAddress::MirrorRam(addr) => self.wram[usize::from(addr)] = value,
             MappedAddress::Oam(addr) => self.ppu.oam_write(addr, value),
             MappedAddress::IoReg => self.io.write(addr, value),
             MappedAddress::TimerReg => self.timers.write(addr, value, &mut self.interrupt_reg),
-            // MappedAddress::ApuReg => todo!(),
-            MappedAddress::PpuReg => self.ppu.reg_write(addr, value),
+            MappedAddress::ApuReg => self.apu.reg_write(addr, value),
+            MappedAddress::PpuReg => {
+                self.ppu.reg_write(addr, value);
+                if addr == PPU_DMA {
+                    self.start_oam_dma(value);
+                }
+            }
             MappedAddress::BankReg => {
                 if value != 0 {
                     self.boot_mode = false;
                     boot_rom_disabled = true;
                 }
             }
+            MappedAddress::Key1 => {
+                if self.cgb_enabled {
+                    self.speed_switch_armed = value.bit(0);
+                }
+            }
             MappedAddress::HRam(addr) => self.hram[usize::from(addr)] = value,
             MappedAddress::Interrupt => self.interrupt_reg.write(addr, value),
-            _ => return Err(()),
+            MappedAddress::UnusedIo => {}
         };
         Ok(WriteInfo {
             mapped_addr,
@@ -287,10 +542,194 @@ impl Mmu {
         })
     }
 
+    /// Begin an OAM DMA transfer from `value * 0x100` to OAM
+    fn start_oam_dma(&mut self, value: u8) {
+        let source = u16::from(value) << 8;
+        tracing::debug!(target: "mmu", "OAM DMA transfer started from ${:04X}", source);
+        self.oam_dma = Some(OamDma {
+            source,
+            index: 0,
+            cycles: 0,
+        });
+    }
+
+    /// Advance an active OAM DMA transfer by the given number of cycles
+    fn tick_oam_dma(&mut self, cycles: TCycles) {
+        let Some(mut dma) = self.oam_dma.take() else {
+            return;
+        };
+
+        dma.cycles += cycles;
+        while dma.cycles >= OAM_DMA_CYCLES_PER_BYTE && dma.index < OAM_DMA_LENGTH {
+            dma.cycles -= OAM_DMA_CYCLES_PER_BYTE;
+            let value = self
+                .raw_read(dma.source.wrapping_add(dma.index))
+                .map_or(DEFAULT_READ_VALUE, |info| info.value);
+            self.ppu.oam_write(dma.index, value);
+            dma.index += 1;
+        }
+
+        if dma.index < OAM_DMA_LENGTH {
+            self.oam_dma = Some(dma);
+        } else {
+            tracing::debug!(target: "mmu", "OAM DMA transfer complete");
+        }
+    }
+
+    /// Determine if the CPU can access the given address.
+    ///
+    /// While an OAM DMA transfer is active, the CPU can only access high RAM.
+    fn cpu_accessible(&self, addr: u16) -> bool {
+        self.oam_dma.is_none() || (HRAM_START..=HRAM_END).contains(&addr)
+    }
+
+    /// Determine if the CPU can access the given address given the current PPU mode.
+    ///
+    /// VRAM is locked during mode 3, and OAM is locked during modes 2 and 3 (unless
+    /// `strict_vram_timing` is disabled).
+    fn ppu_accessible(&self, addr: u16) -> bool {
+        if !self.strict_vram_timing {
+            return true;
+        }
+        match addr {
+            VRAM_START..=VRAM_END => self.ppu.vram_accessible(),
+            OAM_START..=OAM_END => self.ppu.oam_accessible(),
+            _ => true,
+        }
+    }
+
+    /// Enable or disable blocking CPU access to VRAM and OAM while the PPU is using
+    /// them.  This is enabled by default.
+    pub fn set_strict_vram_timing(&mut self, strict: bool) {
+        self.strict_vram_timing = strict;
+    }
+
+    /// Enable or disable open bus emulation, where reading from an unmapped address
+    /// returns the last value transferred on the data bus.  This is disabled by
+    /// default, in which case such reads return 0xFF.
+    pub fn set_open_bus_emulation(&mut self, enabled: bool) {
+        self.open_bus_emulation = enabled;
+    }
+
+    /// Enable or disable the CGB registers supported by the emulator.  Currently, this
+    /// only maps KEY1 (0xFF4D), which allows switching to double speed mode.
+    ///
+    /// This is disabled by default, in which case KEY1 reads 0xFF and ignores writes.
+    pub fn set_cgb_enabled(&mut self, enabled: bool) {
+        self.cgb_enabled = enabled;
+    }
+
+    /// Returns `true` if the CPU is running in CGB double speed mode
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Value of the KEY1 register: bit 7 is the current speed and bit 0 is set if a
+    /// speed switch has been requested
+    fn key1(&self) -> u8 {
+        if !self.cgb_enabled {
+            return DEFAULT_READ_VALUE;
+        }
+        let mut value = 0x7E;
+        if self.double_speed {
+            value.set_bit(7);
+        }
+        if self.speed_switch_armed {
+            value.set_bit(0);
+        }
+        value
+    }
+
+    /// Convert CPU cycles to cycles of the PPU and APU, which keep running at the normal
+    /// speed in double speed mode
+    fn normal_speed_cycles(&mut self, cycles: TCycles) -> TCycles {
+        if !self.double_speed {
+            return cycles;
+        }
+        let cycles = cycles + TCycles::from(self.half_cycle);
+        self.half_cycle = cycles % 2 == 1;
+        cycles / 2
+    }
+
+    /// Value returned when reading from an unmapped address
+    fn unmapped_read_value(&self) -> u8 {
+        if self.open_bus_emulation {
+            self.open_bus.get()
+        } else {
+            DEFAULT_READ_VALUE
+        }
+    }
+
     pub fn screen(&self) -> Vec<Color> {
         self.ppu.screen()
     }
 
+    pub fn tile_atlas(&self) -> Vec<Color> {
+        self.ppu.tile_atlas()
+    }
+
+    pub fn pending_interrupts(&self) -> InterruptFlag {
+        self.interrupt_reg.pending_interrupts()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
+
+    pub fn set_rendering_enabled(&mut self, enabled: bool) {
+        self.ppu.set_rendering_enabled(enabled);
+    }
+
+    /// Return the header of the inserted cartridge
+    pub fn cartridge_header(&self) -> &cartridge::Header {
+        self.cartridge.header()
+    }
+
+    /// Return the current `(left, right)` output of the APU
+    pub fn audio_sample(&self) -> (f32, f32) {
+        self.apu.sample()
+    }
+
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.apu.drain_samples()
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: HashSet<u16>) {
+        self.watchpoints = watchpoints;
+    }
+
+    pub fn take_watchpoint_hit(&mut self) -> Option<(u16, u8)> {
+        self.watchpoint_hit.take()
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.cartridge.header().cartridge_type.has_battery()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        self.cartridge.rom()
+    }
+
+    pub fn current_rom_bank(&self) -> (usize, usize) {
+        self.cartridge.current_rom_bank()
+    }
+
+    pub fn current_ram_bank(&self) -> usize {
+        self.cartridge.current_ram_bank()
+    }
+
+    pub fn ram_bytes(&self) -> Option<&[u8]> {
+        self.cartridge.ram()
+    }
+
+    pub fn load_ram(&mut self, ram: &[u8]) -> Result<(), crate::SaveError> {
+        self.cartridge.load_ram(ram)
+    }
+
     pub fn button_pressed(&mut self, button: JoypadButton) {
         self.io.button_pressed(button);
     }
@@ -298,10 +737,56 @@ impl Mmu {
     pub fn button_released(&mut self, button: JoypadButton) {
         self.io.button_released(button);
     }
+
+    pub fn joypad(&self) -> JoypadState {
+        self.io.joypad()
+    }
+
+    pub fn set_joypad(&mut self, state: JoypadState) {
+        self.io.set_joypad(state);
+    }
+
+    /// Return all bytes sent through the serial port
+    pub fn serial_output(&self) -> impl ExactSizeIterator<Item = u8> + '_ {
+        self.io.sent_bytes()
+    }
+
+    /// Total number of bytes sent through the serial port
+    pub fn serial_byte_count(&self) -> u64 {
+        self.io.sent_byte_count()
+    }
+
+    pub fn set_serial_sink(&mut self, sink: Option<SerialSink>) {
+        self.io.set_serial_sink(sink);
+    }
+
+    pub fn set_link_connected(&mut self, connected: bool) {
+        self.io.set_link_connected(connected);
+    }
+
+    pub fn serial_outgoing(&self) -> Option<u8> {
+        self.io.serial_outgoing()
+    }
+
+    pub fn serial_exchange(&mut self, incoming: u8) -> u8 {
+        self.io.serial_exchange(incoming, &mut self.interrupt_reg)
+    }
+
+    pub fn set_suppress_opposite_directions(&mut self, suppress: bool) {
+        self.io.set_suppress_opposite_directions(suppress);
+    }
 }
 
 impl ReadWriteMemory for Mmu {
     fn read(&self, addr: u16) -> u8 {
+        if !self.cpu_accessible(addr) {
+            tracing::trace!(target: "mmu", "attempted to read from memory address ${addr:04X} during OAM DMA transfer");
+            return DEFAULT_READ_VALUE;
+        }
+        if !self.ppu_accessible(addr) {
+            tracing::trace!(target: "mmu", "attempted to read from memory address ${addr:04X} while locked by the PPU");
+            return DEFAULT_READ_VALUE;
+        }
         match self.raw_read(addr) {
             Some(info) => {
                 if tracing::enabled!(target: "mmu", tracing::Level::TRACE) {
@@ -312,16 +797,29 @@ impl ReadWriteMemory for Mmu {
                     };
                     tracing::trace!(target: "mmu", "read ${:02X} from memory address ${addr:04X} (mapped to {})", info.value, mapped_to);
                 }
+                self.open_bus.set(info.value);
                 info.value
             }
             None => {
                 tracing::error!(target: "mmu", "attempted to read from unmapped memory address ${:04X}", addr);
-                DEFAULT_READ_VALUE
+                self.unmapped_read_value()
             }
         }
     }
 
     fn write(&mut self, addr: u16, value: u8) {
+        if !self.cpu_accessible(addr) {
+            tracing::trace!(target: "mmu", "attempted to write ${value:02X} to memory address ${addr:04X} during OAM DMA transfer");
+            return;
+        }
+        if !self.ppu_accessible(addr) {
+            tracing::trace!(target: "mmu", "attempted to write ${value:02X} to memory address ${addr:04X} while locked by the PPU");
+            return;
+        }
+        if self.watchpoints.contains(&addr) {
+            self.watchpoint_hit.get_or_insert((addr, value));
+        }
+        self.open_bus.set(value);
         match self.raw_write(addr, value) {
             Ok(info) => {
                 tracing::trace!(target: "mmu", "wrote ${value:02X} to memory address ${addr:04X} (mapped to {:?})", info.mapped_addr);
@@ -338,9 +836,24 @@ impl ReadWriteMemory for Mmu {
 
 impl Tick for Mmu {
     fn tick(&mut self, cycles: TCycles) {
+        self.tick_oam_dma(cycles);
         self.io.tick(cycles, &mut self.interrupt_reg);
         self.timers.tick(cycles, &mut self.interrupt_reg);
+
+        let cycles = self.normal_speed_cycles(cycles);
+        self.cartridge.tick(cycles);
         self.ppu.tick(cycles, &mut self.interrupt_reg);
+        self.apu.tick(cycles);
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        if !self.cgb_enabled || !self.speed_switch_armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        tracing::debug!(target: "mmu", "switched to {} speed mode", if self.double_speed { "double" } else { "normal" });
+        true
     }
 }
 
@@ -359,6 +872,65 @@ impl PollState for Mmu {
         }
         self.cartridge.poll_state(state);
         self.io.poll_state(state);
+        self.ppu.poll_state(state);
+    }
+}
+
+impl Snapshot for Mmu {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bool(self.boot_mode);
+        writer.write_bytes(&self.hram);
+        writer.write_bytes(&self.wram);
+        match &self.oam_dma {
+            Some(dma) => {
+                writer.write_bool(true);
+                writer.write_u16(dma.source);
+                writer.write_u16(dma.index);
+                writer.write_i64(dma.cycles);
+            }
+            None => writer.write_bool(false),
+        }
+        writer.write_bool(self.double_speed);
+        writer.write_bool(self.speed_switch_armed);
+        writer.write_bool(self.half_cycle);
+        self.io.save_snapshot(writer);
+        self.ppu.save_snapshot(writer);
+        self.apu.save_snapshot(writer);
+        self.interrupt_reg.save_snapshot(writer);
+        self.timers.save_snapshot(writer);
+        self.cartridge.save_snapshot(writer);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        let boot_mode = reader.read_bool()?;
+        if boot_mode && !self.has_boot_rom() {
+            return Err(SnapshotError::InvalidValue("boot mode"));
+        }
+        self.boot_mode = boot_mode;
+        reader.read_bytes_into(&mut self.hram)?;
+        reader.read_bytes_into(&mut self.wram)?;
+        self.oam_dma = if reader.read_bool()? {
+            let dma = OamDma {
+                source: reader.read_u16()?,
+                index: reader.read_u16()?,
+                cycles: reader.read_i64()?,
+            };
+            if dma.index >= OAM_DMA_LENGTH {
+                return Err(SnapshotError::InvalidValue("OAM DMA index"));
+            }
+            Some(dma)
+        } else {
+            None
+        };
+        self.double_speed = reader.read_bool()?;
+        self.speed_switch_armed = reader.read_bool()?;
+        self.half_cycle = reader.read_bool()?;
+        self.io.restore_snapshot(reader)?;
+        self.ppu.restore_snapshot(reader)?;
+        self.apu.restore_snapshot(reader)?;
+        self.interrupt_reg.restore_snapshot(reader)?;
+        self.timers.restore_snapshot(reader)?;
+        self.cartridge.restore_snapshot(reader)
     }
 }
 
@@ -375,3 +947,363 @@ impl InterruptManager for Mmu {
         self.interrupt_reg.priority_interrupt()
     }
 }
+
+impl JoypadInput for Mmu {
+    fn button_held(&self) -> bool {
+        self.io.button_held()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{components::interrupts::Interrupt, cpu::Cpu};
+
+    use super::*;
+
+    fn new_mmu() -> Mmu {
+        let rom = vec![0; 32 * 1024];
+        let boot_rom = vec![0; BOOT_ROM_SIZE];
+        Mmu::new(&rom, &boot_rom).unwrap()
+    }
+
+    #[test]
+    fn oam_dma_hram_routine() {
+        let mut mmu = new_mmu();
+        for i in 0..OAM_DMA_LENGTH {
+            mmu.write(WRAM_START + i, i as u8);
+        }
+
+        // Standard DMA wait loop executed from high RAM
+        let routine = [
+            0x3E, 0x28, // LD A, $28
+            0x3D, // DEC A
+            0x20, 0xFD, // JR NZ, -3
+            0x18, 0xFE, // JR -2
+        ];
+        for (i, byte) in routine.iter().enumerate() {
+            mmu.write(HRAM_START + i as u16, *byte);
+        }
+
+        mmu.write(PPU_DMA, 0xC0);
+        assert_eq!(mmu.read(WRAM_START), DEFAULT_READ_VALUE);
+        assert_eq!(mmu.read(HRAM_START), 0x3E);
+
+        let mut cpu = Cpu::new(mmu);
+        cpu.pc = HRAM_START;
+        cpu.step();
+        assert_eq!(cpu.a, 0x28);
+        assert_eq!(cpu.mmu.read(WRAM_START), DEFAULT_READ_VALUE);
+        for _ in 0..0x28 {
+            cpu.step();
+            cpu.step();
+        }
+        assert_eq!(cpu.a, 0);
+        assert_eq!(cpu.pc, HRAM_START + 5);
+
+        // The transfer has completed
+        assert_eq!(cpu.mmu.read(WRAM_START + 1), 1);
+        for i in 0..OAM_DMA_LENGTH {
+            assert_eq!(cpu.mmu.read(OAM_START + i), i as u8);
+        }
+    }
+
+    #[test]
+    fn ppu_mapping() {
+        let mut mmu = new_mmu();
+
+        mmu.write(VRAM_START, 0x12);
+        mmu.write(VRAM_END, 0x34);
+        assert_eq!(mmu.read(VRAM_START), 0x12);
+        assert_eq!(mmu.read(VRAM_END), 0x34);
+        assert_eq!(mmu.ppu.vram_read(0x0000), 0x12);
+
+        mmu.write(OAM_START, 0x56);
+        mmu.write(OAM_END, 0x78);
+        assert_eq!(mmu.read(OAM_START), 0x56);
+        assert_eq!(mmu.read(OAM_END), 0x78);
+        assert_eq!(mmu.ppu.oam_read(0x0000), 0x56);
+
+        mmu.write(PPU_SCX, 0x9A);
+        mmu.write(PPU_BGP, 0xE4);
+        assert_eq!(mmu.read(PPU_SCX), 0x9A);
+        assert_eq!(mmu.read(PPU_BGP), 0xE4);
+
+        // The PPU is driven by the MMU
+        mmu.write(PPU_LCDC, 0x80);
+        assert_eq!(mmu.read(PPU_LY), 0);
+        mmu.tick(456 * 3);
+        assert_eq!(mmu.read(PPU_LY), 3);
+    }
+
+    #[test]
+    fn timer_mapping() {
+        let mut mmu = new_mmu();
+        mmu.write(INTERRUPT_ENABLE_REG, 0xFF);
+
+        // Timer enabled, TIMA incremented every 16 cycles
+        mmu.write(TMA_REG, 0xF0);
+        mmu.write(TIMA_REG, 0xFE);
+        mmu.write(TAC_REG, 0b101);
+        assert_eq!(mmu.read(TAC_REG) & 0b111, 0b101);
+
+        mmu.tick(15);
+        assert_eq!(mmu.read(TIMA_REG), 0xFE);
+        mmu.tick(1);
+        assert_eq!(mmu.read(TIMA_REG), 0xFF);
+        assert!(mmu.priority_interrupt().is_none());
+
+        // Overflow reloads TIMA from TMA and requests a timer interrupt
+        mmu.tick(16);
+        assert_eq!(mmu.read(TIMA_REG), 0xF0);
+        assert!(matches!(mmu.priority_interrupt(), Some(Interrupt::Timer)));
+
+        // DIV is incremented every 256 cycles
+        assert_eq!(mmu.read(DIV_REG), 0);
+        mmu.tick(256 - 32);
+        assert_eq!(mmu.read(DIV_REG), 1);
+    }
+
+    #[test]
+    fn interrupt_mapping() {
+        let mut mmu = new_mmu();
+        mmu.write(INTERRUPT_FLAG, 0x04);
+        mmu.write(INTERRUPT_ENABLE_REG, 0x04);
+        assert_eq!(mmu.read(INTERRUPT_FLAG) & 0x1F, 0x04);
+        assert_eq!(mmu.read(INTERRUPT_ENABLE_REG) & 0x1F, 0x04);
+        assert!(matches!(mmu.priority_interrupt(), Some(Interrupt::Timer)));
+
+        mmu.write(INTERRUPT_ENABLE_REG, 0x00);
+        assert!(mmu.priority_interrupt().is_none());
+    }
+
+    #[test]
+    fn echo_ram() {
+        let mut mmu = new_mmu();
+
+        mmu.write(MIRROR_WRAM_START, 0x12);
+        assert_eq!(mmu.read(WRAM_START), 0x12);
+        mmu.write(WRAM_START + 0x1DFF, 0x34);
+        assert_eq!(mmu.read(MIRROR_WRAM_END), 0x34);
+
+        // 0xDE00..=0xDFFF is not mirrored
+        mmu.write(WRAM_END, 0x56);
+        assert_eq!(mmu.read(MIRROR_WRAM_END), 0x34);
+    }
+
+    #[test]
+    fn wram_hram_separate() {
+        let mut mmu = new_mmu();
+
+        mmu.write(WRAM_START, 0xAA);
+        mmu.write(HRAM_START, 0x55);
+        assert_eq!(mmu.read(WRAM_START), 0xAA);
+        assert_eq!(mmu.read(HRAM_START), 0x55);
+        assert_eq!(mmu.wram[0], 0xAA);
+        assert_eq!(mmu.hram[0], 0x55);
+    }
+
+    #[test]
+    fn poll_state_memory() {
+        let mut mmu = new_mmu();
+        mmu.write(WRAM_START, 0x12);
+        mmu.write(HRAM_START, 0x34);
+        mmu.tick(1000);
+
+        let mut state = crate::State::default();
+        mmu.poll_state(&mut state);
+        let mmu_state = state.mmu.unwrap();
+        assert_eq!(mmu_state.boot_rom.len(), BOOT_ROM_SIZE);
+        assert_eq!(mmu_state.wram.len(), WRAM_SIZE);
+        assert_eq!(mmu_state.wram[0], 0x12);
+        // The IE register is appended to the end of HRAM
+        assert_eq!(mmu_state.hram.len(), HRAM_SIZE + 1);
+        assert_eq!(mmu_state.hram[0], 0x34);
+    }
+
+    #[test]
+    fn apu_mapping() {
+        let mut mmu = new_mmu();
+
+        mmu.write(APU_STORAGE_START, 0x12);
+        mmu.write(APU_STORAGE_END, 0x34);
+        assert_eq!(mmu.read(APU_STORAGE_START), 0x12);
+        assert_eq!(mmu.read(APU_STORAGE_END), 0x34);
+
+        mmu.write(APU_SOUND_ON_OFF, 0x80);
+        assert_eq!(mmu.read(APU_SOUND_ON_OFF), 0xF0);
+        mmu.write(APU_CHANNEL2_LENGTH, 0x80);
+        assert_eq!(mmu.read(APU_CHANNEL2_LENGTH), 0xBF);
+        mmu.write(APU_SOUND_ON_OFF, 0x00);
+        assert_eq!(mmu.read(APU_SOUND_ON_OFF), 0x70);
+        assert_eq!(mmu.read(APU_CHANNEL2_LENGTH), 0x3F);
+    }
+
+    #[test]
+    fn poll_state_ppu() {
+        let mut mmu = new_mmu();
+        mmu.write(VRAM_START + 1, 0x12);
+        mmu.write(OAM_START + 2, 0x34);
+        mmu.write(PPU_SCX, 0x56);
+        mmu.write(PPU_LCDC, 0x80);
+        mmu.tick(100);
+
+        let mut state = crate::State::default();
+        mmu.poll_state(&mut state);
+        let ppu_state = state.ppu.unwrap();
+        assert_eq!(ppu_state.lcdc, 0x80);
+        assert_eq!(ppu_state.scx, 0x56);
+        assert_eq!(ppu_state.ly, 0);
+        assert_eq!(ppu_state.mode, 3);
+        assert_eq!(ppu_state.dot, 100);
+        assert_eq!(ppu_state.vram.len(), VRAM_SIZE);
+        assert_eq!(ppu_state.vram[1], 0x12);
+        assert_eq!(ppu_state.oam.len(), OAM_SIZE);
+        assert_eq!(ppu_state.oam[2], 0x34);
+    }
+
+    #[test]
+    fn strict_vram_timing() {
+        let mut mmu = new_mmu();
+        mmu.write(VRAM_START, 0x12);
+        mmu.write(OAM_START, 0x34);
+        mmu.write(PPU_LCDC, 0x80);
+
+        // Mode 2: OAM is locked
+        mmu.tick(1);
+        assert_eq!(mmu.read(VRAM_START), 0x12);
+        assert_eq!(mmu.read(OAM_START), DEFAULT_READ_VALUE);
+        mmu.write(OAM_START, 0x56);
+        assert_eq!(mmu.ppu.oam_read(0x0000), 0x34);
+
+        // Mode 3: VRAM and OAM are locked
+        mmu.tick(80);
+        assert_eq!(mmu.read(VRAM_START), DEFAULT_READ_VALUE);
+        assert_eq!(mmu.read(OAM_START), DEFAULT_READ_VALUE);
+        mmu.write(VRAM_START, 0x78);
+        assert_eq!(mmu.ppu.vram_read(0x0000), 0x12);
+
+        // The lock can be disabled
+        mmu.set_strict_vram_timing(false);
+        assert_eq!(mmu.read(VRAM_START), 0x12);
+        assert_eq!(mmu.read(OAM_START), 0x34);
+        mmu.set_strict_vram_timing(true);
+
+        // Mode 0: VRAM and OAM are accessible
+        mmu.tick(172);
+        assert_eq!(mmu.read(VRAM_START), 0x12);
+        assert_eq!(mmu.read(OAM_START), 0x34);
+    }
+
+    #[test]
+    fn boot_rom_verification() {
+        let mut boot_rom = vec![0; BOOT_ROM_SIZE];
+        boot_rom[BOOT_ROM_LOGO_START..BOOT_ROM_LOGO_START + NINTENDO_LOGO.len()]
+            .copy_from_slice(&NINTENDO_LOGO);
+        boot_rom[BOOT_ROM_DISABLE_START..BOOT_ROM_DISABLE_START + 2]
+            .copy_from_slice(&BOOT_ROM_DISABLE_INSTRUCTION);
+        let rom = vec![0; 32 * 1024];
+        assert!(Mmu::new_verified(&rom, &boot_rom).is_ok());
+
+        assert!(matches!(
+            verify_boot_rom(&boot_rom[..0x80]),
+            Err(crate::BootRomError::Size {
+                expected: BOOT_ROM_SIZE,
+                found: 0x80
+            })
+        ));
+
+        // Corrupted logo
+        let mut corrupted = boot_rom.clone();
+        corrupted[BOOT_ROM_LOGO_START + 5] ^= 0xFF;
+        assert!(matches!(
+            Mmu::new_verified(&rom, &corrupted),
+            Err(crate::BootError::BootRomError(
+                crate::BootRomError::InvalidContents(_)
+            ))
+        ));
+        // Unverified construction still accepts custom boot ROMs
+        assert!(Mmu::new(&rom, &corrupted).is_ok());
+
+        // Boot ROM never unmapped
+        let mut corrupted = boot_rom.clone();
+        corrupted[BOOT_ROM_DISABLE_START + 1] = 0x51;
+        assert!(matches!(
+            verify_boot_rom(&corrupted),
+            Err(crate::BootRomError::InvalidContents(_))
+        ));
+    }
+
+    #[test]
+    fn unused_io() {
+        let mut mmu = new_mmu();
+        assert_eq!(mmu.read(0xFF03), 0xFF);
+        mmu.write(0xFF03, 0x12);
+        assert_eq!(mmu.read(0xFF03), 0xFF);
+        for addr in [0xFF08, 0xFF0E, 0xFF4C, 0xFF7F] {
+            assert!(matches!(
+                MappedAddress::try_from(addr),
+                Ok(MappedAddress::UnusedIo)
+            ));
+            assert_eq!(mmu.read(addr), 0xFF);
+        }
+        // Mapped registers are unaffected
+        assert!(matches!(
+            MappedAddress::try_from(PPU_LCDC),
+            Ok(MappedAddress::PpuReg)
+        ));
+        assert!(MappedAddress::try_from(0xFEA0).is_err());
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut mmu = new_mmu();
+        mmu.write(WRAM_START, 0x42);
+        assert_eq!(mmu.read(0xFEA0), DEFAULT_READ_VALUE);
+
+        mmu.set_open_bus_emulation(true);
+        mmu.write(WRAM_START, 0x42);
+        assert_eq!(mmu.read(0xFEA0), 0x42);
+        mmu.write(HRAM_START, 0x17);
+        assert_eq!(mmu.read(0xFEFF), 0x17);
+        // Reads also update the bus
+        assert_eq!(mmu.read(WRAM_START), 0x42);
+        assert_eq!(mmu.read(0xFEA0), 0x42);
+
+        mmu.set_open_bus_emulation(false);
+        assert_eq!(mmu.read(0xFEA0), DEFAULT_READ_VALUE);
+    }
+
+    #[test]
+    fn key1() {
+        let mut mmu = new_mmu();
+        // KEY1 is unused on the DMG
+        mmu.write(KEY1_REG, 0x01);
+        assert_eq!(mmu.read(KEY1_REG), 0xFF);
+        assert!(!mmu.speed_switch());
+
+        mmu.set_cgb_enabled(true);
+        assert_eq!(mmu.read(KEY1_REG), 0x7E);
+        mmu.write(KEY1_REG, 0xFF);
+        assert_eq!(mmu.read(KEY1_REG), 0x7F);
+        assert!(mmu.speed_switch());
+        assert!(mmu.double_speed());
+        assert_eq!(mmu.read(KEY1_REG), 0xFE);
+        // A speed switch must be requested each time
+        assert!(!mmu.speed_switch());
+
+        // In double speed mode, the PPU receives half of the CPU cycles
+        mmu.write(PPU_LCDC, 0x80);
+        for _ in 0..456 {
+            mmu.tick(2);
+            mmu.tick(1);
+            mmu.tick(1);
+        }
+        assert_eq!(mmu.read(PPU_LY), 2);
+
+        mmu.write(KEY1_REG, 0x01);
+        assert!(mmu.speed_switch());
+        assert_eq!(mmu.read(KEY1_REG), 0x7E);
+        mmu.reset();
+        assert!(!mmu.double_speed());
+    }
+}
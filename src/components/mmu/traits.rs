@@ -8,16 +8,12 @@ pub trait ReadWriteMemory {
     /// Write `value` to the given memory address
     fn write(&mut self, addr: u16, value: u8);
 
-    /// Read two bytes from the given address
-    fn read_u16(&self, addr: u16) -> u16 {
-        u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
-    }
-
-    /// Write `value` to the given memory address
-    fn write_u16(&mut self, addr: u16, value: u16) {
-        let bytes = value.to_le_bytes();
-        self.write(addr, bytes[0]);
-        self.write(addr.wrapping_add(1), bytes[1]);
+    /// Read `len` bytes starting at the given address.  Addresses past 0xFFFF wrap
+    /// around to 0x0000.
+    fn read_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.read(start.wrapping_add(offset as u16)))
+            .collect()
     }
 }
 
@@ -26,6 +22,14 @@ pub trait Tick {
     /// Notify the system components that the CPU has executed the given number
     /// of cycles.
     fn tick(&mut self, cycles: TCycles);
+
+    /// Notify the system components that the CPU has executed STOP.
+    ///
+    /// Returns `true` if this performed a CGB speed switch, in which case the CPU
+    /// keeps running instead of entering stop mode.
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
 }
 
 /// The `InterruptManager` trait is used to manage the IE and IF interrupt registers
@@ -43,3 +47,29 @@ pub trait InterruptManager {
     /// the lowest priority).  If no interrupt requires handling, `None` is returned.
     fn priority_interrupt(&mut self) -> Option<Interrupt>;
 }
+
+/// The `JoypadInput` trait allows the CPU to observe the joypad (e.g., to leave stop
+/// mode)
+pub trait JoypadInput {
+    /// Returns `true` if a button in one of the groups selected by the P1 register is
+    /// being held down
+    fn button_held(&self) -> bool;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::components::mmu::dummy_mmu::DummyMmu;
+
+    use super::*;
+
+    #[test]
+    fn read_range() {
+        let mut mmu = DummyMmu::new(&[0x01, 0x02, 0x03]);
+        mmu.write(0xFFFE, 0xFE);
+        mmu.write(0xFFFF, 0xFF);
+
+        assert_eq!(mmu.read_range(0x0000, 3), [0x01, 0x02, 0x03]);
+        assert_eq!(mmu.read_range(0xFFFE, 4), [0xFE, 0xFF, 0x01, 0x02]);
+        assert!(mmu.read_range(0x1234, 0).is_empty());
+    }
+}
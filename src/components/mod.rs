@@ -1,3 +1,4 @@
+pub mod apu;
 pub mod interrupts;
 pub mod io;
 pub mod mmu;
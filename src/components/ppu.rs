@@ -4,7 +4,9 @@ use crate::{
         PPU_BGP, PPU_LCDC, PPU_LY, PPU_LYC, PPU_OBP0, PPU_OBP1, PPU_SCX, PPU_SCY, PPU_STAT, PPU_WX,
         PPU_WY,
     },
-    TCycles,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    state::PollState,
+    SnapshotError, TCycles,
 };
 
 use super::{
@@ -18,11 +20,28 @@ pub const DISPLAY_HEIGHT: usize = 144;
 const TILE_SIZE: usize = 16;
 const TILE_MAP_WIDTH: usize = 32;
 const TILE_MAP_HEIGHT: usize = 32;
+/// VRAM offsets of the two tile maps
+const TILE_MAP_ADDRESSES: [usize; 2] = [0x1800, 0x1C00];
+/// Number of tiles stored in VRAM
+const TILE_COUNT: usize = 384;
+/// Number of tiles in each row of the tile data image
+const TILE_DATA_IMAGE_COLUMNS: usize = 16;
+/// Width (and height) in pixels of a rendered tile map
+pub const TILE_MAP_IMAGE_SIZE: usize = TILE_MAP_WIDTH * 8;
+/// Width in pixels of the rendered tile data
+pub const TILE_DATA_IMAGE_WIDTH: usize = TILE_DATA_IMAGE_COLUMNS * 8;
+/// Height in pixels of the rendered tile data
+pub const TILE_DATA_IMAGE_HEIGHT: usize = TILE_COUNT / TILE_DATA_IMAGE_COLUMNS * 8;
 const DOTS_PER_SCANLINE: usize = 456;
-const SCANLINES_PER_FRAME: u8 = 153;
+const SCANLINES_PER_FRAME: u8 = 154;
 const MODE2_TOTAL_DOTS: usize = 80;
 const MODE3_TOTAL_DOTS: usize = 172;
 const MODE0_TOTAL_DOTS: usize = 204;
+/// Number of T-cycles required to draw a full frame
+pub const CYCLES_PER_FRAME: TCycles = (DOTS_PER_SCANLINE * SCANLINES_PER_FRAME as usize) as TCycles;
+/// OAM entry size (in bytes)
+const OAM_ENTRY_SIZE: usize = 4;
+const MAX_OBJS_PER_SCANLINE: usize = 10;
 
 #[derive(Debug)]
 pub struct Ppu {
@@ -41,6 +60,17 @@ pub struct Ppu {
     vram: [u8; VRAM_SIZE],
     oam: [u8; OAM_SIZE],
     current_scanline_dot: usize,
+    /// State of the internal STAT interrupt line (the OR of all selected STAT
+    /// interrupt sources)
+    stat_line: bool,
+    /// Number of frames completed (i.e., the number of times VBlank was entered)
+    frame_count: u64,
+    /// Screen contents, in row-major order.  Each scanline is rendered at the end
+    /// of its pixel transfer mode.
+    framebuffer: [Color; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    /// If `false`, scanlines are not rendered into the framebuffer (e.g., while
+    /// skipping frames)
+    rendering_enabled: bool,
 }
 
 impl Ppu {
@@ -61,6 +91,10 @@ impl Ppu {
             vram: [0; VRAM_SIZE],
             oam: [0; OAM_SIZE],
             current_scanline_dot: 0,
+            stat_line: false,
+            frame_count: 0,
+            framebuffer: [Color::default(); DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            rendering_enabled: true,
         }
     }
 
@@ -100,14 +134,38 @@ impl Ppu {
 
     pub fn reg_write(&mut self, addr: u16, value: u8) {
         match addr {
-            PPU_LCDC => self.lcdc = value.into(),
-            PPU_STAT => self.stat = value.into(),
+            PPU_LCDC => {
+                let lcd_enable = self.lcdc.lcd_enable;
+                self.lcdc = value.into();
+                if lcd_enable && !self.lcdc.lcd_enable {
+                    // Turning off the LCD resets LY and the current mode
+                    self.ly = 0;
+                    self.current_scanline_dot = 0;
+                    self.stat.mode_flag = ModeFlag::HBlank;
+                    self.stat_line = false;
+                } else if !lcd_enable && self.lcdc.lcd_enable {
+                    // Turning the LCD back on restarts the frame from LY = 0
+                    self.compare_lyc();
+                }
+            }
+            PPU_STAT => {
+                // The LYC = LY flag and the mode flag are read only
+                let stat = Stat::from(value);
+                self.stat = Stat {
+                    lyc_flag: self.stat.lyc_flag,
+                    mode_flag: self.stat.mode_flag,
+                    ..stat
+                };
+            }
             PPU_SCY => self.scy = value,
             PPU_SCX => self.scx = value,
             PPU_LY => {
                 tracing::error!(target: "ppu", "cannot write to LY register");
             }
-            PPU_LYC => self.lyc = value,
+            PPU_LYC => {
+                self.lyc = value;
+                self.compare_lyc();
+            }
             PPU_DMA => self.dma = value,
             PPU_BGP => self.bgp = value.into(),
             PPU_OBP0 => self.obp0 = value.into(),
@@ -118,105 +176,157 @@ impl Ppu {
         }
     }
 
-    pub fn tick<T: InterruptManager>(&mut self, mut cycles: TCycles, interrupt_manager: &mut T) {
-        while cycles > 0 {
-            // Update LY and current dot location on the scanline
-            self.current_scanline_dot += 1;
-            if self.current_scanline_dot == DOTS_PER_SCANLINE {
-                self.ly += 1;
-                self.current_scanline_dot = 0;
-            }
-            if self.ly > SCANLINES_PER_FRAME {
-                self.ly = 0;
-            }
+    pub fn tick<T: InterruptManager>(&mut self, cycles: TCycles, interrupt_manager: &mut T) {
+        if !self.lcdc.lcd_enable {
+            return;
+        }
+        for _ in 0..cycles {
+            self.tick_dot(interrupt_manager);
+        }
+    }
 
-            // Set mode and check for STAT interrupt (except for LYC = LY interrupt source)
-            if self.ly >= DISPLAY_HEIGHT as u8 {
-                if self.stat.vblank_interrupt_source && self.stat.mode_flag != ModeFlag::VBlank {
-                    interrupt_manager.if_set(Interrupt::LcdStat);
-                }
-                self.stat.mode_flag = ModeFlag::VBlank;
-            } else if self.current_scanline_dot <= MODE2_TOTAL_DOTS {
-                if self.stat.oam_interrupt_source && self.stat.mode_flag != ModeFlag::SearchingOam {
-                    interrupt_manager.if_set(Interrupt::LcdStat);
-                }
-                self.stat.mode_flag = ModeFlag::SearchingOam;
-            } else if self.current_scanline_dot <= MODE2_TOTAL_DOTS + MODE3_TOTAL_DOTS {
-                self.stat.mode_flag = ModeFlag::TransferringData;
-            } else {
-                if self.stat.hblank_interrupt_source && self.stat.mode_flag != ModeFlag::HBlank {
-                    interrupt_manager.if_set(Interrupt::LcdStat);
-                }
-                self.stat.mode_flag = ModeFlag::HBlank;
-            }
+    /// Advance the PPU by a single dot
+    fn tick_dot<T: InterruptManager>(&mut self, interrupt_manager: &mut T) {
+        // Update LY and current dot location on the scanline
+        self.current_scanline_dot += 1;
+        if self.current_scanline_dot == DOTS_PER_SCANLINE {
+            self.current_scanline_dot = 0;
+            self.ly = (self.ly + 1) % SCANLINES_PER_FRAME;
+            self.compare_lyc();
 
-            // Check LYC and LYC = LY STAT interrupt source
-            if self.ly == self.lyc {
-                self.stat.lyc_flag = true;
-                if self.stat.lyc_interrupt_source && self.current_scanline_dot == 0 {
-                    interrupt_manager.if_set(Interrupt::LcdStat);
-                }
-            } else {
-                self.stat.lyc_flag = false;
-            }
-
-            // Check for VBLANK interrupt
-            if self.ly == DISPLAY_HEIGHT as u8 && self.current_scanline_dot == 0 {
+            if self.ly == DISPLAY_HEIGHT as u8 {
                 interrupt_manager.if_set(Interrupt::VBlank);
+                self.frame_count += 1;
             }
+        }
 
-            cycles -= 1;
+        let mode = if self.ly >= DISPLAY_HEIGHT as u8 {
+            ModeFlag::VBlank
+        } else if self.current_scanline_dot < MODE2_TOTAL_DOTS {
+            ModeFlag::SearchingOam
+        } else if self.current_scanline_dot < MODE2_TOTAL_DOTS + MODE3_TOTAL_DOTS {
+            ModeFlag::TransferringData
+        } else {
+            ModeFlag::HBlank
+        };
+        if self.rendering_enabled
+            && self.stat.mode_flag == ModeFlag::TransferringData
+            && mode == ModeFlag::HBlank
+        {
+            self.render_scanline();
         }
+        self.stat.mode_flag = mode;
+        self.update_stat_line(interrupt_manager);
     }
 
-    pub fn screen(&self) -> Vec<Color> {
-        let mut screen = Vec::with_capacity(DISPLAY_HEIGHT * DISPLAY_WIDTH);
+    /// Compare LY and LYC, updating the LYC = LY flag
+    fn compare_lyc(&mut self) {
+        self.stat.lyc_flag = self.ly == self.lyc;
+    }
 
-        for y in 0..DISPLAY_HEIGHT as u8 {
-            for x in 0..DISPLAY_WIDTH as u8 {
-                screen
-                    .push(self.bg_pixel_color(self.scx.wrapping_add(x), self.scy.wrapping_add(y)));
-            }
+    /// Update the STAT interrupt line, requesting a STAT interrupt on its rising edge.
+    ///
+    /// Since the line is the OR of all selected interrupt sources, a source becoming
+    /// active while another source is already active does not request another
+    /// interrupt (i.e., STAT blocking).
+    fn update_stat_line<T: InterruptManager>(&mut self, interrupt_manager: &mut T) {
+        let stat_line = (self.stat.lyc_interrupt_source && self.stat.lyc_flag)
+            || match self.stat.mode_flag {
+                ModeFlag::HBlank => self.stat.hblank_interrupt_source,
+                ModeFlag::VBlank => self.stat.vblank_interrupt_source,
+                ModeFlag::SearchingOam => self.stat.oam_interrupt_source,
+                ModeFlag::TransferringData => false,
+            };
+        if stat_line && !self.stat_line {
+            interrupt_manager.if_set(Interrupt::LcdStat);
         }
+        self.stat_line = stat_line;
+    }
 
-        screen
+    /// Return the number of frames completed since the PPU was created
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
     }
 
-    fn bg_pixel_color(&self, x: u8, y: u8) -> Color {
-        if self.lcdc.lcd_enable {
-            let x_offset = x % 8;
-            let y_offset = y % 8;
-            let x = usize::from(x / 8);
-            let y = usize::from(y / 8);
-            let map_addr = if self.lcdc.bg_tile_map_area {
-                0x1C00 + (y * TILE_MAP_WIDTH) + x
-            } else {
-                0x1800 + (y * TILE_MAP_WIDTH) + x
-            };
+    /// Enable or disable rendering scanlines into the framebuffer.  The PPU timing
+    /// (LY, modes and interrupts) is unaffected.
+    pub fn set_rendering_enabled(&mut self, enabled: bool) {
+        self.rendering_enabled = enabled;
+    }
 
-            let tile_index = self.vram[map_addr];
-            self.bg_tile_pixel_color(tile_index, x_offset, y_offset)
+    /// Returns `true` if the CPU can access VRAM (i.e., the PPU is not transferring
+    /// pixels to the LCD)
+    pub fn vram_accessible(&self) -> bool {
+        self.stat.mode_flag != ModeFlag::TransferringData
+    }
+
+    /// Returns `true` if the CPU can access OAM (i.e., the PPU is not searching OAM or
+    /// transferring pixels to the LCD)
+    pub fn oam_accessible(&self) -> bool {
+        matches!(self.stat.mode_flag, ModeFlag::HBlank | ModeFlag::VBlank)
+    }
+
+    /// Return the most recently rendered screen, in row-major order.  While the LCD is
+    /// off, the screen is blank (i.e., uniformly white).
+    pub fn screen(&self) -> Vec<Color> {
+        if !self.lcdc.lcd_enable {
+            return vec![Color::White; DISPLAY_HEIGHT * DISPLAY_WIDTH];
+        }
+        self.framebuffer.to_vec()
+    }
+
+    /// Render the current scanline (LY) into the framebuffer, using the register
+    /// values as they are at this moment
+    fn render_scanline(&mut self) {
+        let y = self.ly;
+        let sprites = self.scanline_sprites(y);
+        let row = usize::from(y) * DISPLAY_WIDTH;
+        for x in 0..DISPLAY_WIDTH as u8 {
+            let bg_color_index =
+                self.bg_color_index(self.scx.wrapping_add(x), self.scy.wrapping_add(y));
+            let color = self
+                .obj_pixel_color(&sprites, x, y, bg_color_index)
+                .unwrap_or(self.bgp.colors[usize::from(bg_color_index)]);
+            self.framebuffer[row + usize::from(x)] = color;
+        }
+    }
+
+    fn bg_color_index(&self, x: u8, y: u8) -> u8 {
+        let x_offset = x % 8;
+        let y_offset = y % 8;
+        let x = usize::from(x / 8);
+        let y = usize::from(y / 8);
+        let map_addr =
+            TILE_MAP_ADDRESSES[usize::from(self.lcdc.bg_tile_map_area)] + (y * TILE_MAP_WIDTH) + x;
+
+        let tile_index = self.vram[map_addr];
+        self.tile_color_index(self.tile_address(tile_index), x_offset, y_offset)
+    }
+
+    /// Return the VRAM offset of the BG/window tile with the given index.
+    ///
+    /// In 0x8000 addressing mode, `index` is unsigned and tiles are located in
+    /// 0x8000..=0x8FFF.  In 0x8800 addressing mode, `index` is signed and tiles are
+    /// located in 0x8800..=0x97FF (with index 0 located at 0x9000).
+    fn tile_address(&self, index: u8) -> usize {
+        if self.lcdc.bg_window_tile_data_area {
+            usize::from(index) * TILE_SIZE
         } else {
-            Color::Black
+            (0x1000 + isize::from(index as i8) * TILE_SIZE as isize) as usize
         }
     }
 
-    fn bg_tile_pixel_color(&self, tile_index: u8, x_offset: u8, y_offset: u8) -> Color {
+    /// Return the color index (0 to 3) of a pixel in the tile located at the VRAM
+    /// offset `tile_addr`.  A `y_offset` of 8 or more selects a row in the tiles
+    /// that follow (as used by 8x16 objects).
+    fn tile_color_index(&self, tile_addr: usize, x_offset: u8, y_offset: u8) -> u8 {
         assert!(x_offset < 8);
-        assert!(y_offset < 8);
 
         let x_offset = usize::from(x_offset);
-        let y_offset = usize::from(y_offset);
+        let row_addr = tile_addr + usize::from(y_offset) * 2;
 
-        let tile_addr = if self.lcdc.bg_window_tile_data_area {
-            usize::from(tile_index) * TILE_SIZE
-        } else {
-            0x0800 + usize::from(tile_index) * TILE_SIZE
-        };
-
-        let tile = &self.vram[tile_addr..tile_addr + TILE_SIZE];
-        let lsb = tile[y_offset * 2].bit(7 - x_offset);
-        let msb = tile[y_offset * 2 + 1].bit(7 - x_offset);
+        let lsb = self.vram[row_addr].bit(7 - x_offset);
+        let msb = self.vram[row_addr + 1].bit(7 - x_offset);
         let mut val = 0;
         if msb {
             val.set_bit(1);
@@ -224,8 +334,269 @@ impl Ppu {
         if lsb {
             val.set_bit(0);
         }
+        val
+    }
+
+    /// Render the given tile map (0: 0x9800, 1: 0x9C00) as a square image of
+    /// `TILE_MAP_IMAGE_SIZE` pixels, using the current BG/window tile data area and the
+    /// BG palette
+    fn tile_map_image(&self, map: usize) -> Vec<Color> {
+        let mut image = Vec::with_capacity(TILE_MAP_IMAGE_SIZE * TILE_MAP_IMAGE_SIZE);
+        for y in 0..TILE_MAP_HEIGHT * 8 {
+            for x in 0..TILE_MAP_WIDTH * 8 {
+                let map_addr = TILE_MAP_ADDRESSES[map] + (y / 8) * TILE_MAP_WIDTH + x / 8;
+                let tile_addr = self.tile_address(self.vram[map_addr]);
+                let color_index = self.tile_color_index(tile_addr, (x % 8) as u8, (y % 8) as u8);
+                image.push(self.bgp.colors[usize::from(color_index)]);
+            }
+        }
+        image
+    }
+
+    /// Render all 384 tiles in VRAM (in the order they are stored) as a grid of 16 by
+    /// 24 tiles, i.e., an image of `TILE_DATA_IMAGE_WIDTH` by `TILE_DATA_IMAGE_HEIGHT`
+    /// pixels in row-major order, using the BG palette
+    pub fn tile_atlas(&self) -> Vec<Color> {
+        let mut image = Vec::with_capacity(TILE_DATA_IMAGE_WIDTH * TILE_DATA_IMAGE_HEIGHT);
+        for y in 0..TILE_DATA_IMAGE_HEIGHT {
+            for x in 0..TILE_DATA_IMAGE_WIDTH {
+                let tile = (y / 8) * TILE_DATA_IMAGE_COLUMNS + x / 8;
+                let color_index =
+                    self.tile_color_index(tile * TILE_SIZE, (x % 8) as u8, (y % 8) as u8);
+                image.push(self.bgp.colors[usize::from(color_index)]);
+            }
+        }
+        image
+    }
+
+    fn obj_height(&self) -> u8 {
+        if self.lcdc.obj_size {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Return the objects visible on scanline `ly`, ordered by drawing priority.
+    ///
+    /// At most `MAX_OBJS_PER_SCANLINE` objects are selected (in OAM order).  Among
+    /// overlapping objects, the one with the smaller X coordinate is drawn on top,
+    /// with ties broken by OAM order.
+    fn scanline_sprites(&self, ly: u8) -> Vec<Sprite> {
+        if !self.lcdc.obj_enable {
+            return Vec::new();
+        }
+
+        let height = self.obj_height();
+        let line = u16::from(ly) + 16;
+        let mut sprites: Vec<Sprite> = self
+            .oam
+            .chunks_exact(OAM_ENTRY_SIZE)
+            .map(Sprite::from)
+            .filter(|sprite| {
+                let y = u16::from(sprite.y);
+                line >= y && line < y + u16::from(height)
+            })
+            .take(MAX_OBJS_PER_SCANLINE)
+            .collect();
+        // The sort is stable, so OAM order is kept for objects with the same X
+        sprites.sort_by_key(|sprite| sprite.x);
+        sprites
+    }
+
+    /// Return the color of the object pixel at (`x`, `y`), or `None` if the BG
+    /// (with color index `bg_color_index`) should be displayed instead.
+    fn obj_pixel_color(
+        &self,
+        sprites: &[Sprite],
+        x: u8,
+        y: u8,
+        bg_color_index: u8,
+    ) -> Option<Color> {
+        let height = self.obj_height();
+        let column = u16::from(x) + 8;
+        let line = u16::from(y) + 16;
+
+        for sprite in sprites {
+            let sprite_x = u16::from(sprite.x);
+            if column < sprite_x || column >= sprite_x + 8 {
+                continue;
+            }
+
+            let mut x_offset = (column - sprite_x) as u8;
+            let mut y_offset = (line - u16::from(sprite.y)) as u8;
+            if sprite.x_flip() {
+                x_offset = 7 - x_offset;
+            }
+            if sprite.y_flip() {
+                y_offset = height - 1 - y_offset;
+            }
+
+            // Objects always use 0x8000 addressing mode.  In 8x16 mode, bit 0 of the
+            // tile index is ignored: the top tile is even and the bottom tile is odd.
+            let mut tile_index = sprite.tile_index;
+            if self.lcdc.obj_size {
+                tile_index &= 0xFE;
+                if y_offset >= 8 {
+                    tile_index |= 0x01;
+                    y_offset -= 8;
+                }
+            }
+            let tile_addr = usize::from(tile_index) * TILE_SIZE;
+            let color_index = self.tile_color_index(tile_addr, x_offset, y_offset);
+            if color_index == 0 {
+                // Color index 0 is transparent, so lower priority objects may show
+                continue;
+            }
+            if sprite.bg_priority() && bg_color_index != 0 {
+                return None;
+            }
+
+            let palette = if sprite.palette() {
+                &self.obp1
+            } else {
+                &self.obp0
+            };
+            return Some(palette.colors[usize::from(color_index)]);
+        }
+
+        None
+    }
+}
+
+impl PollState for Ppu {
+    fn poll_state(&self, state: &mut crate::State) {
+        if state.ppu.is_none() {
+            state.ppu = Some(Default::default());
+        }
+        if let Some(ppu_state) = &mut state.ppu {
+            ppu_state.lcdc = self.lcdc.into();
+            ppu_state.stat = self.stat.into();
+            ppu_state.scy = self.scy;
+            ppu_state.scx = self.scx;
+            ppu_state.ly = self.ly;
+            ppu_state.lyc = self.lyc;
+            ppu_state.dma = self.dma;
+            ppu_state.bgp = self.bgp.into();
+            ppu_state.obp0 = self.obp0.into();
+            ppu_state.obp1 = self.obp1.into();
+            ppu_state.wy = self.wy;
+            ppu_state.wx = self.wx;
+            ppu_state.mode = self.stat.mode_flag.into();
+            ppu_state.dot = self.current_scanline_dot;
+            ppu_state.frame_count = self.frame_count;
+            ppu_state.vram = self.vram.into();
+            ppu_state.oam = self.oam.into();
+            if state.tile_images_enabled {
+                ppu_state.tile_maps = [self.tile_map_image(0), self.tile_map_image(1)];
+                ppu_state.tile_data = self.tile_atlas();
+            }
+        }
+    }
+}
 
-        self.bgp.colors[usize::from(val)]
+impl Snapshot for Ppu {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u8(self.lcdc.into());
+        writer.write_u8(self.stat.into());
+        writer.write_u8(self.scy);
+        writer.write_u8(self.scx);
+        writer.write_u8(self.ly);
+        writer.write_u8(self.lyc);
+        writer.write_u8(self.dma);
+        writer.write_u8(self.bgp.into());
+        writer.write_u8(self.obp0.into());
+        writer.write_u8(self.obp1.into());
+        writer.write_u8(self.wy);
+        writer.write_u8(self.wx);
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.oam);
+        writer.write_u64(self.current_scanline_dot as u64);
+        writer.write_bool(self.stat_line);
+        writer.write_u64(self.frame_count);
+        let framebuffer: Vec<u8> = self
+            .framebuffer
+            .iter()
+            .map(|color| (*color).into())
+            .collect();
+        writer.write_bytes(&framebuffer);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.lcdc = reader.read_u8()?.into();
+        self.stat = reader.read_u8()?.into();
+        self.scy = reader.read_u8()?;
+        self.scx = reader.read_u8()?;
+        self.ly = reader.read_u8()?;
+        self.lyc = reader.read_u8()?;
+        self.dma = reader.read_u8()?;
+        self.bgp = reader.read_u8()?.into();
+        self.obp0 = reader.read_u8()?.into();
+        self.obp1 = reader.read_u8()?.into();
+        self.wy = reader.read_u8()?;
+        self.wx = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.vram)?;
+        reader.read_bytes_into(&mut self.oam)?;
+        self.current_scanline_dot = usize::try_from(reader.read_u64()?)
+            .ok()
+            .filter(|dot| *dot < DOTS_PER_SCANLINE)
+            .ok_or(SnapshotError::InvalidValue("scanline dot"))?;
+        self.stat_line = reader.read_bool()?;
+        self.frame_count = reader.read_u64()?;
+        if self.ly >= SCANLINES_PER_FRAME {
+            return Err(SnapshotError::InvalidValue("LY"));
+        }
+        let mut framebuffer = [0; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        reader.read_bytes_into(&mut framebuffer)?;
+        if framebuffer.iter().any(|value| *value > 3) {
+            return Err(SnapshotError::InvalidValue("framebuffer color"));
+        }
+        for (color, value) in self.framebuffer.iter_mut().zip(framebuffer) {
+            *color = value.into();
+        }
+        Ok(())
+    }
+}
+
+/// An object attribute entry in OAM
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    /// Y position plus 16
+    y: u8,
+    /// X position plus 8
+    x: u8,
+    tile_index: u8,
+    flags: u8,
+}
+
+impl Sprite {
+    /// BG and window over OBJ (false = no, true = BG and window colors 1-3 over the OBJ)
+    fn bg_priority(&self) -> bool {
+        self.flags.bit(7)
+    }
+
+    fn y_flip(&self) -> bool {
+        self.flags.bit(6)
+    }
+
+    fn x_flip(&self) -> bool {
+        self.flags.bit(5)
+    }
+
+    /// Palette number (false = OBP0, true = OBP1)
+    fn palette(&self) -> bool {
+        self.flags.bit(4)
+    }
+}
+
+impl From<&[u8]> for Sprite {
+    fn from(entry: &[u8]) -> Self {
+        Self {
+            y: entry[0],
+            x: entry[1],
+            tile_index: entry[2],
+            flags: entry[3],
+        }
     }
 }
 
@@ -364,7 +735,8 @@ impl From<u8> for Stat {
 
 impl From<Stat> for u8 {
     fn from(stat: Stat) -> Self {
-        let mut value: u8 = stat.mode_flag.into();
+        // Bit 7 is unused and always reads as 1
+        let mut value: u8 = 0b1000_0000 | u8::from(stat.mode_flag);
         if stat.lyc_flag {
             value.set_bit(2);
         }
@@ -384,7 +756,8 @@ impl From<Stat> for u8 {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Color {
     White,
     LightGray,
@@ -421,6 +794,66 @@ impl From<Color> for u8 {
     }
 }
 
+impl Color {
+    /// Convert the color to an RGBA value using the given palette
+    pub fn to_rgba(self, palette: &Palette) -> [u8; 4] {
+        palette.colors[usize::from(u8::from(self))]
+    }
+}
+
+/// RGBA values used to display each shade of a DMG screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// RGBA values for white, light gray, dark gray and black (in that order)
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Palette {
+    /// Neutral grayscale
+    pub const GRAYSCALE: Palette = Palette {
+        colors: [
+            [0xFF, 0xFF, 0xFF, 0xFF],
+            [0xB2, 0xB2, 0xB2, 0xFF],
+            [0x66, 0x66, 0x66, 0xFF],
+            [0x00, 0x00, 0x00, 0xFF],
+        ],
+    };
+
+    /// Green tint of the original DMG screen
+    pub const DMG_GREEN: Palette = Palette {
+        colors: [
+            [0x9B, 0xBC, 0x0F, 0xFF],
+            [0x8B, 0xAC, 0x0F, 0xFF],
+            [0x30, 0x62, 0x30, 0xFF],
+            [0x0F, 0x38, 0x0F, 0xFF],
+        ],
+    };
+
+    /// Gray tint of the Game Boy Pocket screen
+    pub const POCKET_GRAY: Palette = Palette {
+        colors: [
+            [0xC5, 0xCA, 0xA4, 0xFF],
+            [0x8C, 0x92, 0x6B, 0xFF],
+            [0x4A, 0x51, 0x38, 0xFF],
+            [0x18, 0x18, 0x18, 0xFF],
+        ],
+    };
+
+    /// Convert the colors to a buffer of RGBA8888 pixels (four bytes per color)
+    pub fn rgba(&self, colors: &[Color]) -> Vec<u8> {
+        colors
+            .iter()
+            .flat_map(|color| color.to_rgba(self))
+            .collect()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::GRAYSCALE
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 struct ColorPalette {
     colors: [Color; 4],
@@ -445,3 +878,501 @@ impl From<ColorPalette> for u8 {
             + (u8::from(palette.colors[3]) << 6)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::components::{
+        interrupts::InterruptRegisters,
+        mmu::{INTERRUPT_ENABLE_REG, INTERRUPT_FLAG},
+    };
+
+    use super::*;
+
+    fn lcd_on_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.reg_write(PPU_LCDC, 0x80);
+        ppu
+    }
+
+    #[test]
+    fn mode_timing() {
+        let mut ppu = lcd_on_ppu();
+        let mut interrupts = InterruptRegisters::new();
+
+        ppu.tick(1, &mut interrupts);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::SearchingOam);
+        ppu.tick(MODE2_TOTAL_DOTS as TCycles - 1, &mut interrupts);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::TransferringData);
+        ppu.tick(MODE3_TOTAL_DOTS as TCycles, &mut interrupts);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::HBlank);
+        ppu.tick(MODE0_TOTAL_DOTS as TCycles - 1, &mut interrupts);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::HBlank);
+        assert_eq!(ppu.ly, 0);
+        ppu.tick(1, &mut interrupts);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::SearchingOam);
+        assert_eq!(ppu.ly, 1);
+
+        // Lines 144 through 153 are spent in VBlank
+        ppu.tick(
+            (DOTS_PER_SCANLINE * (DISPLAY_HEIGHT - 1)) as TCycles,
+            &mut interrupts,
+        );
+        assert_eq!(ppu.ly, 144);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::VBlank);
+        ppu.tick((DOTS_PER_SCANLINE * 9) as TCycles, &mut interrupts);
+        assert_eq!(ppu.ly, 153);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::VBlank);
+        ppu.tick(DOTS_PER_SCANLINE as TCycles, &mut interrupts);
+        assert_eq!(ppu.ly, 0);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::SearchingOam);
+    }
+
+    #[test]
+    fn vblank_interrupt() {
+        let mut ppu = lcd_on_ppu();
+        let mut interrupts = InterruptRegisters::new();
+        interrupts.write(INTERRUPT_ENABLE_REG, 0xFF);
+
+        let mut vblank_lines = Vec::new();
+        for _ in 0..2 * DOTS_PER_SCANLINE * usize::from(SCANLINES_PER_FRAME) {
+            ppu.tick(1, &mut interrupts);
+            if let Some(Interrupt::VBlank) = interrupts.priority_interrupt() {
+                vblank_lines.push(ppu.ly);
+                interrupts.if_reset(Interrupt::VBlank);
+            }
+        }
+        assert_eq!(vblank_lines, vec![144, 144]);
+    }
+
+    /// Count the number of STAT interrupts requested during the first frame after the
+    /// LCD is turned on with the given STAT and LYC register values
+    fn stat_interrupts_per_frame(stat: u8, lyc: u8) -> usize {
+        let mut ppu = lcd_on_ppu();
+        ppu.reg_write(PPU_STAT, stat);
+        ppu.reg_write(PPU_LYC, lyc);
+        let mut interrupts = InterruptRegisters::new();
+        interrupts.write(INTERRUPT_ENABLE_REG, 0xFF);
+
+        let mut count = 0;
+        for _ in 1..DOTS_PER_SCANLINE * usize::from(SCANLINES_PER_FRAME) {
+            ppu.tick(1, &mut interrupts);
+            if interrupts.read(INTERRUPT_FLAG).bit(1) {
+                count += 1;
+                interrupts.if_reset(Interrupt::LcdStat);
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn stat_interrupt_sources() {
+        assert_eq!(stat_interrupts_per_frame(0b0000_0000, 10), 0);
+        assert_eq!(stat_interrupts_per_frame(0b0000_1000, 10), DISPLAY_HEIGHT);
+        assert_eq!(stat_interrupts_per_frame(0b0001_0000, 10), 1);
+        assert_eq!(stat_interrupts_per_frame(0b0010_0000, 10), DISPLAY_HEIGHT);
+        assert_eq!(stat_interrupts_per_frame(0b0100_0000, 10), 1);
+    }
+
+    #[test]
+    fn stat_interrupt_blocking() {
+        // Entering VBlank directly from HBlank does not produce a rising edge
+        assert_eq!(stat_interrupts_per_frame(0b0001_1000, 10), DISPLAY_HEIGHT);
+
+        // LY = LYC becomes true while the line is still high from the HBlank of line 9,
+        // and remains true throughout line 10, blocking its HBlank interrupt
+        assert_eq!(
+            stat_interrupts_per_frame(0b0100_1000, 10),
+            DISPLAY_HEIGHT - 1
+        );
+    }
+
+    #[test]
+    fn lyc_compare() {
+        let mut ppu = lcd_on_ppu();
+        let mut interrupts = InterruptRegisters::new();
+
+        ppu.reg_write(PPU_LYC, 2);
+        assert!(!ppu.stat.lyc_flag);
+        ppu.tick((DOTS_PER_SCANLINE * 2) as TCycles, &mut interrupts);
+        assert!(ppu.stat.lyc_flag);
+        ppu.tick(DOTS_PER_SCANLINE as TCycles, &mut interrupts);
+        assert!(!ppu.stat.lyc_flag);
+        ppu.reg_write(PPU_LYC, 3);
+        assert!(ppu.stat.lyc_flag);
+    }
+
+    #[test]
+    fn tile_address() {
+        let mut ppu = Ppu::new();
+
+        // 0x8000 addressing mode
+        ppu.lcdc.bg_window_tile_data_area = true;
+        assert_eq!(ppu.tile_address(0x00), 0x0000);
+        assert_eq!(ppu.tile_address(0x7F), 0x07F0);
+        assert_eq!(ppu.tile_address(0x80), 0x0800);
+        assert_eq!(ppu.tile_address(0xFF), 0x0FF0);
+
+        // 0x8800 addressing mode
+        ppu.lcdc.bg_window_tile_data_area = false;
+        assert_eq!(ppu.tile_address(0x00), 0x1000);
+        assert_eq!(ppu.tile_address(0x7F), 0x17F0);
+        assert_eq!(ppu.tile_address(0x80), 0x0800);
+        assert_eq!(ppu.tile_address(0xFF), 0x0FF0);
+    }
+
+    /// Return a PPU with the LCD, BG and objects enabled, using 0x8000 addressing mode
+    /// and an identity BG palette.  Tile 0 (and hence the whole BG) is blank.
+    fn sprite_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.reg_write(PPU_LCDC, 0b1001_0011);
+        ppu.reg_write(PPU_BGP, 0b1110_0100);
+        ppu.reg_write(PPU_OBP0, 0b1110_0100);
+        ppu.reg_write(PPU_OBP1, 0b0001_1011);
+        ppu
+    }
+
+    fn write_tile(ppu: &mut Ppu, index: u8, rows: &[(u8, u8)]) {
+        for (i, (lsb, msb)) in rows.iter().enumerate() {
+            let addr = usize::from(index) * TILE_SIZE + i * 2;
+            ppu.vram[addr] = *lsb;
+            ppu.vram[addr + 1] = *msb;
+        }
+    }
+
+    fn write_sprite(ppu: &mut Ppu, entry: usize, y: u8, x: u8, tile_index: u8, flags: u8) {
+        let addr = entry * OAM_ENTRY_SIZE;
+        ppu.oam[addr..addr + OAM_ENTRY_SIZE].copy_from_slice(&[y, x, tile_index, flags]);
+    }
+
+    /// Run the PPU for a full frame and return the rendered screen
+    fn render_frame(ppu: &mut Ppu) -> Vec<Color> {
+        ppu.tick(CYCLES_PER_FRAME, &mut InterruptRegisters::new());
+        ppu.screen()
+    }
+
+    fn pixel(screen: &[Color], x: usize, y: usize) -> Color {
+        screen[y * DISPLAY_WIDTH + x]
+    }
+
+    #[test]
+    fn sprite_rendering() {
+        let mut ppu = sprite_ppu();
+        // Tile 1 is solid color 1; tile 2 only has its top left pixel set to color 3
+        write_tile(&mut ppu, 1, &[(0xFF, 0x00); 8]);
+        write_tile(&mut ppu, 2, &[(0x80, 0x80)]);
+
+        write_sprite(&mut ppu, 0, 16, 8, 1, 0x00);
+        write_sprite(&mut ppu, 1, 16, 28, 1, 0x10);
+        write_sprite(&mut ppu, 2, 32, 8, 2, 0x00);
+        write_sprite(&mut ppu, 3, 32, 28, 2, 0x60);
+
+        let screen = render_frame(&mut ppu);
+        // OBP0 and OBP1
+        assert_eq!(pixel(&screen, 0, 0), Color::LightGray);
+        assert_eq!(pixel(&screen, 7, 7), Color::LightGray);
+        assert_eq!(pixel(&screen, 8, 0), Color::White);
+        assert_eq!(pixel(&screen, 20, 0), Color::DarkGray);
+        // Transparent pixels show the BG
+        assert_eq!(pixel(&screen, 0, 16), Color::Black);
+        assert_eq!(pixel(&screen, 1, 16), Color::White);
+        // X and Y flip
+        assert_eq!(pixel(&screen, 20, 16), Color::White);
+        assert_eq!(pixel(&screen, 27, 23), Color::Black);
+
+        // Disabling objects hides them
+        ppu.reg_write(PPU_LCDC, 0b1001_0001);
+        assert!(render_frame(&mut ppu)
+            .iter()
+            .all(|color| *color == Color::White));
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let mut ppu = sprite_ppu();
+        write_tile(&mut ppu, 1, &[(0xFF, 0x00); 8]);
+        write_tile(&mut ppu, 2, &[(0xFF, 0xFF); 8]);
+        write_tile(&mut ppu, 3, &[(0x00, 0xFF); 8]);
+
+        // The object with the smaller X is drawn on top, regardless of OAM order
+        write_sprite(&mut ppu, 0, 16, 12, 1, 0x00);
+        write_sprite(&mut ppu, 1, 16, 8, 2, 0x00);
+        // With equal X, the first object in OAM is drawn on top
+        write_sprite(&mut ppu, 2, 32, 8, 3, 0x00);
+        write_sprite(&mut ppu, 3, 32, 8, 1, 0x00);
+        // BG color 1-3 is drawn over objects with the BG priority flag
+        write_sprite(&mut ppu, 4, 48, 8, 2, 0x80);
+        write_sprite(&mut ppu, 5, 48, 40, 2, 0x80);
+        ppu.vram[0x1800 + 4 * TILE_MAP_WIDTH] = 1;
+
+        let screen = render_frame(&mut ppu);
+        assert_eq!(pixel(&screen, 4, 0), Color::Black);
+        assert_eq!(pixel(&screen, 8, 0), Color::LightGray);
+        assert_eq!(pixel(&screen, 0, 16), Color::DarkGray);
+        assert_eq!(pixel(&screen, 0, 32), Color::LightGray);
+        assert_eq!(pixel(&screen, 32, 32), Color::Black);
+    }
+
+    #[test]
+    fn sprites_per_scanline() {
+        let mut ppu = sprite_ppu();
+        write_tile(&mut ppu, 1, &[(0xFF, 0xFF); 8]);
+        for i in 0..=MAX_OBJS_PER_SCANLINE {
+            write_sprite(&mut ppu, i, 16, 8 + 10 * i as u8, 1, 0x00);
+        }
+
+        let screen = render_frame(&mut ppu);
+        for i in 0..MAX_OBJS_PER_SCANLINE {
+            assert_eq!(pixel(&screen, 10 * i, 0), Color::Black);
+        }
+        assert_eq!(pixel(&screen, 10 * MAX_OBJS_PER_SCANLINE, 0), Color::White);
+    }
+
+    #[test]
+    fn tall_sprites() {
+        let mut ppu = sprite_ppu();
+        write_tile(&mut ppu, 2, &[(0xFF, 0x00); 8]);
+        write_tile(&mut ppu, 3, &[(0x00, 0xFF); 8]);
+        write_sprite(&mut ppu, 0, 16, 8, 2, 0x00);
+        write_sprite(&mut ppu, 1, 16, 16, 2, 0x40);
+
+        // 8x8 objects only use a single tile
+        let screen = render_frame(&mut ppu);
+        assert_eq!(pixel(&screen, 0, 8), Color::White);
+
+        ppu.reg_write(PPU_LCDC, 0b1001_0111);
+        let screen = render_frame(&mut ppu);
+        assert_eq!(pixel(&screen, 0, 0), Color::LightGray);
+        assert_eq!(pixel(&screen, 0, 15), Color::DarkGray);
+        // Y flip swaps the top and bottom tiles
+        assert_eq!(pixel(&screen, 8, 0), Color::DarkGray);
+        assert_eq!(pixel(&screen, 8, 15), Color::LightGray);
+    }
+
+    #[test]
+    fn tall_sprite_tile_index() {
+        let mut ppu = sprite_ppu();
+        ppu.reg_write(PPU_LCDC, 0b1001_0111);
+        // Each row of tiles 4 and 5 has a single pixel set, at an X equal to the row
+        let rows: Vec<(u8, u8)> = (0..8).map(|i| (0x80 >> i, 0x00)).collect();
+        write_tile(&mut ppu, 4, &rows);
+        let rows: Vec<(u8, u8)> = (0..8).map(|i| (0x80 >> i, 0x80 >> i)).collect();
+        write_tile(&mut ppu, 5, &rows);
+        // Tile 6 must never be drawn
+        write_tile(&mut ppu, 6, &[(0xFF, 0xFF); 8]);
+
+        // Both index 4 and index 5 select tile 4 on top and tile 5 on the bottom
+        write_sprite(&mut ppu, 0, 16, 8, 4, 0x00);
+        write_sprite(&mut ppu, 1, 16, 24, 5, 0x00);
+        write_sprite(&mut ppu, 2, 16, 40, 5, 0x40);
+
+        let screen = render_frame(&mut ppu);
+        for x in [0, 16] {
+            for row in 0..8 {
+                assert_eq!(pixel(&screen, x + row, row), Color::LightGray);
+                assert_eq!(pixel(&screen, x + row, row + 8), Color::Black);
+            }
+            assert_eq!(pixel(&screen, x + 1, 0), Color::White);
+            assert_eq!(pixel(&screen, x + 1, 8), Color::White);
+        }
+
+        // Y flip swaps the tiles and reverses the rows within each tile
+        for row in 0..8 {
+            assert_eq!(pixel(&screen, 32 + row, 15 - row), Color::LightGray);
+            assert_eq!(pixel(&screen, 32 + row, 7 - row), Color::Black);
+        }
+        assert_eq!(pixel(&screen, 32, 0), Color::White);
+    }
+
+    #[test]
+    fn palettes() {
+        let colors = [
+            Color::White,
+            Color::LightGray,
+            Color::DarkGray,
+            Color::Black,
+        ];
+        for palette in [Palette::GRAYSCALE, Palette::DMG_GREEN, Palette::POCKET_GRAY] {
+            let rgba: Vec<[u8; 4]> = colors.iter().map(|c| c.to_rgba(&palette)).collect();
+            assert_eq!(rgba, palette.colors);
+            // Each shade is darker than the previous one
+            let brightness: Vec<u32> = rgba
+                .iter()
+                .map(|[r, g, b, _]| u32::from(*r) + u32::from(*g) + u32::from(*b))
+                .collect();
+            assert!(brightness.windows(2).all(|pair| pair[0] > pair[1]));
+        }
+        assert_eq!(Color::Black.to_rgba(&Palette::default()), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn tile_images() {
+        let mut ppu = Ppu::new();
+        ppu.reg_write(PPU_BGP, 0b1110_0100);
+        ppu.reg_write(PPU_LCDC, 0b0001_0000); // 0x8000 addressing mode
+        write_tile(&mut ppu, 1, &[(0xFF, 0x00), (0x00, 0xFF), (0xFF, 0xFF)]);
+        ppu.vram_write(0x1800 + 33, 1); // Second row, second column of the first map
+        ppu.vram_write(0x1C00, 1);
+
+        let mut state = crate::State::default();
+        ppu.poll_state(&mut state);
+        let ppu_state = state.ppu.unwrap();
+        assert_eq!(ppu_state.lcdc, 0b0001_0000);
+        assert_eq!(ppu_state.bgp, 0b1110_0100);
+
+        let [map0, map1] = &ppu_state.tile_maps;
+        assert_eq!(map0.len(), TILE_MAP_IMAGE_SIZE * TILE_MAP_IMAGE_SIZE);
+        assert_eq!(map0[8 * TILE_MAP_IMAGE_SIZE + 8], Color::LightGray);
+        assert_eq!(map0[9 * TILE_MAP_IMAGE_SIZE + 15], Color::DarkGray);
+        assert_eq!(map0[10 * TILE_MAP_IMAGE_SIZE + 8], Color::Black);
+        assert_eq!(map0[0], Color::White);
+        assert_eq!(map1[0], Color::LightGray);
+
+        let tiles = &ppu_state.tile_data;
+        assert_eq!(tiles.len(), TILE_DATA_IMAGE_WIDTH * TILE_DATA_IMAGE_HEIGHT);
+        assert_eq!(TILE_DATA_IMAGE_HEIGHT, 192);
+        assert_eq!(tiles[8], Color::LightGray);
+        assert_eq!(tiles[TILE_DATA_IMAGE_WIDTH + 8], Color::DarkGray);
+        assert_eq!(tiles[7], Color::White);
+    }
+
+    #[test]
+    fn tile_images_disabled() {
+        let mut ppu = Ppu::new();
+        ppu.reg_write(PPU_BGP, 0b1110_0100);
+        ppu.reg_write(PPU_LCDC, 0b0001_0000); // 0x8000 addressing mode
+        write_tile(&mut ppu, 0, &[(0xFF, 0xFF)]);
+
+        let mut state = crate::State {
+            tile_images_enabled: false,
+            ..Default::default()
+        };
+        ppu.poll_state(&mut state);
+        let ppu_state = state.ppu.as_ref().unwrap();
+        assert_eq!(ppu_state.lcdc, 0b0001_0000);
+        assert!(ppu_state.tile_maps.iter().all(Vec::is_empty));
+        assert!(ppu_state.tile_data.is_empty());
+
+        state.tile_images_enabled = true;
+        ppu.poll_state(&mut state);
+        assert_eq!(state.ppu.as_ref().unwrap().tile_data[0], Color::Black);
+
+        // The images from the previous poll are kept
+        state.tile_images_enabled = false;
+        write_tile(&mut ppu, 0, &[(0x00, 0x00)]);
+        ppu.poll_state(&mut state);
+        assert_eq!(state.ppu.as_ref().unwrap().tile_data[0], Color::Black);
+    }
+
+    #[test]
+    fn lcd_toggle() {
+        let mut ppu = lcd_on_ppu();
+        let mut interrupts = InterruptRegisters::new();
+        ppu.reg_write(PPU_LYC, 0);
+        ppu.tick(50 * DOTS_PER_SCANLINE as TCycles + 100, &mut interrupts);
+        assert_eq!(ppu.reg_read(PPU_LY), 50);
+        assert!(!ppu.stat.lyc_flag);
+
+        // Turning the LCD off resets LY and the mode
+        ppu.reg_write(PPU_LCDC, 0x00);
+        assert_eq!(ppu.reg_read(PPU_LY), 0);
+        assert_eq!(ppu.reg_read(PPU_STAT) & 0b11, 0);
+        ppu.tick(10 * DOTS_PER_SCANLINE as TCycles, &mut interrupts);
+        assert_eq!(ppu.reg_read(PPU_LY), 0);
+
+        // LY is read only
+        ppu.reg_write(PPU_LY, 0x12);
+        assert_eq!(ppu.reg_read(PPU_LY), 0);
+
+        // Turning the LCD back on restarts the frame
+        ppu.reg_write(PPU_LCDC, 0x80);
+        assert!(ppu.stat.lyc_flag);
+        ppu.tick(1, &mut interrupts);
+        assert_eq!(ppu.stat.mode_flag, ModeFlag::SearchingOam);
+        ppu.tick(DOTS_PER_SCANLINE as TCycles - 1, &mut interrupts);
+        assert_eq!(ppu.reg_read(PPU_LY), 1);
+    }
+
+    #[test]
+    fn scanline_scroll() {
+        let mut ppu = sprite_ppu();
+        let mut interrupts = InterruptRegisters::new();
+        // Tile 1 is solid color 3, and is placed in the first column of the BG
+        write_tile(&mut ppu, 1, &[(0xFF, 0xFF); 8]);
+        for row in 0..TILE_MAP_HEIGHT {
+            ppu.vram[0x1800 + row * TILE_MAP_WIDTH] = 1;
+        }
+
+        // Scroll each of the first four lines by a different amount, changing SCX
+        // during HBlank
+        for line in 0..4 {
+            ppu.reg_write(PPU_SCX, 2 * line);
+            ppu.tick(DOTS_PER_SCANLINE as TCycles, &mut interrupts);
+        }
+        ppu.tick(
+            CYCLES_PER_FRAME - 4 * DOTS_PER_SCANLINE as TCycles,
+            &mut interrupts,
+        );
+
+        let screen = ppu.screen();
+        for line in 0..4 {
+            let scroll = 2 * line;
+            assert_eq!(pixel(&screen, 7 - scroll, line), Color::Black);
+            assert_eq!(pixel(&screen, 8 - scroll, line), Color::White);
+        }
+        // The remaining lines use the final SCX value
+        assert_eq!(pixel(&screen, 1, 100), Color::Black);
+        assert_eq!(pixel(&screen, 2, 100), Color::White);
+
+        // Changing SCX does not affect the latched framebuffer
+        ppu.reg_write(PPU_SCX, 0);
+        assert_eq!(ppu.screen(), screen);
+    }
+
+    #[test]
+    fn tile_atlas() {
+        let mut ppu = Ppu::new();
+        ppu.reg_write(PPU_BGP, 0b1110_0100);
+        // Tile 17 (second row, second column of the atlas): each row has the color
+        // indices 0, 1, 2, 3, 0, 1, 2, 3
+        let tile = 17;
+        for row in 0..8 {
+            ppu.vram_write((tile * TILE_SIZE + row * 2) as u16, 0b0101_0101);
+            ppu.vram_write((tile * TILE_SIZE + row * 2 + 1) as u16, 0b0011_0011);
+        }
+
+        let atlas = ppu.tile_atlas();
+        assert_eq!(atlas.len(), 128 * 192);
+        let expected = [
+            Color::White,
+            Color::LightGray,
+            Color::DarkGray,
+            Color::Black,
+        ];
+        for y in 8..16 {
+            for x in 8..16 {
+                assert_eq!(atlas[y * 128 + x], expected[x % 4]);
+            }
+        }
+        // Neighbouring tiles are empty
+        assert!(atlas[7 * 128..8 * 128].iter().all(|&c| c == Color::White));
+        assert_eq!(atlas[8 * 128 + 7], Color::White);
+        assert_eq!(atlas[8 * 128 + 16], Color::White);
+    }
+
+    #[test]
+    fn stat_unused_bit() {
+        let mut ppu = lcd_on_ppu();
+        let mut interrupts = InterruptRegisters::new();
+        ppu.reg_write(PPU_STAT, 0x00);
+        assert_eq!(ppu.reg_read(PPU_STAT) & 0b1111_1000, 0b1000_0000);
+        ppu.reg_write(PPU_STAT, 0xFF);
+        assert_eq!(ppu.reg_read(PPU_STAT) & 0b1111_1000, 0b1111_1000);
+
+        // Bit 7 reads as 1 in every mode
+        for _ in 0..4 {
+            ppu.tick(100, &mut interrupts);
+            assert!(ppu.reg_read(PPU_STAT).bit(7));
+        }
+        assert_eq!(u8::from(Stat::from(0x00)), 0b1000_0000);
+    }
+}
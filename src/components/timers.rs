@@ -1,4 +1,8 @@
-use crate::{bits::Bits, TCycles};
+use crate::{
+    bits::Bits,
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
+    SnapshotError, TCycles,
+};
 
 use super::mmu::{InterruptManager, DIV_REG, TAC_REG, TIMA_REG, TMA_REG};
 
@@ -24,6 +28,11 @@ impl Timers {
         }
     }
 
+    /// Set the system clock (e.g., to its value after the boot ROM has finished)
+    pub fn set_system_clock(&mut self, value: u16) {
+        self.system_clock = value;
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
             DIV_REG => self.system_clock.to_le_bytes()[1],
@@ -90,16 +99,75 @@ impl Timers {
     }
 
     fn falling_edge_detector(&mut self, prev_system_clock: u16) -> bool {
-        let prev_bytes = prev_system_clock.to_le_bytes();
-        let bytes = self.system_clock.to_le_bytes();
-        let bits = match self.tac.bits(0..=1) {
-            0 => (prev_bytes[1].bit(1), bytes[1].bit(1)),
-            1 => (prev_bytes[0].bit(3), bytes[0].bit(3)),
-            2 => (prev_bytes[0].bit(5), bytes[0].bit(5)),
-            3 => (prev_bytes[0].bit(7), bytes[1].bit(7)),
+        let index = match self.tac.bits(0..=1) {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            3 => 7,
             _ => unreachable!(),
         };
 
-        bits.0 && !bits.1
+        prev_system_clock.bit(index) && !self.system_clock.bit(index)
+    }
+}
+
+impl Snapshot for Timers {
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u16(self.system_clock);
+        writer.write_u8(self.tima);
+        writer.write_u8(self.tma);
+        writer.write_u8(self.tac);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.system_clock = reader.read_u16()?;
+        self.tima = reader.read_u8()?;
+        self.tma = reader.read_u8()?;
+        self.tac = reader.read_u8()? & 0b0000_0111;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::components::interrupts::InterruptRegisters;
+
+    use super::*;
+
+    #[test]
+    fn div_runs_while_timer_disabled() {
+        let mut timers = Timers::new();
+        let mut interrupts = InterruptRegisters::new();
+        timers.write(TAC_REG, 0b011, &mut interrupts);
+
+        timers.tick(255, &mut interrupts);
+        assert_eq!(timers.read(DIV_REG), 0);
+        timers.tick(1, &mut interrupts);
+        assert_eq!(timers.read(DIV_REG), 1);
+        timers.tick(256 * 9, &mut interrupts);
+        assert_eq!(timers.read(DIV_REG), 10);
+        assert_eq!(timers.read(TIMA_REG), 0);
+
+        // Writing to DIV resets it
+        timers.write(DIV_REG, 0xAB, &mut interrupts);
+        assert_eq!(timers.read(DIV_REG), 0);
+        assert_eq!(timers.read(TIMA_REG), 0);
+    }
+
+    #[test]
+    fn tac_frequencies() {
+        // TAC clock select -> TIMA period in T-cycles
+        for (select, period) in [(0b00, 1024), (0b01, 16), (0b10, 64), (0b11, 256)] {
+            let mut timers = Timers::new();
+            let mut interrupts = InterruptRegisters::new();
+            timers.write(TAC_REG, 0b100 | select, &mut interrupts);
+
+            for expected in 1..=3 {
+                timers.tick(period - 1, &mut interrupts);
+                assert_eq!(timers.read(TIMA_REG), expected - 1, "TAC={select:02b}");
+                timers.tick(1, &mut interrupts);
+                assert_eq!(timers.read(TIMA_REG), expected, "TAC={select:02b}");
+            }
+        }
     }
 }
@@ -5,10 +5,11 @@ use bitflags::bitflags;
 use crate::{
     components::{
         interrupts::Interrupt,
-        mmu::{InterruptManager, ReadWriteMemory, Tick},
+        mmu::{InterruptManager, JoypadInput, ReadWriteMemory, Tick},
     },
+    gb::snapshot::{Snapshot, SnapshotReader, SnapshotWriter},
     state::{InstructionInfo, PollState},
-    TCycles,
+    SnapshotError, TCycles,
 };
 
 use super::{
@@ -19,8 +20,8 @@ use super::{
 /// Number of cycles required to read or write a byte from memory
 const DEFAULT_READ_WRITE_CYCLES: TCycles = 4;
 
-/// Number of cycles required to read or write two bytes from memory
-const DEFAULT_READ_WRITE_U16_CYCLES: TCycles = DEFAULT_READ_WRITE_CYCLES * 2;
+/// Opcode of the EI instruction
+const EI_OPCODE: u8 = 0xFB;
 
 #[derive(Debug)]
 pub struct Cpu<T>
@@ -49,10 +50,12 @@ where
     pub pc: u16,
     /// Interrupt master enable flag
     pub ime: bool,
+    /// Indicates EI was executed; IME is set once the following instruction executes
+    pub ime_pending: bool,
     /// Memory management unit
     pub mmu: T,
-    /// Number of cycles executed by the MMU (and its components) from reading/writing
-    /// to memory while executing the current instruction
+    /// Number of cycles executed by the MMU (and its components) so far while
+    /// executing the current instruction
     pub rw_cycles: TCycles,
     /// Previously executed instruction
     pub prev_instruction: Option<instruction::Instruction>,
@@ -60,6 +63,8 @@ where
     pub halt_state: Option<HaltState>,
     /// Indicates the CPU should read the next byte twice.
     pub halt_bug: bool,
+    /// Indicates the CPU is in (very low power) stop mode
+    pub stopped: bool,
 }
 
 bitflags! {
@@ -107,22 +112,86 @@ where
             sp: 0,
             pc: 0,
             ime: false,
+            ime_pending: false,
             mmu,
             rw_cycles: 0,
             prev_instruction: None,
             halt_state: None,
             halt_bug: false,
+            stopped: false,
         }
     }
+
+    /// Reset the registers and execution state to their power-on values.  The MMU is
+    /// not reset.
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.b = 0;
+        self.c = 0;
+        self.d = 0;
+        self.e = 0;
+        self.f = FlagsRegister::default();
+        self.h = 0;
+        self.l = 0;
+        self.sp = 0;
+        self.pc = 0;
+        self.ime = false;
+        self.ime_pending = false;
+        self.rw_cycles = 0;
+        self.prev_instruction = None;
+        self.halt_state = None;
+        self.halt_bug = false;
+        self.stopped = false;
+    }
+
+    /// Set the registers to the values they hold after the DMG boot ROM has finished.
+    ///
+    /// The H and C flags are only set if the header checksum is non-zero.
+    pub fn init_post_boot(&mut self, header_checksum: u8) {
+        self.a = 0x01;
+        self.f = FlagsRegister::Z;
+        if header_checksum != 0 {
+            self.f.insert(FlagsRegister::H | FlagsRegister::C);
+        }
+        self.b = 0x00;
+        self.c = 0x13;
+        self.d = 0x00;
+        self.e = 0xD8;
+        self.h = 0x01;
+        self.l = 0x4D;
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+    }
+
+    /// Returns `true` if the CPU is waiting for an interrupt after executing HALT
+    pub fn is_halted(&self) -> bool {
+        self.halt_state.is_some()
+    }
+
+    /// Returns `true` if the CPU is in stop mode after executing STOP
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
 }
 impl<T> Cpu<T>
 where
-    T: Debug + ReadWriteMemory + Tick + InterruptManager,
+    T: Debug + ReadWriteMemory + Tick + InterruptManager + JoypadInput,
 {
     /// Execute the next instruction.
     ///
     /// Returns the number of cycles required to execute the instruction.
     pub fn step(&mut self) -> TCycles {
+        // Handle the case when the CPU is in stop mode, which is only left once a
+        // button is pressed
+        if self.stopped {
+            if self.mmu.button_held() {
+                self.stopped = false;
+            } else {
+                self.mmu.tick(4);
+                return 4;
+            }
+        }
+
         // Handle the case when the CPU is in a halt state
         if let Some(status) = self.halt_state {
             tracing::debug!(target: "cpu", "CPU is in {:?} state", status);
@@ -170,18 +239,6 @@ where
         };
         let cycles = self.execute(instr);
 
-        // Since the effect of EI is delayed one instruction, a previous EI instruction
-        // is handled here, except if it is followed immediately by a DI instruction
-        if let Some(prev_instruction) = self.prev_instruction {
-            match (prev_instruction.opcode, instr.opcode) {
-                (opcode::Opcode::EI, opcode::Opcode::DI) => {}
-                (opcode::Opcode::EI, _) => {
-                    self.ime = true;
-                }
-                _ => {}
-            }
-        }
-
         self.prev_instruction = Some(instr);
         cycles
     }
@@ -200,7 +257,14 @@ where
         res
     }
 
+    /// Dispatch the given interrupt: disable interrupts, acknowledge the interrupt in
+    /// the IF register, push the program counter to the stack, and jump to the
+    /// interrupt handler.
+    ///
+    /// Returns the number of cycles required to dispatch the interrupt.
     fn call_interrupt_service_routine(&mut self, interrupt: Interrupt) -> TCycles {
+        self.ime = false;
+        self.ime_pending = false;
         self.mmu.tick(8);
         self.mmu.if_reset(interrupt);
         self.call(interrupt.handler_address());
@@ -211,7 +275,7 @@ where
 
 impl<T> Cpu<T>
 where
-    T: Debug + ReadWriteMemory + Tick + InterruptManager,
+    T: Debug + ReadWriteMemory + Tick + InterruptManager + JoypadInput,
 {
     /// Execute the given `Instruction`.
     ///
@@ -220,6 +284,13 @@ where
     fn execute(&mut self, instr: instruction::Instruction) -> TCycles {
         tracing::trace!(target: "cpu", "executing {}", instr.opcode);
 
+        // The effect of EI is delayed one instruction, so a previous EI takes effect
+        // before this instruction executes (allowing an immediate DI to cancel it)
+        if self.ime_pending {
+            self.ime_pending = false;
+            self.ime = true;
+        }
+
         // Increment the program counter (if not dealing with the halt bug) as this
         // is the default behavior for most operations
         if !self.halt_bug {
@@ -229,18 +300,23 @@ where
             self.halt_bug = false;
         }
 
-        // Each component should execute the number of cycles required to read the
-        // instructions operands
-        self.mmu.tick(instr.read_cycles);
-        self.rw_cycles = instr.read_cycles;
+        // Each component should execute the number of cycles required to fetch the
+        // opcode and its operands.  Every cycle after this point is ticked as it
+        // occurs: each memory access and internal operation ticks one M-cycle.
+        let fetch_cycles = DEFAULT_READ_WRITE_CYCLES + instr.read_cycles;
+        self.mmu.tick(fetch_cycles);
+        self.rw_cycles = fetch_cycles;
 
         let total_cycles = instr.execute(self);
 
         self.f.insert(instr.set_flags);
         self.f.remove(instr.reset_flags);
 
-        assert!(total_cycles >= self.rw_cycles);
-        self.mmu.tick(total_cycles - self.rw_cycles);
+        assert_eq!(
+            total_cycles, self.rw_cycles,
+            "cycle count mismatch for {}",
+            instr.opcode
+        );
 
         tracing::trace!(target: "cpu", cpu = ?self);
         total_cycles
@@ -265,16 +341,21 @@ where
     }
 
     pub(super) fn read_u16(&mut self, addr: u16) -> u16 {
-        let value = self.mmu.read_u16(addr);
-        self.rw_cycles += DEFAULT_READ_WRITE_U16_CYCLES;
-        self.mmu.tick(DEFAULT_READ_WRITE_U16_CYCLES);
-        value
+        let low = self.read(addr);
+        let high = self.read(addr.wrapping_add(1));
+        u16::from_le_bytes([low, high])
     }
 
     pub(super) fn write_u16(&mut self, addr: u16, value: u16) {
-        self.mmu.write_u16(addr, value);
-        self.rw_cycles += DEFAULT_READ_WRITE_U16_CYCLES;
-        self.mmu.tick(DEFAULT_READ_WRITE_U16_CYCLES);
+        let [low, high] = value.to_le_bytes();
+        self.write(addr, low);
+        self.write(addr.wrapping_add(1), high);
+    }
+
+    /// Spend one M-cycle on an internal operation (i.e., without accessing memory)
+    pub(super) fn internal_cycle(&mut self) {
+        self.rw_cycles += DEFAULT_READ_WRITE_CYCLES;
+        self.mmu.tick(DEFAULT_READ_WRITE_CYCLES);
     }
 }
 
@@ -413,9 +494,14 @@ where
     }
 
     /// Push a 16-bit value onto the stack and update the stack pointer.
+    ///
+    /// The high byte is written first.
     pub(super) fn push(&mut self, value: u16) {
-        self.sp = self.sp.wrapping_sub(2);
-        self.write_u16(self.sp, value);
+        let [low, high] = value.to_le_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(self.sp, high);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(self.sp, low);
     }
 
     /// Push a 16-bit value from the given register onto the stack and update the stack
@@ -426,6 +512,43 @@ where
     }
 }
 
+impl<T> Cpu<T>
+where
+    T: Debug + ReadWriteMemory,
+{
+    /// Decode `count` instructions from memory starting at the address `start`.
+    ///
+    /// Bytes which cannot be decoded are reported as `DATA` entries.
+    pub fn disassemble(&self, start: u16, count: usize) -> Vec<InstructionInfo> {
+        instruction::MemoryByteStream::new(&self.mmu, start)
+            .take(count)
+            .collect()
+    }
+
+    /// Format the CPU registers and the four bytes of memory starting at the program
+    /// counter as a single line (see `GameBoy::trace_line()`).
+    pub fn trace_line(&self) -> String {
+        let memory = self.mmu.read_range(self.pc, 4);
+        format!(
+            "A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X})",
+            self.a,
+            self.f.bits(),
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.sp,
+            self.pc,
+            memory[0],
+            memory[1],
+            memory[2],
+            memory[3]
+        )
+    }
+}
+
 impl<T> PollState for Cpu<T>
 where
     T: Debug + ReadWriteMemory + Tick + PollState,
@@ -456,73 +579,123 @@ where
             cpu_state.pc = self.pc;
             cpu_state.sp = self.sp;
 
-            let mut stream = DebugByteStream {
-                pc: self.pc,
-                mmu: &self.mmu,
-                bytes: Vec::new(),
+            cpu_state.instructions = if state.disassembly_enabled {
+                let decode_count = state
+                    .instruction_decoding
+                    .decode_count(state.instruction_buffer_size);
+                self.disassemble(self.pc, decode_count)
+            } else {
+                Vec::new()
             };
-
-            cpu_state.instructions.clear();
-            for _ in 0..state.instruction_buffer_size {
-                cpu_state.instructions.push(stream.fetch());
-            }
         }
         self.mmu.poll_state(state);
     }
 }
 
-/// `ByteStream` implementation for polling the state of the emulator (for debugging)
-struct DebugByteStream<'a, T>
-where
-    T: ReadWriteMemory,
-{
-    /// Program counter
-    pc: u16,
-    /// Memory management unit to read bytes from
-    mmu: &'a T,
-    /// List of bytes read
-    bytes: Vec<u8>,
+/// Snapshot encoding of a previously executed instruction.
+///
+/// Only whether the instruction was EI affects emulation (see `HaltState::HaltBug`), so
+/// any other instruction is stored as a NOP.
+fn save_instruction(writer: &mut SnapshotWriter, instr: Option<instruction::Instruction>) {
+    match instr {
+        Some(instruction::Instruction {
+            opcode: opcode::Opcode::EI,
+            ..
+        }) => writer.write_u8(EI_OPCODE),
+        Some(_) => writer.write_u8(0x00),
+        None => writer.write_u8(0x01),
+    }
 }
 
-impl<'a, T> instruction::ByteStream for DebugByteStream<'a, T>
-where
-    T: ReadWriteMemory,
-{
+fn restore_instruction(
+    reader: &mut SnapshotReader,
+) -> Result<Option<instruction::Instruction>, SnapshotError> {
+    match reader.read_u8()? {
+        0x01 => Ok(None),
+        byte @ (0x00 | EI_OPCODE) => {
+            instruction::Instruction::try_from(&mut SnapshotByteStream(byte))
+                .map(Some)
+                .map_err(|_| SnapshotError::InvalidValue("instruction"))
+        }
+        _ => Err(SnapshotError::InvalidValue("instruction")),
+    }
+}
+
+/// `ByteStream` implementation for decoding a single byte instruction from a snapshot
+struct SnapshotByteStream(u8);
+
+impl instruction::ByteStream for SnapshotByteStream {
     fn fetch(&mut self) -> u8 {
-        let old_pc = self.pc;
-        self.pc = self.pc.wrapping_add(1);
-        let byte = self.mmu.read(old_pc);
-        self.bytes.push(byte);
-        byte
+        self.0
     }
 }
 
-impl<'a, T> DebugByteStream<'a, T>
+impl<T> Snapshot for Cpu<T>
 where
-    T: ReadWriteMemory,
+    T: Debug + Snapshot,
 {
-    fn fetch(&mut self) -> InstructionInfo {
-        let instr = instruction::Instruction::try_from(self);
-        let info = match instr {
-            Ok(instr) => InstructionInfo {
-                display: instr.opcode.to_string(),
-                address: self.pc.wrapping_sub(instr.length),
-                bytes: self.bytes.clone(),
-            },
-            Err(byte) => InstructionInfo {
-                display: format!("DATA {:02X}", byte),
-                address: self.pc.wrapping_sub(1),
-                bytes: self.bytes.clone(),
-            },
+    fn save_snapshot(&self, writer: &mut SnapshotWriter) {
+        for reg in [
+            self.a,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.f.bits(),
+            self.h,
+            self.l,
+        ] {
+            writer.write_u8(reg);
+        }
+        writer.write_u16(self.sp);
+        writer.write_u16(self.pc);
+        writer.write_bool(self.ime);
+        writer.write_bool(self.ime_pending);
+        writer.write_i64(self.rw_cycles);
+        save_instruction(writer, self.prev_instruction);
+        match self.halt_state {
+            None => writer.write_u8(0),
+            Some(HaltState::Halt) => writer.write_u8(1),
+            Some(HaltState::HaltBug(instr)) => {
+                writer.write_u8(2);
+                save_instruction(writer, instr);
+            }
+        }
+        writer.write_bool(self.halt_bug);
+        writer.write_bool(self.stopped);
+        self.mmu.save_snapshot(writer);
+    }
+
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.a = reader.read_u8()?;
+        self.b = reader.read_u8()?;
+        self.c = reader.read_u8()?;
+        self.d = reader.read_u8()?;
+        self.e = reader.read_u8()?;
+        self.f = FlagsRegister::from_bits_truncate(reader.read_u8()?);
+        self.h = reader.read_u8()?;
+        self.l = reader.read_u8()?;
+        self.sp = reader.read_u16()?;
+        self.pc = reader.read_u16()?;
+        self.ime = reader.read_bool()?;
+        self.ime_pending = reader.read_bool()?;
+        self.rw_cycles = reader.read_i64()?;
+        self.prev_instruction = restore_instruction(reader)?;
+        self.halt_state = match reader.read_u8()? {
+            0 => None,
+            1 => Some(HaltState::Halt),
+            2 => Some(HaltState::HaltBug(restore_instruction(reader)?)),
+            _ => return Err(SnapshotError::InvalidValue("halt state")),
         };
-        self.bytes.clear();
-        info
+        self.halt_bug = reader.read_bool()?;
+        self.stopped = reader.read_bool()?;
+        self.mmu.restore_snapshot(reader)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::components::mmu::dummy_mmu::DummyMmu;
+    use crate::{components::mmu::dummy_mmu::DummyMmu, state::InstructionDecoding, State};
 
     use super::*;
 
@@ -739,4 +912,331 @@ mod test {
         execute(&mut cpu);
         assert_eq!(cpu.pc, 0x000E);
     }
+
+    #[test]
+    fn lazy_instruction_decoding() {
+        let cpu = new_cpu(&[0x00; 0x0400]);
+        let mut state = State {
+            instruction_buffer_size: 200,
+            ..Default::default()
+        };
+
+        cpu.poll_state(&mut state);
+        let eager_count = state.cpu.as_ref().unwrap().instructions.len();
+
+        state.instruction_decoding = InstructionDecoding::Lazy(Box::new(|| 5..15));
+        cpu.poll_state(&mut state);
+        let lazy_count = state.cpu.as_ref().unwrap().instructions.len();
+
+        assert_eq!(eager_count, 200);
+        assert_eq!(lazy_count, 15);
+        assert!(lazy_count < eager_count);
+    }
+
+    #[test]
+    fn disassembly_disabled() {
+        let cpu = new_cpu(&[0x00; 0x0400]);
+        let mut state = State {
+            instruction_buffer_size: 200,
+            ..Default::default()
+        };
+
+        cpu.poll_state(&mut state);
+        assert_eq!(state.cpu.as_ref().unwrap().instructions.len(), 200);
+
+        state.disassembly_enabled = false;
+        cpu.poll_state(&mut state);
+        let cpu_state = state.cpu.as_ref().unwrap();
+        assert!(cpu_state.instructions.is_empty());
+        // The registers are still reported
+        assert_eq!(cpu_state.pc, cpu.pc);
+    }
+
+    #[test]
+    fn reti() {
+        let mut cpu = new_cpu(&[0xD9]); // RETI
+        cpu.sp = 0x0200;
+        cpu.push(0x1234);
+        cpu.ime = false;
+
+        assert_eq!(execute(&mut cpu), 16);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.sp, 0x0200);
+        assert!(cpu.ime);
+    }
+
+    #[test]
+    fn instruction_timing() {
+        // Every instruction must tick exactly as many cycles as it reports, whether
+        // or not its branch is taken
+        for prefix in [None, Some(0xCB)] {
+            for byte in 0..=0xFFu8 {
+                for flags in [FlagsRegister::empty(), FlagsRegister::all()] {
+                    let rom = match prefix {
+                        Some(prefix) => vec![prefix, byte],
+                        None => vec![byte, 0x00, 0x00],
+                    };
+                    let mut cpu = new_cpu(&rom);
+                    cpu.sp = 0xD000;
+                    cpu.f = flags;
+                    let Ok(instr) = cpu.fetch() else {
+                        continue;
+                    };
+                    let (cycles, branch_cycles) = (instr.cycles, instr.branch_cycles);
+                    let total = cpu.execute(instr);
+                    assert!(total == cycles || total == branch_cycles);
+                    assert_eq!(total, cpu.rw_cycles);
+                }
+            }
+        }
+    }
+
+    /// An MMU which records the cycle at which each memory access occurs
+    #[derive(Debug)]
+    struct TimingMmu {
+        memory: DummyMmu,
+        cycles: std::cell::Cell<TCycles>,
+        accesses: std::cell::RefCell<Vec<(TCycles, char, u16)>>,
+    }
+
+    impl TimingMmu {
+        fn new(rom: &[u8]) -> Self {
+            Self {
+                memory: DummyMmu::new(rom),
+                cycles: Default::default(),
+                accesses: Default::default(),
+            }
+        }
+
+        /// Recorded accesses of the given kind (`'r'` or `'w'`) with their cycle timestamps
+        fn accesses(&self, kind: char) -> Vec<(TCycles, u16)> {
+            self.accesses
+                .borrow()
+                .iter()
+                .filter(|(_, k, _)| *k == kind)
+                .map(|(cycle, _, addr)| (*cycle, *addr))
+                .collect()
+        }
+    }
+
+    impl ReadWriteMemory for TimingMmu {
+        fn read(&self, addr: u16) -> u8 {
+            self.accesses
+                .borrow_mut()
+                .push((self.cycles.get(), 'r', addr));
+            self.memory.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.accesses
+                .borrow_mut()
+                .push((self.cycles.get(), 'w', addr));
+            self.memory.write(addr, value);
+        }
+    }
+
+    impl Tick for TimingMmu {
+        fn tick(&mut self, cycles: TCycles) {
+            self.cycles.set(self.cycles.get() + cycles);
+        }
+    }
+
+    impl InterruptManager for TimingMmu {
+        fn if_set(&mut self, _interrupt: Interrupt) {}
+
+        fn if_reset(&mut self, _interrupt: Interrupt) {}
+
+        fn priority_interrupt(&mut self) -> Option<Interrupt> {
+            None
+        }
+    }
+
+    impl JoypadInput for TimingMmu {
+        fn button_held(&self) -> bool {
+            false
+        }
+    }
+
+    fn access_timing(rom: &[u8], kind: char) -> Vec<(TCycles, u16)> {
+        let mut cpu = Cpu::new(TimingMmu::new(rom));
+        cpu.sp = 0xD000;
+        cpu.h = 0xC0;
+        cpu.l = 0x00;
+        cpu.b = 0x12;
+        cpu.c = 0x34;
+        let instr = cpu.fetch().unwrap();
+        cpu.mmu.accesses.borrow_mut().clear();
+        cpu.execute(instr);
+        cpu.mmu.accesses(kind)
+    }
+
+    #[test]
+    fn memory_access_timing() {
+        // PUSH BC: one internal cycle, then the high byte followed by the low byte
+        assert_eq!(access_timing(&[0xC5], 'w'), vec![(8, 0xCFFF), (12, 0xCFFE)]);
+        // CALL a16: the return address is pushed during the last two M-cycles
+        assert_eq!(
+            access_timing(&[0xCD, 0x00, 0x02], 'w'),
+            vec![(16, 0xCFFF), (20, 0xCFFE)]
+        );
+        // LD (a16),SP
+        assert_eq!(
+            access_timing(&[0x08, 0x00, 0xC1], 'w'),
+            vec![(12, 0xC100), (16, 0xC101)]
+        );
+        // LD A,(HL)
+        assert_eq!(access_timing(&[0x7E], 'r'), vec![(4, 0xC000)]);
+        // POP BC: each byte is read during its own M-cycle
+        assert_eq!(access_timing(&[0xC1], 'r'), vec![(4, 0xD000), (8, 0xD001)]);
+    }
+
+    #[test]
+    fn bit_deref_hl_timing() {
+        for (value, z_flag) in [(0x80, false), (0x7F, true)] {
+            // BIT 7,(HL)
+            let mut cpu = Cpu::new(TimingMmu::new(&[0xCB, 0x7E]));
+            cpu.h = 0xC0;
+            cpu.l = 0x00;
+            cpu.mmu.write(0xC000, value);
+            let instr = cpu.fetch().unwrap();
+            assert_eq!(instr.cycles, 12);
+            cpu.mmu.accesses.borrow_mut().clear();
+
+            assert_eq!(cpu.execute(instr), 12);
+            assert_eq!(cpu.rw_cycles, 12);
+            assert_eq!(cpu.mmu.cycles.get(), 12);
+            // (HL) is read during the third M-cycle, after the two opcode bytes
+            assert_eq!(cpu.mmu.accesses('r'), vec![(8, 0xC000)]);
+            assert!(cpu.mmu.accesses('w').is_empty());
+            assert_eq!(cpu.f.contains(FlagsRegister::Z), z_flag);
+            assert!(cpu.f.contains(FlagsRegister::H));
+            assert!(!cpu.f.contains(FlagsRegister::N));
+        }
+    }
+
+    #[test]
+    fn daa() {
+        // (A, N, H, C) -> (A, Z, C)
+        let vectors = [
+            // After ADD
+            ((0x00, false, false, false), (0x00, true, false)),
+            ((0x0A, false, false, false), (0x10, false, false)),
+            ((0x12, false, true, false), (0x18, false, false)),
+            ((0x9A, false, false, false), (0x00, true, true)),
+            ((0xA0, false, false, false), (0x00, true, true)),
+            ((0x9F, false, false, false), (0x05, false, true)),
+            ((0x20, false, false, true), (0x80, false, true)),
+            ((0x32, false, true, true), (0x98, false, true)),
+            ((0x00, false, false, true), (0x60, false, true)),
+            // After SUB
+            ((0x00, true, false, false), (0x00, true, false)),
+            ((0x0F, true, true, false), (0x09, false, false)),
+            ((0xF0, true, false, true), (0x90, false, true)),
+            ((0xFF, true, true, true), (0x99, false, true)),
+            ((0x66, true, true, true), (0x00, true, true)),
+        ];
+        for ((a, n, h, c), (expected_a, z, expected_c)) in vectors {
+            let mut cpu = new_cpu(&[0x27]); // DAA
+            cpu.a = a;
+            cpu.f.set(FlagsRegister::Z, !z);
+            cpu.f.set(FlagsRegister::N, n);
+            cpu.f.set(FlagsRegister::H, h);
+            cpu.f.set(FlagsRegister::C, c);
+            execute(&mut cpu);
+
+            let input = format!("A={a:02X} N={n} H={h} C={c}");
+            assert_eq!(cpu.a, expected_a, "{input}");
+            assert_eq!(cpu.f.contains(FlagsRegister::Z), z, "{input}");
+            assert_eq!(cpu.f.contains(FlagsRegister::C), expected_c, "{input}");
+            assert_eq!(cpu.f.contains(FlagsRegister::N), n, "{input}");
+            assert!(!cpu.f.contains(FlagsRegister::H), "{input}");
+        }
+    }
+
+    #[test]
+    fn daa_bcd_arithmetic() {
+        let to_bcd = |value: u32| (((value / 10 % 10) << 4) | (value % 10)) as u8;
+        for x in 0..100 {
+            for y in 0..100 {
+                // ADD A, B; DAA
+                let mut cpu = new_cpu(&[0x80, 0x27]);
+                cpu.a = to_bcd(x);
+                cpu.b = to_bcd(y);
+                execute(&mut cpu);
+                execute(&mut cpu);
+                assert_eq!(cpu.a, to_bcd(x + y), "{x} + {y}");
+                assert_eq!(cpu.f.contains(FlagsRegister::C), x + y >= 100);
+                assert_eq!(cpu.f.contains(FlagsRegister::Z), (x + y) % 100 == 0);
+
+                // SUB B; DAA
+                let mut cpu = new_cpu(&[0x90, 0x27]);
+                cpu.a = to_bcd(x);
+                cpu.b = to_bcd(y);
+                execute(&mut cpu);
+                execute(&mut cpu);
+                assert_eq!(cpu.a, to_bcd(x + 100 - y), "{x} - {y}");
+                assert_eq!(cpu.f.contains(FlagsRegister::C), x < y);
+                assert_eq!(cpu.f.contains(FlagsRegister::Z), x == y);
+            }
+        }
+    }
+
+    #[test]
+    fn scf_preserves_z() {
+        for z in [false, true] {
+            let mut cpu = new_cpu(&[0x37]); // SCF
+            cpu.f = FlagsRegister::N | FlagsRegister::H;
+            cpu.f.set(FlagsRegister::Z, z);
+            execute(&mut cpu);
+            assert_eq!(cpu.f.contains(FlagsRegister::Z), z);
+            assert!(!cpu.f.contains(FlagsRegister::N));
+            assert!(!cpu.f.contains(FlagsRegister::H));
+            assert!(cpu.f.contains(FlagsRegister::C));
+        }
+    }
+
+    #[test]
+    fn sp_offset_flags() {
+        // (SP, offset) -> (result, H, C); the flags come from the unsigned addition of
+        // the low byte of SP and the offset byte, regardless of the offset's sign
+        let vectors = [
+            ((0x00FF, 1i8), (0x0100, true, true)),
+            ((0x0001, -1), (0x0000, true, true)),
+            ((0x0000, -1), (0xFFFF, false, false)),
+            ((0x000F, 1), (0x0010, true, false)),
+            ((0x00F0, 0x10), (0x0100, false, true)),
+            ((0xFFFF, 1), (0x0000, true, true)),
+            ((0x1000, -128), (0x0F80, false, false)),
+            ((0x0F88, -128), (0x0F08, false, true)),
+            ((0xDFFD, 127), (0xE07C, true, true)),
+            ((0xFFF8, 2), (0xFFFA, false, false)),
+        ];
+        for ((sp, offset), (expected, h, c)) in vectors {
+            let input = format!("SP={sp:04X} e8={offset}");
+
+            let mut cpu = new_cpu(&[0xE8, offset as u8]); // ADD SP, e8
+            cpu.sp = sp;
+            cpu.f = FlagsRegister::all();
+            execute(&mut cpu);
+            assert_eq!(cpu.sp, expected, "{input}");
+            assert!(
+                !cpu.f.intersects(FlagsRegister::Z | FlagsRegister::N),
+                "{input}"
+            );
+            assert_eq!(cpu.f.contains(FlagsRegister::H), h, "{input}");
+            assert_eq!(cpu.f.contains(FlagsRegister::C), c, "{input}");
+
+            let mut cpu = new_cpu(&[0xF8, offset as u8]); // LD HL, SP + e8
+            cpu.sp = sp;
+            execute(&mut cpu);
+            assert_eq!(cpu.wide_reg(WideRegister::HL), expected, "{input}");
+            assert_eq!(cpu.sp, sp, "{input}");
+            assert!(
+                !cpu.f.intersects(FlagsRegister::Z | FlagsRegister::N),
+                "{input}"
+            );
+            assert_eq!(cpu.f.contains(FlagsRegister::H), h, "{input}");
+            assert_eq!(cpu.f.contains(FlagsRegister::C), c, "{input}");
+        }
+    }
 }
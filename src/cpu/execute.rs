@@ -2,7 +2,7 @@ use std::fmt::Debug;
 
 use crate::{
     bits::Bits,
-    components::mmu::{InterruptManager, ReadWriteMemory, Tick},
+    components::mmu::{InterruptManager, JoypadInput, ReadWriteMemory, Tick, DIV_REG},
     TCycles,
 };
 
@@ -15,7 +15,7 @@ use super::{
 impl Instruction {
     pub(super) fn execute<T>(&self, cpu: &mut Cpu<T>) -> TCycles
     where
-        T: Debug + ReadWriteMemory + Tick + InterruptManager,
+        T: Debug + ReadWriteMemory + Tick + InterruptManager + JoypadInput,
     {
         match self.opcode {
             Opcode::Nop => self.cycles,
@@ -24,15 +24,36 @@ impl Instruction {
                 self.cycles
             }
             Opcode::Stop => {
-                tracing::warn!(target: "cpu", "STOP instruction is not supported");
+                if cpu.mmu.speed_switch() {
+                    // A CGB speed switch resets DIV, but does not enter stop mode
+                    cpu.mmu.write(DIV_REG, 0);
+                    return self.cycles;
+                }
+                // STOP is a 2-byte instruction, unless an interrupt is pending
+                let interrupt_pending = cpu.mmu.priority_interrupt().is_some();
+                if interrupt_pending {
+                    cpu.pc = cpu.pc.wrapping_sub(1);
+                }
+                if cpu.mmu.button_held() {
+                    // With a button held, stop mode is not entered; the CPU halts
+                    // instead (unless an interrupt is pending)
+                    if !interrupt_pending {
+                        cpu.halt_state = Some(HaltState::Halt);
+                    }
+                } else {
+                    cpu.mmu.write(DIV_REG, 0);
+                    cpu.stopped = true;
+                }
                 self.cycles
             }
             Opcode::Jr(offset) => {
+                cpu.internal_cycle();
                 cpu.pc = cpu.pc.wrapping_add_signed(i16::from(offset));
                 self.cycles
             }
             Opcode::JrCond(cond, offset) => {
                 if cpu.condition(cond) {
+                    cpu.internal_cycle();
                     cpu.pc = cpu.pc.wrapping_add_signed(i16::from(offset));
                     self.branch_cycles
                 } else {
@@ -44,6 +65,7 @@ impl Instruction {
                 self.cycles
             }
             Opcode::AddHLWideReg(reg) => {
+                cpu.internal_cycle();
                 cpu.add_wide(WideRegister::HL, reg);
                 self.cycles
             }
@@ -83,10 +105,12 @@ impl Instruction {
                 self.cycles
             }
             Opcode::IncWideReg(reg) => {
+                cpu.internal_cycle();
                 cpu.inc_wide(reg);
                 self.cycles
             }
             Opcode::DecWideReg(reg) => {
+                cpu.internal_cycle();
                 cpu.dec_wide(reg);
                 self.cycles
             }
@@ -157,9 +181,16 @@ impl Instruction {
                 self.cycles
             }
             Opcode::Halt => {
-                if cpu.ime {
-                    cpu.halt_state = Some(HaltState::Halt);
-                } else if cpu.mmu.priority_interrupt().is_some() {
+                // An EI immediately before HALT has only just taken effect, so a pending
+                // interrupt still triggers the halt bug
+                let ei_delay = matches!(
+                    cpu.prev_instruction,
+                    Some(Instruction {
+                        opcode: Opcode::EI,
+                        ..
+                    })
+                );
+                if (!cpu.ime || ei_delay) && cpu.mmu.priority_interrupt().is_some() {
                     cpu.halt_state = Some(HaltState::HaltBug(cpu.prev_instruction));
                 } else {
                     cpu.halt_state = Some(HaltState::Halt);
@@ -207,8 +238,11 @@ impl Instruction {
                 self.cycles
             }
             Opcode::RetCond(cond) => {
+                // Checking the condition takes an extra cycle
+                cpu.internal_cycle();
                 if cpu.condition(cond) {
                     cpu.ret();
+                    cpu.internal_cycle();
                     self.branch_cycles
                 } else {
                     self.cycles
@@ -220,6 +254,8 @@ impl Instruction {
                 self.cycles
             }
             Opcode::AddSpDisp(offset) => {
+                cpu.internal_cycle();
+                cpu.internal_cycle();
                 cpu.sp = cpu.add_sp_offset(offset);
                 self.cycles
             }
@@ -229,6 +265,7 @@ impl Instruction {
                 self.cycles
             }
             Opcode::LdHLSPDisp(offset) => {
+                cpu.internal_cycle();
                 let value = cpu.add_sp_offset(offset);
                 cpu.set_wide_reg(WideRegister::HL, value);
                 self.cycles
@@ -239,11 +276,14 @@ impl Instruction {
             }
             Opcode::Ret => {
                 cpu.ret();
+                cpu.internal_cycle();
                 self.cycles
             }
             Opcode::Reti => {
-                cpu.ime = true;
+                // Unlike EI, RETI enables interrupts immediately
                 cpu.ret();
+                cpu.internal_cycle();
+                cpu.ime = true;
                 self.cycles
             }
             Opcode::JpHL => {
@@ -252,12 +292,14 @@ impl Instruction {
                 self.cycles
             }
             Opcode::LdSPHL => {
+                cpu.internal_cycle();
                 let addr = cpu.wide_reg(WideRegister::HL);
                 cpu.sp = addr;
                 self.cycles
             }
             Opcode::JPCondImm(cond, addr) => {
                 if cpu.condition(cond) {
+                    cpu.internal_cycle();
                     cpu.pc = addr;
                     self.branch_cycles
                 } else {
@@ -289,21 +331,23 @@ impl Instruction {
                 self.cycles
             }
             Opcode::JP(addr) => {
+                cpu.internal_cycle();
                 cpu.pc = addr;
                 self.cycles
             }
             Opcode::DI => {
                 cpu.ime = false;
+                cpu.ime_pending = false;
                 self.cycles
             }
             Opcode::EI => {
-                // Since the effect of EI is delayed one instruction, the execution of EI
-                // handled in the `Cpu::step()` function by looking at what the previous
-                // instruction was
+                // The effect of EI is delayed one instruction (see `Cpu::execute()`)
+                cpu.ime_pending = true;
                 self.cycles
             }
             Opcode::CallCondImm(cond, addr) => {
                 if cpu.condition(cond) {
+                    cpu.internal_cycle();
                     cpu.call(addr);
                     self.branch_cycles
                 } else {
@@ -311,10 +355,12 @@ impl Instruction {
                 }
             }
             Opcode::PushWideReg(reg) => {
+                cpu.internal_cycle();
                 cpu.push_wide_reg(reg);
                 self.cycles
             }
             Opcode::CallImm(addr) => {
+                cpu.internal_cycle();
                 cpu.call(addr);
                 self.cycles
             }
@@ -351,6 +397,7 @@ impl Instruction {
                 self.cycles
             }
             Opcode::Rst(addr) => {
+                cpu.internal_cycle();
                 cpu.call(addr.into());
                 self.cycles
             }
@@ -5,7 +5,7 @@
 
 use crate::cpu::opcode::Opcode;
 
-use crate::TCycles;
+use crate::{components::mmu::ReadWriteMemory, state::InstructionInfo, TCycles};
 
 use super::{opcode::Register, FlagsRegister};
 
@@ -16,7 +16,8 @@ pub struct Instruction {
     pub opcode: Opcode,
     /// Length of the instruction in bytes
     pub length: u16,
-    /// Number of T-cycles required to read operands from memory
+    /// Number of T-cycles required to read the bytes following the first opcode byte
+    /// (i.e., the operands or the opcode following a 0xCB prefix)
     pub read_cycles: TCycles,
     /// Number of T-cycles without branch
     pub cycles: TCycles,
@@ -96,10 +97,13 @@ impl InstructionBuilder {
 
     fn build(&mut self) -> Instruction {
         let cycles = self.cycles.unwrap_or(4);
+        let length = self.length.unwrap_or(1);
         Instruction {
             opcode: self.opcode,
-            length: self.length.unwrap_or(1),
-            read_cycles: self.read_cycles.unwrap_or(0),
+            length,
+            read_cycles: self
+                .read_cycles
+                .unwrap_or(4 * TCycles::from(length.saturating_sub(1))),
             cycles,
             branch_cycles: self.branch_cycles.unwrap_or(cycles),
             set_flags: self.set_flags.unwrap_or_default(),
@@ -125,6 +129,93 @@ pub trait ByteStream {
     }
 }
 
+/// Bytes are fetched from the front of the slice.  Once the slice is exhausted, zero
+/// is returned.
+impl ByteStream for &[u8] {
+    fn fetch(&mut self) -> u8 {
+        match self.split_first() {
+            Some((&byte, rest)) => {
+                *self = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+}
+
+/// `ByteStream` implementation which reads bytes from any memory source, starting at
+/// an arbitrary address.
+///
+/// Iterating over the stream decodes one instruction at a time, which allows memory to
+/// be disassembled without a `Cpu`.  Bytes which cannot be decoded are reported as
+/// `DATA` entries.  Addresses past 0xFFFF wrap around to 0x0000.
+pub struct MemoryByteStream<'a, M>
+where
+    M: ReadWriteMemory,
+{
+    /// Address of the next byte to fetch
+    address: u16,
+    /// Memory to read bytes from
+    memory: &'a M,
+    /// Bytes fetched for the instruction currently being decoded
+    bytes: Vec<u8>,
+}
+
+impl<'a, M> MemoryByteStream<'a, M>
+where
+    M: ReadWriteMemory,
+{
+    pub fn new(memory: &'a M, start: u16) -> Self {
+        Self {
+            address: start,
+            memory,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Address of the next byte to fetch
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+}
+
+impl<'a, M> ByteStream for MemoryByteStream<'a, M>
+where
+    M: ReadWriteMemory,
+{
+    fn fetch(&mut self) -> u8 {
+        let byte = self.memory.read(self.address);
+        self.address = self.address.wrapping_add(1);
+        self.bytes.push(byte);
+        byte
+    }
+}
+
+impl<'a, M> Iterator for MemoryByteStream<'a, M>
+where
+    M: ReadWriteMemory,
+{
+    type Item = InstructionInfo;
+
+    /// Decode the next instruction.  The stream never ends.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.bytes.clear();
+        let info = match Instruction::try_from(self) {
+            Ok(instr) => InstructionInfo {
+                display: instr.opcode.to_string(),
+                address: self.address.wrapping_sub(instr.length),
+                bytes: self.bytes.clone(),
+            },
+            Err(byte) => InstructionInfo {
+                display: format!("DATA {:02X}", byte),
+                address: self.address.wrapping_sub(1),
+                bytes: self.bytes.clone(),
+            },
+        };
+        Some(info)
+    }
+}
+
 impl Instruction {
     /// Decode the next instruction from the given `ByteStream`
     ///
@@ -134,14 +225,30 @@ impl Instruction {
     where
         T: ByteStream,
     {
-        let mut instr = InstructionBuilder::new(Opcode::try_from(stream)?);
+        Ok(Self::from_opcode(Opcode::try_from(stream)?))
+    }
+
+    /// Return the length (in bytes), cycles without branch and cycles with branch of
+    /// the given opcode.
+    ///
+    /// The timing of an instruction does not depend on its operands, so any operand
+    /// values can be used.
+    pub fn metadata(opcode: Opcode) -> (u16, TCycles, TCycles) {
+        let instr = Self::from_opcode(opcode);
+        (instr.length, instr.cycles, instr.branch_cycles)
+    }
+
+    /// Construct the instruction corresponding to an already decoded opcode
+    fn from_opcode(opcode: Opcode) -> Instruction {
+        let mut instr = InstructionBuilder::new(opcode);
         match instr.opcode {
             Opcode::Nop => {}
             Opcode::LdDerefImmSp(_) => {
                 instr.length(3).read_cycles(8).cycles(20);
             }
             Opcode::Stop => {
-                instr.length(2);
+                // The byte following STOP does not take any cycles to skip
+                instr.length(2).read_cycles(0);
             }
             Opcode::Jr(_) => {
                 instr.length(2).read_cycles(4).cycles(12);
@@ -427,7 +534,7 @@ impl Instruction {
             }
         }
 
-        Ok(instr.build())
+        instr.build()
     }
 }
 
@@ -502,6 +609,7 @@ mod test {
             0x10,
             0xFF,
             0x10, // STOP
+            0x00,
             0x18, // JR -22
             -22i8 as u8,
             0x20, // JR NZ, -10
@@ -702,4 +810,78 @@ mod test {
             .collect();
         assert_eq!(opcodes, correct_opcodes);
     }
+
+    #[test]
+    fn stop_length() {
+        let mut bytes: &[u8] = &[0x10, 0x00, 0x00];
+        let instr = Instruction::try_from(&mut bytes).unwrap();
+        assert_eq!(instr.opcode, Opcode::Stop);
+        assert_eq!(instr.length, 2);
+        assert_eq!(bytes.len(), 1);
+    }
+
+    #[test]
+    fn metadata() {
+        // JR NZ, e: 8 cycles if not taken, 12 cycles if taken
+        assert_eq!(
+            Instruction::metadata(Opcode::JrCond(FlagCondition::NZ, 0)),
+            (2, 8, 12)
+        );
+        // Operands do not affect the timing
+        assert_eq!(
+            Instruction::metadata(Opcode::JrCond(FlagCondition::C, -10)),
+            (2, 8, 12)
+        );
+        assert_eq!(Instruction::metadata(Opcode::Nop), (1, 4, 4));
+
+        let mut bytes: &[u8] = &[0x20];
+        let instr = Instruction::try_from(&mut bytes).unwrap();
+        assert_eq!(instr.opcode, Opcode::JrCond(FlagCondition::NZ, 0));
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn memory_byte_stream() {
+        use crate::components::mmu::dummy_mmu::DummyMmu;
+
+        let mut mmu = DummyMmu::new(&[]);
+        for (offset, byte) in [0x3E, 0x12, 0xCB, 0x37, 0xD3, 0xC3, 0x50, 0x01]
+            .into_iter()
+            .enumerate()
+        {
+            mmu.write(0xC000 + offset as u16, byte);
+        }
+
+        let mut stream = MemoryByteStream::new(&mmu, 0xC000);
+        let instructions: Vec<InstructionInfo> = stream.by_ref().take(4).collect();
+        assert_eq!(stream.address(), 0xC008);
+        let addresses: Vec<u16> = instructions.iter().map(|info| info.address).collect();
+        assert_eq!(addresses, [0xC000, 0xC002, 0xC004, 0xC005]);
+        let displays: Vec<&str> = instructions
+            .iter()
+            .map(|info| info.display.as_str())
+            .collect();
+        assert_eq!(displays, ["LD A, $12", "SWAP A", "DATA D3", "JP $0150"]);
+        assert_eq!(instructions[3].bytes, [0xC3, 0x50, 0x01]);
+
+        // Addresses wrap around past 0xFFFF
+        mmu.write(0xFFFF, 0x01);
+        let info = MemoryByteStream::new(&mmu, 0xFFFF).next().unwrap();
+        assert_eq!(info.address, 0xFFFF);
+        assert_eq!(info.bytes, [0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn rst_vectors() {
+        let instr = Instruction::try_from(&mut &[0xFF][..]).unwrap();
+        assert!(matches!(instr.opcode, Opcode::Rst(0x38)));
+        assert_eq!(instr.opcode.to_string(), "RST $38");
+
+        // The operand is the vector address itself, not the index of the vector
+        for vector in (0x00..=0x38).step_by(8) {
+            let instr = Instruction::try_from(&mut &[0xC7 + vector][..]).unwrap();
+            assert!(matches!(instr.opcode, Opcode::Rst(v) if v == vector));
+            assert_eq!(instr.opcode.to_string(), format!("RST ${:02X}", vector));
+        }
+    }
 }
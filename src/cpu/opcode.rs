@@ -162,7 +162,7 @@ impl Display for Opcode {
             Opcode::XorImm(n) => write!(f, "XOR A, ${:02X}", n),
             Opcode::OrImm(n) => write!(f, "OR A, ${:02X}", n),
             Opcode::CpImm(n) => write!(f, "CP A, ${:02X}", n),
-            Opcode::Rst(y) => write!(f, "RST ${:02X}", y),
+            Opcode::Rst(vector) => write!(f, "RST ${:02X}", vector),
             Opcode::Rlc(reg) => write!(f, "RLC {}", reg),
             Opcode::Rrc(reg) => write!(f, "RRC {}", reg),
             Opcode::Rl(reg) => write!(f, "RL {}", reg),
@@ -211,6 +211,19 @@ impl DecomposedByte {
 }
 
 impl Opcode {
+    /// Returns `true` if the opcode calls a subroutine (`CALL` or `RST`)
+    pub fn is_subroutine_call(&self) -> bool {
+        matches!(
+            self,
+            Self::CallImm(_) | Self::CallCondImm(_, _) | Self::Rst(_)
+        )
+    }
+
+    /// Returns `true` if the opcode returns from a subroutine (`RET` or `RETI`)
+    pub fn is_subroutine_return(&self) -> bool {
+        matches!(self, Self::Ret | Self::RetCond(_) | Self::Reti)
+    }
+
     /// Decode the next `Opcode` from the given `ByteStream`.
     ///
     /// Returns `Err` containing the fetched byte from `ByteStream` if this byte
@@ -227,7 +240,12 @@ impl Opcode {
             (0, 0) => match db.y {
                 0 => Ok(Opcode::Nop),
                 1 => Ok(Opcode::LdDerefImmSp(stream.fetch_u16())),
-                2 => Ok(Opcode::Stop),
+                2 => {
+                    // STOP is followed by an unused byte, which is part of the
+                    // instruction
+                    stream.fetch();
+                    Ok(Opcode::Stop)
+                }
                 3 => Ok(Opcode::Jr(stream.fetch_i8())),
                 y if y < 8 => Ok(Opcode::JrCond(
                     FlagCondition::from(y - 4),
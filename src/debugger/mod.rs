@@ -2,6 +2,7 @@ mod widgets;
 
 use std::{
     collections::{HashMap, HashSet},
+    ops::Range,
     sync::mpsc::{channel, Receiver, Sender},
 };
 
@@ -9,15 +10,18 @@ use fltk::{
     app::{self},
     browser,
     button::{Button, CheckButton},
-    enums::CallbackTrigger,
+    enums::{CallbackTrigger, ColorDepth},
     frame::Frame,
     group::{Flex, Tabs},
-    input,
+    image::RgbImage,
+    input, misc,
     prelude::*,
     text::{TextBuffer, TextDisplay},
     window::DoubleWindow,
 };
 
+use qgb::{state::PpuState, Color, Palette};
+
 use self::widgets::{
     EmitButton, InstructionBrowser, MemoryTable, RegisterDisplay, WideRegisterDisplay,
 };
@@ -30,6 +34,11 @@ const COMMAND_COLUMN_WIDTH: i32 = 400;
 const BUTTON_HEIGHT: i32 = 35;
 const LABEL_HEIGHT: i32 = BUTTON_HEIGHT;
 const BREAKPOINT_BROWSER_HEIGHT: i32 = 3 * BUTTON_HEIGHT;
+const PPU_REGISTER_COLUMN_WIDTH: i32 = 200;
+/// Scale at which tile maps and tile data are displayed
+const TILE_IMAGE_SCALE: i32 = 2;
+/// Maximum number of instructions which can be disassembled at once
+const MAX_INSTRUCTION_BUFFER_SIZE: usize = 1000;
 
 #[derive(Debug)]
 pub struct Debugger {
@@ -54,15 +63,29 @@ pub struct Debugger {
     hram_table: MemoryTable,
     cartridge: browser::Browser,
     serial_output: TextDisplay,
+    ppu_registers: browser::Browser,
+    tile_map0: Frame,
+    tile_map1: Frame,
+    tile_data: Frame,
     breakpoint_list: browser::SelectBrowser,
     breakpoints: HashMap<String, u16>,
+    watchpoint_list: browser::SelectBrowser,
+    watchpoints: HashMap<String, u16>,
     breakpoint_receiver: Receiver<BreakpointEvent>,
 }
 
 #[derive(Debug)]
 enum BreakpointEvent {
-    Add(String, u16),
-    Delete(String),
+    Add(BreakpointKind, String, u16),
+    Delete(BreakpointKind, String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BreakpointKind {
+    /// Pause when the program counter reaches the address
+    Execute,
+    /// Pause when the address is written to
+    Write,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -70,7 +93,13 @@ pub enum Message {
     Pause,
     Run,
     Step,
+    /// Step over subroutine calls
+    StepOver,
+    /// Run until the current subroutine returns
+    StepOut,
     Quit,
+    /// Change the number of instructions disassembled from the program counter
+    InstructionBufferSize(usize),
 }
 
 impl Debugger {
@@ -99,6 +128,11 @@ impl Debugger {
         let cartridge;
         let serial_output;
         let breakpoint_list;
+        let watchpoint_list;
+        let ppu_registers;
+        let tile_map0;
+        let tile_map1;
+        let tile_data;
 
         let (breakpoint_sender, breakpoint_receiver) = channel::<BreakpointEvent>();
 
@@ -115,48 +149,47 @@ impl Debugger {
                     let _run = EmitButton::new("Run", msg_sender.clone(), Message::Run);
                     let _pause = EmitButton::new("Pause", msg_sender.clone(), Message::Pause);
                     let _step = EmitButton::new("Step", msg_sender.clone(), Message::Step);
+                    let _step_over =
+                        EmitButton::new("Step Over", msg_sender.clone(), Message::StepOver);
+                    let _step_out =
+                        EmitButton::new("Step Out", msg_sender.clone(), Message::StepOut);
                     row.end();
                     row.set_pad(PADDING);
                     col.fixed(&row, BUTTON_HEIGHT);
                 }
-
-                disassembly = InstructionBrowser::new();
-
-                let breakpoint_label = Frame::default().with_label("Breakpoints");
-                col.fixed(&breakpoint_label, LABEL_HEIGHT);
-
-                breakpoint_list = browser::SelectBrowser::default();
                 {
-                    let row = Flex::default_fill().row();
-                    let mut breakpoint_input = input::Input::default();
-                    let mut add_breakpoint = Button::default().with_label("Add");
-                    let mut delete_breakpoint = Button::default().with_label("Delete");
-                    row.end();
-                    col.fixed(&row, BUTTON_HEIGHT);
-                    add_breakpoint.set_callback({
-                        let sender = breakpoint_sender.clone();
-                        move |_| {
-                            let addr_str = breakpoint_input.value();
-                            let addr = match u16::from_str_radix(&addr_str, 16) {
-                                Ok(value) => value,
-                                Err(_) => return,
-                            };
-                            let addr_str = format!("{:04X}", addr);
-                            _ = sender.send(BreakpointEvent::Add(addr_str, addr));
-                            breakpoint_input.set_value("");
+                    let mut row = Flex::default_fill().row();
+                    let _label = Frame::default().with_label("Instructions");
+                    let mut buffer_size = misc::Spinner::default();
+                    buffer_size.set_range(1.0, MAX_INSTRUCTION_BUFFER_SIZE as f64);
+                    buffer_size.set_step(1.0);
+                    buffer_size.set_value(state.instruction_buffer_size() as f64);
+                    buffer_size.set_callback({
+                        let sender = msg_sender.clone();
+                        move |spinner| {
+                            let size = spinner.value() as usize;
+                            _ = sender.send(Message::InstructionBufferSize(size));
                         }
                     });
-                    delete_breakpoint.set_callback({
-                        let sender = breakpoint_sender.clone();
-                        let list = breakpoint_list.clone();
-                        move |_| {
-                            if let Some(addr_str) = list.selected_text() {
-                                _ = sender.send(BreakpointEvent::Delete(addr_str));
-                            }
-                        }
-                    })
+                    row.end();
+                    row.set_pad(PADDING);
+                    col.fixed(&row, BUTTON_HEIGHT);
                 }
-                col.fixed(&breakpoint_list, BREAKPOINT_BROWSER_HEIGHT);
+
+                disassembly = InstructionBrowser::new();
+
+                breakpoint_list = address_list(
+                    &mut col,
+                    "Breakpoints",
+                    BreakpointKind::Execute,
+                    &breakpoint_sender,
+                );
+                watchpoint_list = address_list(
+                    &mut col,
+                    "Watchpoints",
+                    BreakpointKind::Write,
+                    &breakpoint_sender,
+                );
             }
             col.end();
             col.set_pad(PADDING);
@@ -300,9 +333,28 @@ impl Debugger {
                     row.set_margin(MARGIN);
                 }
                 {
-                    let row = Flex::default_fill().row().with_label("PPU\t");
-                    Frame::default().with_label("No data available");
+                    let mut row = Flex::default_fill().row().with_label("PPU\t");
+                    {
+                        let mut col = Flex::default_fill().column();
+                        let ppu_register_label = Frame::default().with_label("Registers");
+                        ppu_registers = browser::Browser::default();
+                        col.end();
+                        col.fixed(&ppu_register_label, BUTTON_HEIGHT);
+                        col.set_pad(PADDING);
+                        row.fixed(&col, PPU_REGISTER_COLUMN_WIDTH);
+                    }
+                    {
+                        let mut image_tabs = Tabs::default_fill();
+                        tile_map0 = image_tab("Tile Map $9800\t");
+                        tile_map1 = image_tab("Tile Map $9C00\t");
+                        tile_data = image_tab("Tile Data\t");
+                        image_tabs.end();
+                        image_tabs.visible_focus(false);
+                        image_tabs.auto_layout();
+                    }
                     row.end();
+                    row.set_pad(PADDING);
+                    row.set_margin(MARGIN);
                 }
                 {
                     let row = Flex::default_fill().row().with_label("APU\t");
@@ -353,29 +405,37 @@ impl Debugger {
             hram_table,
             cartridge,
             serial_output,
+            ppu_registers,
+            tile_map0,
+            tile_map1,
+            tile_data,
             breakpoints: HashMap::default(),
-            breakpoint_receiver,
             breakpoint_list,
+            watchpoints: HashMap::default(),
+            watchpoint_list,
+            breakpoint_receiver,
         }
     }
 
     pub fn handle_events(&mut self) {
         app::check();
         app::redraw();
-        if let Ok(bp_event) = self.breakpoint_receiver.try_recv() {
+        while let Ok(bp_event) = self.breakpoint_receiver.try_recv() {
             match bp_event {
-                BreakpointEvent::Add(addr_str, addr) => {
-                    if !self.breakpoints.contains_key(&addr_str) {
-                        self.breakpoints.insert(addr_str.clone(), addr);
-                        self.breakpoint_list.add(&addr_str);
+                BreakpointEvent::Add(kind, addr_str, addr) => {
+                    let (addresses, list) = self.address_list(kind);
+                    if !addresses.contains_key(&addr_str) {
+                        addresses.insert(addr_str.clone(), addr);
+                        list.add(&addr_str);
                     }
                 }
-                BreakpointEvent::Delete(addr_str) => {
-                    self.breakpoints.remove(&addr_str);
-                    for idx in 1..self.breakpoint_list.size() + 1 {
-                        if let Some(idx_str) = self.breakpoint_list.text(idx) {
+                BreakpointEvent::Delete(kind, addr_str) => {
+                    let (addresses, list) = self.address_list(kind);
+                    addresses.remove(&addr_str);
+                    for idx in 1..list.size() + 1 {
+                        if let Some(idx_str) = list.text(idx) {
                             if idx_str == addr_str {
-                                self.breakpoint_list.remove(idx);
+                                list.remove(idx);
                                 break;
                             }
                         }
@@ -385,12 +445,28 @@ impl Debugger {
         }
     }
 
-    pub fn breakpoints(&self) -> HashSet<u16> {
-        let mut breakpoints = HashSet::with_capacity(self.breakpoints.len());
-        for value in self.breakpoints.values() {
-            breakpoints.insert(*value);
+    fn address_list(
+        &mut self,
+        kind: BreakpointKind,
+    ) -> (&mut HashMap<String, u16>, &mut browser::SelectBrowser) {
+        match kind {
+            BreakpointKind::Execute => (&mut self.breakpoints, &mut self.breakpoint_list),
+            BreakpointKind::Write => (&mut self.watchpoints, &mut self.watchpoint_list),
         }
-        breakpoints
+    }
+
+    /// Return a callback which computes the range of instructions visible in the
+    /// disassembly view (see `InstructionDecoding::Lazy`)
+    pub fn disassembly_window(&self) -> impl Fn() -> Range<usize> + Send + Sync + 'static {
+        self.disassembly.visible_rows()
+    }
+
+    pub fn breakpoints(&self) -> HashSet<u16> {
+        self.breakpoints.values().copied().collect()
+    }
+
+    pub fn watchpoints(&self) -> HashSet<u16> {
+        self.watchpoints.values().copied().collect()
     }
 
     pub fn update(&mut self, state: &qgb::State) {
@@ -419,7 +495,7 @@ impl Debugger {
             self.cartridge
                 .add(&format!("Title: {}", cart_state.header.title));
             self.cartridge.add(&format!(
-                "Cartridge Type: {:?}",
+                "Cartridge Type: {}",
                 cart_state.header.cartridge_type
             ));
             self.cartridge
@@ -432,6 +508,27 @@ impl Debugger {
                 "Checksum Passed: {}",
                 cart_state.header.checksum_passed
             ));
+            self.cartridge.add(&format!(
+                "Global Checksum: {:#06X}",
+                cart_state.header.global_checksum
+            ));
+            self.cartridge.add(&format!(
+                "Global Checksum Passed: {}",
+                cart_state.header.global_checksum_passed
+            ));
+            self.cartridge.add(&format!(
+                "CGB Flag: {:#04X} ({:?})",
+                cart_state.header.cgb_flag, cart_state.header.cgb_mode
+            ));
+            self.cartridge
+                .add(&format!("SGB Flag: {:#04X}", cart_state.header.sgb_flag));
+            match &cart_state.header.new_licensee_code {
+                Some(code) => self.cartridge.add(&format!("Licensee: {}", code)),
+                None => self.cartridge.add(&format!(
+                    "Licensee: {:#04X}",
+                    cart_state.header.old_licensee_code
+                )),
+            }
             self.cartridge
                 .add(&format!("ROM Bank0: {}", cart_state.rom_bank0));
             self.cartridge
@@ -486,5 +583,135 @@ impl Debugger {
             buffer.set_text(&io_state.transmitted_bytes_ascii());
             self.serial_output.set_buffer(buffer);
         }
+
+        // PPU state
+        if let Some(ppu_state) = &state.ppu {
+            self.ppu_registers.clear();
+            for (name, value) in [
+                ("LCDC", ppu_state.lcdc),
+                ("STAT", ppu_state.stat),
+                ("SCY", ppu_state.scy),
+                ("SCX", ppu_state.scx),
+                ("LY", ppu_state.ly),
+                ("LYC", ppu_state.lyc),
+                ("DMA", ppu_state.dma),
+                ("BGP", ppu_state.bgp),
+                ("OBP0", ppu_state.obp0),
+                ("OBP1", ppu_state.obp1),
+                ("WY", ppu_state.wy),
+                ("WX", ppu_state.wx),
+            ] {
+                self.ppu_registers
+                    .add(&format!("{}: {:02X} ({:08b})", name, value, value));
+            }
+            self.ppu_registers.add(&format!("Mode: {}", ppu_state.mode));
+            self.ppu_registers.add(&format!("Dot: {}", ppu_state.dot));
+            self.ppu_registers
+                .add(&format!("Frame: {}", ppu_state.frame_count));
+
+            let map_size = PpuState::TILE_MAP_IMAGE_SIZE;
+            set_image(
+                &mut self.tile_map0,
+                &ppu_state.tile_maps[0],
+                map_size,
+                map_size,
+            );
+            set_image(
+                &mut self.tile_map1,
+                &ppu_state.tile_maps[1],
+                map_size,
+                map_size,
+            );
+            set_image(
+                &mut self.tile_data,
+                &ppu_state.tile_data,
+                PpuState::TILE_DATA_IMAGE_WIDTH,
+                PpuState::TILE_DATA_IMAGE_HEIGHT,
+            );
+        }
+    }
+}
+
+/// Parse a hexadecimal address, optionally prefixed with `$` or `0x`
+fn parse_address(input: &str) -> Option<u16> {
+    let input = input.trim();
+    let digits = input
+        .strip_prefix('$')
+        .or_else(|| input.strip_prefix("0x"))
+        .or_else(|| input.strip_prefix("0X"))
+        .unwrap_or(input);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Create a labelled list of addresses in `col`, with an input and buttons to add and
+/// delete addresses
+fn address_list(
+    col: &mut Flex,
+    label: &str,
+    kind: BreakpointKind,
+    sender: &Sender<BreakpointEvent>,
+) -> browser::SelectBrowser {
+    let list_label = Frame::default().with_label(label);
+    col.fixed(&list_label, LABEL_HEIGHT);
+
+    let list = browser::SelectBrowser::default();
+    {
+        let row = Flex::default_fill().row();
+        let mut address_input = input::Input::default();
+        let mut add_address = Button::default().with_label("Add");
+        let mut delete_address = Button::default().with_label("Delete");
+        row.end();
+        col.fixed(&row, BUTTON_HEIGHT);
+        add_address.set_callback({
+            let sender = sender.clone();
+            move |_| {
+                let Some(addr) = parse_address(&address_input.value()) else {
+                    return;
+                };
+                let addr_str = format!("{:04X}", addr);
+                _ = sender.send(BreakpointEvent::Add(kind, addr_str, addr));
+                address_input.set_value("");
+            }
+        });
+        delete_address.set_callback({
+            let sender = sender.clone();
+            let list = list.clone();
+            move |_| {
+                if let Some(addr_str) = list.selected_text() {
+                    _ = sender.send(BreakpointEvent::Delete(kind, addr_str));
+                }
+            }
+        })
+    }
+    col.fixed(&list, BREAKPOINT_BROWSER_HEIGHT);
+    list
+}
+
+/// Create a tab containing a frame used to display an image
+fn image_tab(label: &str) -> Frame {
+    let row = Flex::default_fill().row().with_label(label);
+    let frame = Frame::default();
+    row.end();
+    frame
+}
+
+/// Display an image of `width` by `height` colors (in row-major order) in `frame`
+fn set_image(frame: &mut Frame, colors: &[Color], width: usize, height: usize) {
+    let rgba: Vec<u8> = colors
+        .iter()
+        .flat_map(|color| color.to_rgba(&Palette::GRAYSCALE))
+        .collect();
+    match RgbImage::new(&rgba, width as i32, height as i32, ColorDepth::Rgba8) {
+        Ok(mut image) => {
+            image.scale(
+                width as i32 * TILE_IMAGE_SCALE,
+                height as i32 * TILE_IMAGE_SCALE,
+                true,
+                true,
+            );
+            frame.set_image(Some(image));
+            frame.redraw();
+        }
+        Err(err) => tracing::error!(target: "debugger", "failed to create image: {}", err),
     }
 }
@@ -1,4 +1,4 @@
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
 use std::sync::mpsc::Sender;
 
 use fltk::browser;
@@ -172,6 +172,21 @@ impl InstructionBrowser {
             self.browser.add(&str_instr);
         }
     }
+
+    /// Return a callback which computes the range of rows currently visible in the
+    /// browser.
+    ///
+    /// Rows are assumed to be at least as tall as the text size, so the range may
+    /// extend a few rows past the bottom of the browser.
+    pub fn visible_rows(&self) -> impl Fn() -> Range<usize> + Send + Sync + 'static {
+        let browser = self.browser.clone();
+        move || {
+            let row_height = browser.text_size().max(1);
+            let first = browser.position() / row_height;
+            let last = (browser.position() + browser.h()) / row_height + 1;
+            first.max(0) as usize..last.max(0) as usize
+        }
+    }
 }
 
 impl Deref for InstructionBrowser {
@@ -22,10 +22,10 @@ pub enum RomError {
     UnrecognizedRomSize(u8),
     #[error("cartridge header: unrecognized RAM size ({0:02X})")]
     UnrecognizedRamSize(u8),
-    #[error("unsupported cartridge type (found '{0:?}' cartridge type)")]
+    #[error("unsupported cartridge type (found '{0}' cartridge type)")]
     UnsupportedCartridgeType(cartridge::CartridgeType),
     #[error(
-        "unexpected ROM file size for cartridge type `{cartridge_type:?}' (found {found} bytes) "
+        "unexpected ROM file size for cartridge type `{cartridge_type}' (found {found} bytes) "
     )]
     Oversized {
         cartridge_type: cartridge::CartridgeType,
@@ -33,10 +33,40 @@ pub enum RomError {
     },
     #[error("unexpected ROM file size (expected {expected} bytes, found {found} bytes")]
     Size { expected: usize, found: usize },
+    #[error("cartridge header: ROM size overflows ({0} banks)")]
+    SizeOverflow(usize),
+    #[error("unsupported number of RAM banks ({0}, expected 1 to 16)")]
+    InvalidRamBanks(usize),
 }
 
 #[derive(Debug, Error)]
 pub enum BootRomError {
     #[error("unexpected boot ROM size (expected {expected} bytes, found {found} bytes")]
     Size { expected: usize, found: usize },
+    #[error("boot ROM does not appear to be a DMG boot ROM ({0})")]
+    InvalidContents(&'static str),
+}
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("cartridge does not have battery-backed RAM")]
+    NoBattery,
+    #[error("unexpected save file size (expected {expected} bytes, found {found} bytes)")]
+    Size { expected: usize, found: usize },
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("invalid snapshot (missing header)")]
+    InvalidHeader,
+    #[error("unsupported snapshot version (expected {expected}, found {found})")]
+    Version { expected: u8, found: u8 },
+    #[error("snapshot was created with a different cartridge")]
+    CartridgeMismatch,
+    #[error("snapshot ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("invalid value in snapshot ({0})")]
+    InvalidValue(&'static str),
+    #[error("unexpected data at the end of the snapshot")]
+    TrailingData,
 }
@@ -0,0 +1,174 @@
+//! Recording and playback of joypad input
+//!
+//! Input is stored as one `JoypadState` per frame, which makes it possible to replay
+//! a run on a fresh emulator (e.g., for tool-assisted play or to reproduce a bug).
+
+use crate::{GameBoy, JoypadState};
+
+/// Records the state of the joypad on each frame
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InputRecorder {
+    frames: Vec<JoypadState>,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Append the joypad state of the next frame
+    pub fn record(&mut self, state: JoypadState) {
+        self.frames.push(state);
+    }
+
+    /// Append the buttons currently held down on the emulator's joypad
+    pub fn record_frame(&mut self, gb: &GameBoy) {
+        self.record(gb.joypad());
+    }
+
+    /// Return the recorded states, one per frame
+    pub fn frames(&self) -> &[JoypadState] {
+        &self.frames
+    }
+
+    /// Number of recorded frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Encode the recording as one byte per frame (see `JoypadState`)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.frames.iter().map(JoypadState::bits).collect()
+    }
+
+    /// Create a player which replays the recorded input from the first frame
+    pub fn into_player(self) -> InputPlayer {
+        InputPlayer::new(self.frames)
+    }
+}
+
+/// Supplies recorded joypad states to an emulator, one per frame
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InputPlayer {
+    frames: Vec<JoypadState>,
+    /// Index of the next frame to play
+    position: usize,
+}
+
+impl InputPlayer {
+    pub fn new(frames: Vec<JoypadState>) -> Self {
+        Self {
+            frames,
+            position: 0,
+        }
+    }
+
+    /// Create a player from a recording encoded with `InputRecorder::to_bytes()`
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self::new(
+            bytes
+                .iter()
+                .map(|byte| JoypadState::from_bits_retain(*byte))
+                .collect(),
+        )
+    }
+
+    /// Return the joypad state of the next frame, or `None` once every recorded frame
+    /// has been played
+    pub fn next_frame(&mut self) -> Option<JoypadState> {
+        let state = self.frames.get(self.position).copied()?;
+        self.position += 1;
+        Some(state)
+    }
+
+    /// Set the emulator's joypad to the state of the next frame.
+    ///
+    /// Returns `false` (leaving the joypad unchanged) once every recorded frame has
+    /// been played.
+    pub fn apply(&mut self, gb: &mut GameBoy) -> bool {
+        match self.next_frame() {
+            Some(state) => {
+                gb.set_joypad(state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Index of the next frame to play
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns `true` once every recorded frame has been played
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.frames.len()
+    }
+
+    /// Restart playback from the first frame
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_gameboy() -> GameBoy {
+        let rom = vec![0; 32 * 1024];
+        let mut boot_rom = vec![0; 0x0100];
+        // JR -2
+        boot_rom[..2].copy_from_slice(&[0x18, 0xFE]);
+        GameBoy::new(&rom, &boot_rom).unwrap()
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let inputs = [
+            JoypadState::empty(),
+            JoypadState::A,
+            JoypadState::A | JoypadState::Right,
+            JoypadState::Right,
+            JoypadState::Start,
+            JoypadState::empty(),
+            JoypadState::Up | JoypadState::Left | JoypadState::B,
+            JoypadState::Down,
+            JoypadState::all(),
+            JoypadState::Select,
+        ];
+
+        let mut gb = new_gameboy();
+        let mut recorder = InputRecorder::new();
+        for input in inputs {
+            gb.set_joypad(input);
+            gb.run_frame();
+            recorder.record_frame(&gb);
+        }
+        assert_eq!(recorder.len(), 10);
+        assert_eq!(recorder.frames(), inputs);
+
+        let bytes = recorder.to_bytes();
+        assert_eq!(bytes.len(), 10);
+        let mut player = InputPlayer::from_bytes(&bytes);
+        assert_eq!(player, recorder.clone().into_player());
+
+        let mut gb = new_gameboy();
+        let mut replayed = Vec::new();
+        while player.apply(&mut gb) {
+            gb.run_frame();
+            replayed.push(gb.joypad());
+        }
+        assert_eq!(replayed, inputs);
+        assert!(player.is_finished());
+        assert_eq!(player.position(), 10);
+        assert_eq!(player.next_frame(), None);
+
+        player.rewind();
+        assert_eq!(player.next_frame(), Some(JoypadState::empty()));
+    }
+}
@@ -1,27 +1,159 @@
 pub mod error;
+pub mod input;
+pub(crate) mod snapshot;
 pub mod state;
 
-use crate::{components::mmu, cpu::cpu, BootError, Color, JoypadButton, State};
+use std::collections::HashSet;
 
-use self::state::PollState;
+use crate::{
+    components::{
+        mmu::{self, ReadWriteMemory},
+        ppu::CYCLES_PER_FRAME,
+    },
+    cpu::{cpu, instruction::Instruction},
+    BootError, CartridgeOptions, Color, Header, JoypadButton, JoypadState, Palette, RomError,
+    SaveError, SerialSink, SnapshotError, State, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+};
+
+use self::{
+    snapshot::{Snapshot, SnapshotReader, SnapshotWriter, SNAPSHOT_MAGIC, SNAPSHOT_VERSION},
+    state::{InstructionDecoding, InstructionInfo, IoState, PollState},
+};
 
 pub type TCycles = i64;
 
+/// Frequency of the DMG master clock (in T-cycles per second)
+pub const CLOCK_HZ: u32 = 4_194_304;
+/// Rate at which the DMG refreshes the LCD (in frames per second, about 59.73 Hz)
+pub const FRAME_RATE: f64 = CLOCK_HZ as f64 / CYCLES_PER_FRAME as f64;
+
+/// Serial output which marks the end of a test ROM (as printed by Blargg's tests)
+const SERIAL_TEST_RESULTS: [&str; 2] = ["Passed", "Failed"];
+
+/// Parameters of the 64-bit FNV-1a hash used by `GameBoy::frame_hash()`
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// Parse the cartridge header of the given ROM without creating an emulator.
+///
+/// Unlike `GameBoy::new()`, the size of the ROM is not required to match the number
+/// of ROM banks given in the header.
+pub fn inspect_rom(rom: &[u8]) -> Result<Header, RomError> {
+    Header::parse(rom)
+}
+
+/// Return the length (in bytes), cycles without branch and cycles with branch of the
+/// instruction encoded at the start of `bytes`, or `None` if the first byte is not a
+/// valid opcode.
+///
+/// Only the opcode bytes are required (e.g., `[0xCB, 0x06]`); missing operands are
+/// treated as zero since they do not affect the timing.
+pub fn instruction_metadata(mut bytes: &[u8]) -> Option<(u16, TCycles, TCycles)> {
+    let instr = Instruction::try_from(&mut bytes).ok()?;
+    Some(Instruction::metadata(instr.opcode))
+}
+
 pub struct GameBoy {
     pub(crate) cpu: cpu::Cpu<mmu::Mmu>,
     pub state: State,
+    /// Screen produced by the most recent call to `run_frame()`
+    frame: Vec<Color>,
+    /// PPU frame count at the most recent call to `screen()`
+    screen_frame: u64,
+    /// Cycles run by `step()` since creation or the last call to `reset_cycle_counter()`
+    total_cycles: TCycles,
 }
 
 impl GameBoy {
     pub fn new(rom: &[u8], boot_rom: &[u8]) -> Result<Self, BootError> {
-        let mmu = mmu::Mmu::new(rom, boot_rom)?;
+        Self::new_with_options(rom, boot_rom, CartridgeOptions::default())
+    }
+
+    /// Create an emulator whose cartridge overrides values of its header with
+    /// `options` (e.g., the number of external RAM banks of a homebrew ROM)
+    pub fn new_with_options(
+        rom: &[u8],
+        boot_rom: &[u8],
+        options: CartridgeOptions,
+    ) -> Result<Self, BootError> {
+        Self::new_owned(rom.to_vec(), Some(boot_rom), options)
+    }
+
+    /// Create an emulator which takes ownership of the ROM buffer, instead of copying
+    /// it (e.g., when the ROM has just been read from a file).
+    ///
+    /// Without a boot ROM, the cartridge is started immediately, as with
+    /// `GameBoy::new_no_boot()`.
+    pub fn new_owned(
+        rom: Vec<u8>,
+        boot_rom: Option<&[u8]>,
+        options: CartridgeOptions,
+    ) -> Result<Self, BootError> {
+        let mmu = mmu::Mmu::new_owned(rom, boot_rom, options)?;
+        let header_checksum = mmu.cartridge_header().checksum;
+        let mut cpu = cpu::Cpu::new(mmu);
+        if boot_rom.is_none() {
+            cpu.init_post_boot(header_checksum);
+        }
+
+        Ok(Self {
+            cpu,
+            state: Default::default(),
+            frame: vec![Color::default(); DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            screen_frame: 0,
+            total_cycles: 0,
+        })
+    }
+
+    /// Create an emulator after checking that `boot_rom` looks like a DMG boot ROM: it
+    /// must contain the Nintendo logo and end by unmapping itself.
+    ///
+    /// Unlike `GameBoy::new()`, custom boot ROMs are likely to be rejected.
+    pub fn new_verified(rom: &[u8], boot_rom: &[u8]) -> Result<Self, BootError> {
+        let mmu = mmu::Mmu::new_verified(rom, boot_rom)?;
 
         Ok(Self {
             cpu: cpu::Cpu::new(mmu),
             state: Default::default(),
+            frame: vec![Color::default(); DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            screen_frame: 0,
+            total_cycles: 0,
         })
     }
 
+    /// Create an emulator which starts executing the cartridge immediately, without a
+    /// boot ROM.  The CPU and hardware registers are initialized to the values they
+    /// hold after the DMG boot ROM has finished.
+    pub fn new_no_boot(rom: &[u8]) -> Result<Self, BootError> {
+        Self::new_no_boot_with_options(rom, CartridgeOptions::default())
+    }
+
+    /// Create an emulator without a boot ROM (see `new_no_boot()`) whose cartridge
+    /// overrides values of its header with `options`
+    pub fn new_no_boot_with_options(
+        rom: &[u8],
+        options: CartridgeOptions,
+    ) -> Result<Self, BootError> {
+        Self::new_owned(rom.to_vec(), None, options)
+    }
+
+    /// Reset the emulator to its power-on state, as if it had just been created with
+    /// the same ROM (and boot ROM, if any).
+    ///
+    /// The contents of battery-backed cartridge RAM are kept, along with settings such
+    /// as watchpoints, the audio sample rate and the serial sink.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.cpu.mmu.reset();
+        if !self.cpu.mmu.has_boot_rom() {
+            let header_checksum = self.cpu.mmu.cartridge_header().checksum;
+            self.cpu.init_post_boot(header_checksum);
+        }
+        self.frame = vec![Color::default(); DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        self.screen_frame = 0;
+        self.total_cycles = 0;
+    }
+
     pub fn state(&mut self) -> &State {
         self.cpu.poll_state(&mut self.state);
         &self.state
@@ -31,23 +163,1663 @@ impl GameBoy {
         self.state.instruction_buffer_size = buffer_size;
     }
 
+    pub fn set_instruction_decoding(&mut self, decoding: InstructionDecoding) {
+        self.state.instruction_decoding = decoding;
+    }
+
+    /// Enable or disable disassembling the instruction buffer when the state is polled
+    pub fn set_disassembly_enabled(&mut self, enabled: bool) {
+        self.state.disassembly_enabled = enabled;
+    }
+
+    /// Enable or disable rendering the tile maps and tile data when the state is polled
+    pub fn set_tile_images_enabled(&mut self, enabled: bool) {
+        self.state.tile_images_enabled = enabled;
+    }
+
     pub fn step(&mut self) -> TCycles {
-        self.cpu.step()
+        let cycles = self.cpu.step();
+        self.total_cycles += cycles;
+        cycles
+    }
+
+    /// Number of cycles run since the emulator was created or reset, or since the last
+    /// call to `reset_cycle_counter()`.
+    pub fn total_cycles(&self) -> TCycles {
+        self.total_cycles
+    }
+
+    pub fn reset_cycle_counter(&mut self) {
+        self.total_cycles = 0;
+    }
+
+    /// Run the emulator until the next frame has been completed (i.e., until the PPU
+    /// enters VBlank) and return the rendered screen.
+    ///
+    /// If the LCD is off, the emulator is run for the duration of one frame instead.
+    pub fn run_frame(&mut self) -> &[Color] {
+        self.step_frame();
+        self.frame = self.screen();
+        &self.frame
+    }
+
+    /// Run `n` frames (see `run_frame()`) and return the screen rendered during the
+    /// last one.
+    ///
+    /// The intermediate frames are not rendered, which makes this suitable for
+    /// fast-forwarding.  The PPU timing and interrupts are unaffected, so the game
+    /// runs exactly as it would with `run_frame()`.  If `n` is 0, the emulator is not
+    /// run and the screen produced by the most recent frame is returned.
+    pub fn run_frames(&mut self, n: usize) -> &[Color] {
+        if n == 0 {
+            return &self.frame;
+        }
+        self.cpu.mmu.set_rendering_enabled(false);
+        for _ in 1..n {
+            self.step_frame();
+        }
+        self.cpu.mmu.set_rendering_enabled(true);
+        self.run_frame()
+    }
+
+    /// Run the emulator until the PPU enters VBlank, or for the duration of one frame
+    /// if the LCD is off.
+    ///
+    /// The frame is run inside an `info` level `frame` span, which records the number of
+    /// instructions executed and the elapsed cycles.
+    fn step_frame(&mut self) {
+        let frame_count = self.cpu.mmu.frame_count();
+        let span = tracing::info_span!(
+            target: "emulator",
+            "frame",
+            frame = frame_count,
+            instructions = tracing::field::Empty,
+            cycles = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+
+        let mut instructions: u64 = 0;
+        let mut cycles = 0;
+        while self.cpu.mmu.frame_count() == frame_count && cycles < CYCLES_PER_FRAME {
+            cycles += self.step();
+            instructions += 1;
+        }
+
+        span.record("instructions", instructions);
+        span.record("cycles", cycles);
+    }
+
+    /// Run the emulator until "Passed" or "Failed" is sent through the serial port (as
+    /// printed by Blargg's test ROMs), or until `max_cycles` T-cycles have elapsed.
+    /// Results printed before the call are ignored.
+    ///
+    /// Returns all of the serial output so far, interpreted as ASCII.  Any text printed
+    /// after the test result is not included.
+    pub fn run_until_serial(&mut self, max_cycles: TCycles) -> String {
+        // Only the bytes sent since the last check are searched, along with enough of
+        // the preceding output to find a result split across checks
+        let overlap = SERIAL_TEST_RESULTS
+            .iter()
+            .map(|result| result.len() - 1)
+            .max()
+            .unwrap_or_default();
+        let output_len = self.cpu.mmu.serial_output().len();
+        let mut tail: Vec<u8> = self
+            .cpu
+            .mmu
+            .serial_output()
+            .skip(output_len.saturating_sub(overlap))
+            .collect();
+        let mut byte_count = self.cpu.mmu.serial_byte_count();
+
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            cycles += self.step();
+            let new_count = self.cpu.mmu.serial_byte_count();
+            if new_count == byte_count {
+                continue;
+            }
+            let new_bytes = usize::try_from(new_count - byte_count).unwrap_or(usize::MAX);
+            byte_count = new_count;
+            let output_len = self.cpu.mmu.serial_output().len();
+            tail.extend(
+                self.cpu
+                    .mmu
+                    .serial_output()
+                    .skip(output_len.saturating_sub(new_bytes)),
+            );
+
+            let finished = SERIAL_TEST_RESULTS.iter().any(|result| {
+                tail.windows(result.len())
+                    .any(|window| window == result.as_bytes())
+            });
+            if finished {
+                break;
+            }
+            tail.drain(..tail.len().saturating_sub(overlap));
+        }
+
+        let io_state = IoState {
+            transmitted_bytes: self.cpu.mmu.serial_output().collect(),
+            ..Default::default()
+        };
+        io_state.transmitted_bytes_ascii()
+    }
+
+    /// Disassemble `count` instructions from memory starting at the address `start`
+    pub fn disassemble(&self, start: u16, count: usize) -> Vec<InstructionInfo> {
+        self.cpu.disassemble(start, count)
+    }
+
+    /// Format the current CPU state as a trace log line.
+    ///
+    /// The format is `A: 01 F: B0 B: 00 C: 13 D: 00 E: D8 H: 01 L: 4D SP: FFFE PC: 00:0100 (00 C3 13 02)`,
+    /// where the last four values are the bytes in memory starting at the program
+    /// counter.  This is not the Gameboy Doctor format
+    /// (`A:01 F:B0 ... SP:FFFE PC:0100 PCMEM:00,C3,13,02`), but the lines hold the same
+    /// values and are comparable via the frontend's reference-log parser.
+    pub fn trace_line(&self) -> String {
+        self.cpu.trace_line()
     }
 
     pub fn pc(&self) -> u16 {
         self.cpu.pc
     }
 
+    /// Returns `true` while the boot ROM is mapped, i.e., until the boot ROM hands
+    /// control to the cartridge.  Always `false` when running without a boot ROM.
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.cpu.mmu.boot_rom_mapped()
+    }
+
+    /// Step the emulator until the program counter equals `addr` or `max_cycles` have
+    /// been run, whichever happens first.
+    ///
+    /// Returns `true` if the target address was reached.  If the program counter
+    /// already equals `addr`, nothing is executed.
+    pub fn run_until(&mut self, addr: u16, max_cycles: TCycles) -> bool {
+        let mut cycles = 0;
+        while self.cpu.pc != addr {
+            if cycles >= max_cycles {
+                return false;
+            }
+            cycles += self.step();
+        }
+        true
+    }
+
+    /// Decode the instruction at the program counter without executing it
+    pub fn peek_instruction(&self) -> InstructionInfo {
+        self.disassemble(self.cpu.pc, 1).remove(0)
+    }
+
+    /// Execute the next instruction.  If it calls a subroutine (`CALL` or `RST`), keep
+    /// running until the subroutine returns to the following instruction.
+    ///
+    /// Returns `false` if the subroutine did not return within `max_cycles`.
+    pub fn step_over(&mut self, max_cycles: TCycles) -> bool {
+        let Some(instr) = self.next_instruction() else {
+            self.step();
+            return true;
+        };
+        if !instr.opcode.is_subroutine_call() {
+            self.step();
+            return true;
+        }
+
+        let return_addr = self.cpu.pc.wrapping_add(instr.length);
+        let sp = self.cpu.sp;
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            cycles += self.step();
+            // Recursive calls to the same subroutine return to the same address with a
+            // lower stack pointer
+            if self.cpu.pc == return_addr && self.cpu.sp >= sp {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Run until the current subroutine returns (i.e., until a `RET` or `RETI` pops
+    /// the return address of the current stack frame).
+    ///
+    /// Returns `false` if the subroutine did not return within `max_cycles`.
+    pub fn step_out(&mut self, max_cycles: TCycles) -> bool {
+        let sp = self.cpu.sp;
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            let returning = self
+                .next_instruction()
+                .is_some_and(|instr| instr.opcode.is_subroutine_return());
+            cycles += self.step();
+            if returning && self.cpu.sp > sp {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Decode the instruction at the program counter, if it is valid
+    fn next_instruction(&self) -> Option<Instruction> {
+        let bytes = self.cpu.mmu.read_range(self.cpu.pc, 3);
+        Instruction::try_from(&mut bytes.as_slice()).ok()
+    }
+
+    /// Return the interrupts which are both requested and enabled (i.e., `IF & IE`),
+    /// using the bit layout of the IF and IE registers (bit 0: VBlank, bit 1: LCD
+    /// STAT, bit 2: Timer, bit 3: Serial, bit 4: Joypad).
+    ///
+    /// Unlike the CPU, this does not select or acknowledge an interrupt.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.cpu.mmu.pending_interrupts().bits()
+    }
+
+    /// Returns `true` if the CPU is halted (i.e., waiting for an interrupt)
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    /// Returns `true` if the CPU is stopped
+    pub fn is_stopped(&self) -> bool {
+        self.cpu.is_stopped()
+    }
+
+    /// Notify the joypad that `button` is being held down
     pub fn button_pressed(&mut self, button: JoypadButton) {
         self.cpu.mmu.button_pressed(button);
     }
 
+    /// Notify the joypad that `button` has been released
     pub fn button_released(&mut self, button: JoypadButton) {
         self.cpu.mmu.button_released(button);
     }
 
-    pub fn screen(&self) -> Vec<Color> {
+    /// Return the joypad buttons which are held down
+    pub fn joypad(&self) -> JoypadState {
+        self.cpu.mmu.joypad()
+    }
+
+    /// Hold down every button in `state` and release all others, replacing the state
+    /// of the whole joypad in one call (e.g., when playing back recorded input)
+    pub fn set_joypad(&mut self, state: JoypadState) {
+        self.cpu.mmu.set_joypad(state);
+    }
+
+    /// Set a callback which is invoked with each byte sent through the serial port
+    /// (e.g., to stream the output of test ROMs), or remove it with `None`
+    pub fn set_serial_sink(&mut self, sink: Option<SerialSink>) {
+        self.cpu.mmu.set_serial_sink(sink);
+    }
+
+    /// Connect or disconnect a serial link partner (e.g., another `GameBoy`).
+    ///
+    /// While connected, transfers are not completed automatically.  Instead, whenever
+    /// `serial_outgoing()` returns a byte for one side, the driver should pass it to
+    /// the other side's `serial_exchange()`, and pass the result back to the first
+    /// side's `serial_exchange()`.
+    pub fn set_link_connected(&mut self, connected: bool) {
+        self.cpu.mmu.set_link_connected(connected);
+    }
+
+    /// Return the byte to be sent if a serial transfer has been started using the
+    /// internal clock (i.e., this side is the link master)
+    pub fn serial_outgoing(&self) -> Option<u8> {
+        self.cpu.mmu.serial_outgoing()
+    }
+
+    /// Exchange a byte with the serial link partner: `incoming` is shifted into SB
+    /// and the previous contents of SB are returned.  Any pending transfer is completed.
+    pub fn serial_exchange(&mut self, incoming: u8) -> u8 {
+        self.cpu.mmu.serial_exchange(incoming)
+    }
+
+    /// Enable or disable blocking CPU access to VRAM during PPU mode 3 and to OAM
+    /// during PPU modes 2 and 3, where reads return 0xFF and writes are ignored.
+    ///
+    /// This is enabled by default.
+    pub fn set_strict_vram_timing(&mut self, strict: bool) {
+        self.cpu.mmu.set_strict_vram_timing(strict);
+    }
+
+    /// Enable or disable open bus emulation, where reading from an unmapped address
+    /// returns the last value transferred on the data bus instead of 0xFF.
+    ///
+    /// This is disabled by default.
+    pub fn set_open_bus_emulation(&mut self, enabled: bool) {
+        self.cpu.mmu.set_open_bus_emulation(enabled);
+    }
+
+    /// Enable or disable the CGB registers supported by the emulator.  Currently, this
+    /// only maps KEY1 (0xFF4D), which allows a program to switch to double speed mode
+    /// by executing STOP.
+    ///
+    /// This is disabled by default.
+    pub fn set_cgb_enabled(&mut self, enabled: bool) {
+        self.cpu.mmu.set_cgb_enabled(enabled);
+    }
+
+    /// Returns `true` if the CPU is running in CGB double speed mode
+    pub fn double_speed(&self) -> bool {
+        self.cpu.mmu.double_speed()
+    }
+
+    /// If `suppress` is `true`, the joypad never reports opposite directions (Left
+    /// and Right, or Up and Down) as held down at the same time, which is impossible on
+    /// real hardware.  The most recently pressed direction takes precedence.
+    ///
+    /// This is disabled by default.
+    pub fn set_suppress_opposite_directions(&mut self, suppress: bool) {
+        self.cpu.mmu.set_suppress_opposite_directions(suppress);
+    }
+
+    /// Return the current screen, in row-major order.
+    ///
+    /// The returned vector always contains `DISPLAY_WIDTH * DISPLAY_HEIGHT` colors.
+    /// Calling this method clears the flag returned by `frame_ready()`.
+    pub fn screen(&mut self) -> Vec<Color> {
+        self.screen_frame = self.cpu.mmu.frame_count();
         self.cpu.mmu.screen()
     }
+
+    /// Render all 384 tiles in VRAM as a grid of 16 by 24 tiles (128 by 192 pixels, in
+    /// row-major order) using the BG palette.  This is useful for inspecting the
+    /// tileset independently of the tile maps.
+    pub fn tile_atlas(&self) -> Vec<Color> {
+        self.cpu.mmu.tile_atlas()
+    }
+
+    /// Return the current screen as a buffer of RGBA8888 pixels, ready to be copied to
+    /// a texture.
+    ///
+    /// The returned vector always contains `DISPLAY_WIDTH * DISPLAY_HEIGHT * 4` bytes.
+    /// Unlike `screen()`, this method does not clear the flag returned by
+    /// `frame_ready()`.
+    pub fn frame_rgba(&self, palette: &Palette) -> Vec<u8> {
+        palette.rgba(&self.cpu.mmu.screen())
+    }
+
+    /// Return a 64-bit FNV-1a hash of the current screen.
+    ///
+    /// The hash only depends on the shade of each pixel, so it is stable across
+    /// platforms and releases and can be compared against a value recorded in a
+    /// regression test.  Like `frame_rgba()`, this method does not clear the flag
+    /// returned by `frame_ready()`.
+    pub fn frame_hash(&self) -> u64 {
+        self.cpu
+            .mmu
+            .screen()
+            .into_iter()
+            .fold(FNV_OFFSET_BASIS, |hash, color| {
+                (hash ^ u64::from(u8::from(color))).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// Returns `true` if the PPU has completed a frame (i.e., entered VBlank) since
+    /// the last call to `screen()`.
+    ///
+    /// The flag is cleared by `screen()` (and hence by `run_frame()`), so frontends
+    /// can use it to only update their display when a new frame is available.
+    pub fn frame_ready(&self) -> bool {
+        self.cpu.mmu.frame_count() != self.screen_frame
+    }
+
+    /// Return the current audio output as a `(left, right)` pair of samples, each in
+    /// the range `-1.0..=1.0`
+    pub fn audio_sample(&self) -> (f32, f32) {
+        self.cpu.mmu.audio_sample()
+    }
+
+    /// Set the rate (in Hz) at which the audio output is sampled into the buffer
+    /// returned by `audio_samples()`.  The default rate is `DEFAULT_SAMPLE_RATE`.
+    ///
+    /// Any buffered samples are discarded.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.mmu.set_audio_sample_rate(sample_rate);
+    }
+
+    /// Remove and return the audio samples produced since the last call.
+    ///
+    /// Samples are interleaved stereo (`AUDIO_CHANNELS` channels, left followed by
+    /// right), with each sample in the range `-1.0..=1.0`.  The master volume (NR50)
+    /// and panning (NR51) have already been applied.  At most one second of audio is
+    /// buffered; older samples are discarded if the buffer is not drained.
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.mmu.audio_samples()
+    }
+
+    /// Set the addresses which are watched for writes by the CPU
+    pub fn set_watchpoints(&mut self, watchpoints: HashSet<u16>) {
+        self.cpu.mmu.set_watchpoints(watchpoints);
+    }
+
+    /// Remove and return the first write (address and value) to a watched address
+    /// since the last call, if any
+    pub fn take_watchpoint_hit(&mut self) -> Option<(u16, u8)> {
+        self.cpu.mmu.take_watchpoint_hit()
+    }
+
+    /// Returns `true` if the cartridge has battery-backed external RAM
+    pub fn has_battery(&self) -> bool {
+        self.cpu.mmu.has_battery()
+    }
+
+    /// Return the entire contents of the cartridge ROM
+    pub fn rom(&self) -> &[u8] {
+        self.cpu.mmu.rom()
+    }
+
+    /// Return the indices of the cartridge ROM banks currently mapped to
+    /// 0x0000..=0x3FFF and 0x4000..=0x7FFF
+    pub fn current_rom_bank(&self) -> (usize, usize) {
+        self.cpu.mmu.current_rom_bank()
+    }
+
+    /// Return the index of the external RAM bank currently mapped to 0xA000..=0xBFFF
+    pub fn current_ram_bank(&self) -> usize {
+        self.cpu.mmu.current_ram_bank()
+    }
+
+    /// Return a copy of the battery-backed external RAM, or `None` if the cartridge
+    /// does not have a battery
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        if !self.has_battery() {
+            return None;
+        }
+        self.cpu.mmu.ram_bytes().map(|ram| ram.to_vec())
+    }
+
+    /// Restore the battery-backed external RAM (e.g., from a save file)
+    pub fn load_ram(&mut self, ram: &[u8]) -> Result<(), SaveError> {
+        if !self.has_battery() {
+            return Err(SaveError::NoBattery);
+        }
+        self.cpu.mmu.load_ram(ram)
+    }
+
+    /// Capture the complete state of the emulator (CPU, memory, PPU, timers, IO and
+    /// cartridge) as a save state.
+    ///
+    /// The snapshot can only be restored into a `GameBoy` running the same ROM.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        for byte in SNAPSHOT_MAGIC {
+            writer.write_u8(byte);
+        }
+        writer.write_u8(SNAPSHOT_VERSION);
+        self.write_cartridge_identity(&mut writer);
+        self.cpu.save_snapshot(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restore a save state created by `snapshot()`.
+    ///
+    /// The restore is atomic: if the snapshot is invalid (e.g., truncated or corrupt),
+    /// an error is returned and the state of the emulator is left unchanged.
+    pub fn restore(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = SnapshotReader::new(data);
+        self.verify_snapshot_header(&mut reader)?;
+
+        // Keep the current state, so a snapshot which turns out to be corrupt part way
+        // through can be rolled back
+        let backup = self.snapshot();
+        if let Err(err) = self.restore_components(&mut reader) {
+            let mut reader = SnapshotReader::new(&backup);
+            self.verify_snapshot_header(&mut reader)
+                .and_then(|_| self.restore_components(&mut reader))
+                .expect("a snapshot of the current state can be restored");
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Verify the header, version and cartridge of a snapshot
+    fn verify_snapshot_header(&self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for byte in SNAPSHOT_MAGIC {
+            if reader.read_u8().map_err(|_| SnapshotError::InvalidHeader)? != byte {
+                return Err(SnapshotError::InvalidHeader);
+            }
+        }
+        let version = reader.read_u8()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version {
+                expected: SNAPSHOT_VERSION,
+                found: version,
+            });
+        }
+
+        let mut identity = SnapshotWriter::new();
+        self.write_cartridge_identity(&mut identity);
+        for byte in identity.into_bytes() {
+            if reader.read_u8()? != byte {
+                return Err(SnapshotError::CartridgeMismatch);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore the state of every component, following the snapshot header
+    fn restore_components(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.cpu.restore_snapshot(reader)?;
+        if !reader.is_empty() {
+            return Err(SnapshotError::TrailingData);
+        }
+        Ok(())
+    }
+
+    /// Write the values identifying the inserted cartridge
+    fn write_cartridge_identity(&self, writer: &mut SnapshotWriter) {
+        let header = self.cpu.mmu.cartridge_header();
+        writer.write_u8(header.checksum);
+        writer.write_u64(header.rom_banks as u64);
+        writer.write_bytes(header.title.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        components::{
+            interrupts::Interrupt,
+            mmu::{ReadWriteMemory, INTERRUPT_ENABLE_REG, INTERRUPT_FLAG, PPU_LY},
+        },
+        AUDIO_CHANNELS,
+    };
+
+    use std::{
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    fn new_gameboy(boot_rom: &[u8]) -> GameBoy {
+        let rom = vec![0; 32 * 1024];
+        let mut boot = vec![0; 0x0100];
+        boot[..boot_rom.len()].copy_from_slice(boot_rom);
+        GameBoy::new(&rom, &boot).unwrap()
+    }
+
+    #[test]
+    fn halted() {
+        let mut gb = new_gameboy(&[0x76]); // HALT
+        assert!(!gb.is_halted());
+        gb.step();
+        assert!(gb.is_halted());
+        assert!(!gb.is_stopped());
+    }
+
+    #[test]
+    fn stopped() {
+        let mut gb = new_gameboy(&[0x10, 0x00]); // STOP
+        assert!(!gb.is_stopped());
+        gb.step();
+        assert!(gb.is_stopped());
+        assert!(!gb.is_halted());
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let mut gb = new_gameboy(&[
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0xFB, // EI
+            0x00, // NOP
+            0x00, // NOP
+        ]);
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x01);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x01);
+
+        gb.step();
+        gb.step();
+        assert!(!gb.cpu.ime);
+        gb.step();
+        assert!(gb.cpu.ime);
+
+        assert_eq!(gb.step(), 20);
+        assert_eq!(gb.pc(), Interrupt::VBlank.handler_address());
+        assert!(!gb.cpu.ime);
+        assert_eq!(gb.cpu.mmu.read(INTERRUPT_FLAG) & 0x1F, 0x00);
+        assert_eq!(gb.cpu.sp, 0xFFFC);
+        assert_eq!(gb.cpu.mmu.read(0xFFFC), 0x05);
+        assert_eq!(gb.cpu.mmu.read(0xFFFD), 0x00);
+    }
+
+    #[test]
+    fn ei_delay() {
+        let mut gb = new_gameboy(&[
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0xFB, // EI
+            0x00, // NOP
+            0x00, // NOP
+        ]);
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x04);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x04);
+
+        gb.step();
+        gb.step();
+        assert_eq!(gb.pc(), 0x0004);
+
+        // The interrupt is only serviced after the NOP following EI
+        gb.step();
+        assert_eq!(gb.pc(), 0x0005);
+        gb.step();
+        assert_eq!(gb.pc(), Interrupt::Timer.handler_address());
+    }
+
+    #[test]
+    fn ei_di() {
+        let mut gb = new_gameboy(&[
+            0xFB, // EI
+            0xF3, // DI
+            0x00, // NOP
+            0x00, // NOP
+        ]);
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x04);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x04);
+
+        gb.step();
+        gb.step();
+        assert!(!gb.cpu.ime);
+        gb.step();
+        gb.step();
+        assert_eq!(gb.pc(), 0x0004);
+    }
+
+    #[test]
+    fn ei_before_dispatch() {
+        let mut gb = new_gameboy(&[
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0xFB, // EI
+            0x00, // NOP
+        ]);
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x04);
+        gb.step();
+        gb.cpu.ime = true;
+
+        // EI while interrupts are already enabled, then dispatch an interrupt
+        gb.step();
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x04);
+        gb.step();
+        assert_eq!(gb.pc(), Interrupt::Timer.handler_address());
+
+        // The pending EI does not re-enable interrupts inside the handler
+        gb.step();
+        assert_eq!(gb.pc(), Interrupt::Timer.handler_address() + 1);
+        assert!(!gb.cpu.ime);
+    }
+
+    #[test]
+    fn ly_advances() {
+        // Wait for VBlank by polling LY, as the boot ROM does
+        let mut gb = new_gameboy(&[
+            0x3E, 0x80, // LD A, $80
+            0xE0, 0x40, // LDH ($40), A
+            0xF0, 0x44, // LDH A, ($44)
+            0xFE, 0x90, // CP $90
+            0x20, 0xFA, // JR NZ, -6
+            0x18, 0xFE, // JR -2
+        ]);
+
+        let mut ly_values = Vec::new();
+        for _ in 0..100_000 {
+            if gb.pc() == 0x000A {
+                break;
+            }
+            gb.step();
+            ly_values.push(gb.cpu.mmu.read(PPU_LY));
+        }
+        assert_eq!(gb.pc(), 0x000A);
+        assert!(ly_values.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(ly_values.last(), Some(&0x90));
+    }
+
+    #[test]
+    fn battery_ram() {
+        // MBC1+RAM+BATTERY with a single 8 KiB RAM bank
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        let boot_rom = vec![0; 0x0100];
+        let mut gb = GameBoy::new(&rom, &boot_rom).unwrap();
+        assert!(gb.has_battery());
+
+        gb.cpu.mmu.write(0x0000, 0x0A);
+        gb.cpu.mmu.write(0xA000, 0x12);
+        gb.cpu.mmu.write(0xBFFF, 0x34);
+        let ram = gb.save_ram().unwrap();
+        assert_eq!(ram.len(), 8 * 1024);
+
+        let mut gb = GameBoy::new(&rom, &boot_rom).unwrap();
+        gb.load_ram(&ram).unwrap();
+        gb.cpu.mmu.write(0x0000, 0x0A);
+        assert_eq!(gb.cpu.mmu.read(0xA000), 0x12);
+        assert_eq!(gb.cpu.mmu.read(0xBFFF), 0x34);
+
+        assert!(matches!(
+            gb.load_ram(&ram[1..]),
+            Err(SaveError::Size {
+                expected: 8192,
+                found: 8191
+            })
+        ));
+
+        // Cartridges without a battery are not saved
+        let gb = new_gameboy(&[]);
+        assert!(!gb.has_battery());
+        assert!(gb.save_ram().is_none());
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut gb = new_gameboy(&[
+            0x3E, 0x80, // LD A, $80
+            0xE0, 0x40, // LDH ($40), A
+            0x18, 0xFE, // JR -2
+        ]);
+
+        let screen = gb.run_frame().to_vec();
+        assert_eq!(screen.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+        assert_eq!(screen, gb.screen());
+        assert_eq!(gb.cpu.mmu.frame_count(), 1);
+        assert_eq!(gb.cpu.mmu.read(PPU_LY), 144);
+
+        gb.run_frame();
+        assert_eq!(gb.cpu.mmu.frame_count(), 2);
+        assert_eq!(gb.cpu.mmu.read(PPU_LY), 144);
+    }
+
+    #[test]
+    fn run_frame_lcd_off() {
+        let mut gb = new_gameboy(&[0x18, 0xFE]); // JR -2
+        gb.run_frame();
+        assert_eq!(gb.cpu.mmu.frame_count(), 0);
+        assert_eq!(gb.pc(), 0x0000);
+    }
+
+    #[test]
+    fn screen() {
+        let mut gb = new_gameboy(&[]);
+        assert_eq!(gb.screen().len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+    }
+
+    /// Boot ROM program that enables the timer and fills WRAM in a loop
+    const SNAPSHOT_PROGRAM: [u8; 10] = [
+        0x3E, 0x05, // LD A, 0x05
+        0xE0, 0x07, // LDH (TAC), A
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0x3C, // INC A
+        0x22, // LD (HL+), A
+        0x18, // JR -4
+    ];
+
+    fn snapshot_gameboy() -> GameBoy {
+        let mut program = SNAPSHOT_PROGRAM.to_vec();
+        program.push(0xFC);
+        new_gameboy(&program)
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut gb = snapshot_gameboy();
+        for _ in 0..10_000 {
+            gb.step();
+        }
+        let snapshot = gb.snapshot();
+        assert_eq!(&snapshot[..4], b"QGBS");
+
+        let mut restored = snapshot_gameboy();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+
+        // Both machines continue identically
+        for _ in 0..10_000 {
+            assert_eq!(gb.step(), restored.step());
+            assert_eq!(gb.pc(), restored.pc());
+        }
+        assert_eq!(gb.snapshot(), restored.snapshot());
+        assert_ne!(gb.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn snapshot_errors() {
+        let mut gb = snapshot_gameboy();
+        let snapshot = gb.snapshot();
+
+        assert!(matches!(
+            gb.restore(b"NOPE"),
+            Err(SnapshotError::InvalidHeader)
+        ));
+
+        let mut wrong_version = snapshot.clone();
+        wrong_version[4] = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            gb.restore(&wrong_version),
+            Err(SnapshotError::Version { .. })
+        ));
+
+        assert!(matches!(
+            gb.restore(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::UnexpectedEnd)
+        ));
+
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert!(matches!(
+            gb.restore(&trailing),
+            Err(SnapshotError::TrailingData)
+        ));
+
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0134] = b'A';
+        let mut other = GameBoy::new(&rom, &[0; 0x0100]).unwrap();
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(SnapshotError::CartridgeMismatch)
+        ));
+    }
+
+    #[test]
+    fn restore_is_atomic() {
+        let mut gb = snapshot_gameboy();
+        for _ in 0..10_000 {
+            gb.step();
+        }
+        let snapshot = gb.snapshot();
+        for _ in 0..1_000 {
+            gb.step();
+        }
+        let trace_line = gb.trace_line();
+        let current = gb.snapshot();
+
+        // The snapshot ends after part of the machine has been restored
+        assert!(matches!(
+            gb.restore(&snapshot[..snapshot.len() / 2]),
+            Err(SnapshotError::UnexpectedEnd)
+        ));
+        assert_eq!(gb.trace_line(), trace_line);
+        assert_eq!(gb.snapshot(), current);
+
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert!(matches!(
+            gb.restore(&trailing),
+            Err(SnapshotError::TrailingData)
+        ));
+        assert_eq!(gb.snapshot(), current);
+
+        gb.restore(&snapshot).unwrap();
+        assert_eq!(gb.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn snapshot_boot_rom_mapped() {
+        let rom = vec![0; 32 * 1024];
+        let gb = GameBoy::new(&rom, &[0; 0x0100]).unwrap();
+        assert!(gb.boot_rom_mapped());
+        let snapshot = gb.snapshot();
+
+        // The snapshot maps a boot ROM which this emulator does not have
+        let mut no_boot = GameBoy::new_no_boot(&rom).unwrap();
+        assert!(matches!(
+            no_boot.restore(&snapshot),
+            Err(SnapshotError::InvalidValue("boot mode"))
+        ));
+
+        // Restoring the other way around is allowed
+        let mut gb = GameBoy::new(&rom, &[0; 0x0100]).unwrap();
+        gb.restore(&GameBoy::new_no_boot(&rom).unwrap().snapshot())
+            .unwrap();
+        assert!(!gb.boot_rom_mapped());
+        gb.step();
+        assert_eq!(gb.pc(), 0x0101);
+    }
+
+    #[test]
+    fn audio_samples() {
+        let mut gb = new_gameboy(&[0x18, 0xFE]); // JR -2
+        gb.run_frame();
+        let samples = gb.audio_samples();
+        // 70224 cycles per frame at 44100 Hz is just over 738 stereo samples
+        assert_eq!(samples.len(), 738 * AUDIO_CHANNELS);
+        assert!(gb.audio_samples().is_empty());
+
+        gb.set_audio_sample_rate(22_050);
+        gb.run_frame();
+        assert_eq!(gb.audio_samples().len(), 369 * AUDIO_CHANNELS);
+    }
+
+    #[test]
+    fn stopped_until_input() {
+        let mut gb = new_gameboy(&[0x10, 0x00, 0x00]); // STOP; NOP
+        gb.step();
+        assert!(gb.is_stopped());
+        assert_eq!(gb.pc(), 0x0002);
+
+        // Interrupts do not leave stop mode
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x01);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x01);
+        for _ in 0..100 {
+            assert_eq!(gb.step(), 4);
+        }
+        assert!(gb.is_stopped());
+        assert_eq!(gb.pc(), 0x0002);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x00);
+
+        // Pressing a button selected by P1 wakes the CPU
+        gb.button_pressed(JoypadButton::Start);
+        gb.step();
+        assert!(!gb.is_stopped());
+        assert_eq!(gb.pc(), 0x0003);
+    }
+
+    #[test]
+    fn stop_with_button_held() {
+        // Without a pending interrupt, STOP is a 2-byte instruction that halts the CPU
+        let mut gb = new_gameboy(&[0x10, 0x00, 0x00]); // STOP; NOP
+        gb.button_pressed(JoypadButton::A);
+        gb.step();
+        assert!(!gb.is_stopped());
+        assert!(gb.is_halted());
+        assert_eq!(gb.pc(), 0x0002);
+
+        // With a pending interrupt, STOP is a 1-byte instruction that does nothing
+        let mut gb = new_gameboy(&[0x10, 0x00, 0x00]);
+        gb.button_pressed(JoypadButton::A);
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x01);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x01);
+        gb.step();
+        assert!(!gb.is_stopped());
+        assert!(!gb.is_halted());
+        assert_eq!(gb.pc(), 0x0001);
+    }
+
+    #[test]
+    fn inspect_rom_header() {
+        // 48 KiB dump of a 32 KiB MBC1 cartridge
+        let mut rom = vec![0; 48 * 1024];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom[0x0147] = 0x01;
+
+        let header = inspect_rom(&rom).unwrap();
+        assert_eq!(header.title, "TEST");
+        assert!(matches!(header.cartridge_type, crate::CartridgeType::Mbc1));
+        assert_eq!(header.rom_banks, 2);
+        assert_eq!(header.ram_banks, 0);
+        assert!(matches!(
+            GameBoy::new(&rom, &[0; 0x0100]),
+            Err(BootError::RomError(RomError::Size { .. }))
+        ));
+
+        assert!(matches!(
+            inspect_rom(&rom[..0x0150]),
+            Err(RomError::Undersized { .. })
+        ));
+    }
+
+    #[test]
+    fn disassemble() {
+        let gb = new_gameboy(&[
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0xCB, 0x7C, // BIT 7, H
+            0xD3, // Illegal opcode
+            0x00, // NOP
+        ]);
+
+        let instructions = gb.disassemble(0x0000, 4);
+        let addresses: Vec<u16> = instructions.iter().map(|info| info.address).collect();
+        assert_eq!(addresses, vec![0x0000, 0x0003, 0x0005, 0x0006]);
+        assert_eq!(instructions[0].bytes, vec![0x31, 0xFE, 0xFF]);
+        assert_eq!(instructions[1].bytes, vec![0xCB, 0x7C]);
+        assert_eq!(instructions[2].display, "DATA D3");
+        assert_eq!(instructions[2].bytes, vec![0xD3]);
+        assert_eq!(instructions[3].bytes, vec![0x00]);
+
+        // Disassembling does not affect the emulator
+        assert_eq!(gb.pc(), 0x0000);
+        assert_eq!(
+            gb.disassemble(0x0003, 1)[0].display,
+            instructions[1].display
+        );
+    }
+
+    #[test]
+    fn trace_line() {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x13, 0x02]);
+        let mut boot = vec![0; 0x0100];
+        boot[..22].copy_from_slice(&[
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0x01, 0xB0, 0x01, // LD BC, $01B0
+            0xC5, // PUSH BC
+            0xF1, // POP AF
+            0x01, 0x13, 0x00, // LD BC, $0013
+            0x11, 0xD8, 0x00, // LD DE, $00D8
+            0x21, 0x4D, 0x01, // LD HL, $014D
+            0xE0, 0x50, // LDH ($50), A
+            0xC3, 0x00, 0x01, // JP $0100
+        ]);
+        let mut gb = GameBoy::new(&rom, &boot).unwrap();
+        while gb.pc() != 0x0100 {
+            gb.step();
+        }
+
+        assert_eq!(
+            gb.trace_line(),
+            "A: 01 F: B0 B: 00 C: 13 D: 00 E: D8 H: 01 L: 4D SP: FFFE PC: 00:0100 (00 C3 13 02)"
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut gb = new_gameboy(&[
+            0x31, 0x00, 0xD0, // LD SP, $D000
+            0x21, 0x00, 0xC0, // LD HL, $C000
+            0x36, 0x42, // LD (HL), $42
+            0xC5, // PUSH BC
+            0x36, 0x43, // LD (HL), $43
+        ]);
+        gb.set_watchpoints(HashSet::from([0xC000, 0xCFFF]));
+
+        gb.step();
+        gb.step();
+        assert_eq!(gb.take_watchpoint_hit(), None);
+        gb.step();
+        assert_eq!(gb.take_watchpoint_hit(), Some((0xC000, 0x42)));
+        assert_eq!(gb.take_watchpoint_hit(), None);
+
+        // Only the high byte of a pushed value is written to a watched address
+        gb.step();
+        assert_eq!(gb.take_watchpoint_hit(), Some((0xCFFF, 0x00)));
+
+        gb.set_watchpoints(HashSet::new());
+        gb.step();
+        assert_eq!(gb.take_watchpoint_hit(), None);
+    }
+
+    #[test]
+    fn instruction_buffer_size() {
+        let mut gb = new_gameboy(&[]);
+        for size in [1, 200] {
+            gb.set_instruction_buffer_size(size);
+            let state = gb.state();
+            assert_eq!(state.instruction_buffer_size(), size);
+            let instructions = &state.cpu.as_ref().unwrap().instructions;
+            assert_eq!(instructions.len(), size);
+            assert_eq!(instructions[0].address, 0x0000);
+            assert_eq!(instructions[size - 1].address, size as u16 - 1);
+        }
+    }
+
+    #[test]
+    fn new_no_boot() {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0000] = 0x12;
+        rom[0x014D] = 0x5A;
+        let gb = GameBoy::new_no_boot(&rom).unwrap();
+        assert_eq!(
+            gb.trace_line(),
+            "A: 01 F: B0 B: 00 C: 13 D: 00 E: D8 H: 01 L: 4D SP: FFFE PC: 00:0100 (00 00 00 00)"
+        );
+        assert!(!gb.cpu.ime);
+
+        // The cartridge ROM is mapped at 0x0000
+        assert_eq!(gb.cpu.mmu.read(0x0000), 0x12);
+        assert_eq!(gb.cpu.mmu.read(0xFF04), 0xAB); // DIV
+        assert_eq!(gb.cpu.mmu.read(0xFF0F), 0xE1); // IF
+        assert_eq!(gb.cpu.mmu.read(0xFF24), 0x77); // NR50
+        assert_eq!(gb.cpu.mmu.read(0xFF25), 0xF3); // NR51
+        assert_eq!(gb.cpu.mmu.read(0xFF40), 0x91); // LCDC
+        assert_eq!(gb.cpu.mmu.read(0xFF47), 0xFC); // BGP
+
+        // The H and C flags depend on the header checksum
+        rom[0x014D] = 0x00;
+        let gb = GameBoy::new_no_boot(&rom).unwrap();
+        assert!(gb.trace_line().starts_with("A: 01 F: 80 "));
+    }
+
+    #[test]
+    fn frame_ready() {
+        let mut gb = new_gameboy(&[
+            0x3E, 0x80, // LD A, $80
+            0xE0, 0x40, // LDH ($40), A
+            0x18, 0xFE, // JR -2
+        ]);
+        assert!(!gb.frame_ready());
+
+        // The flag is set once per frame, when the PPU enters VBlank
+        let mut frames = 0;
+        let mut cycles = 0;
+        while cycles < 3 * CYCLES_PER_FRAME {
+            cycles += gb.step();
+            if gb.frame_ready() {
+                frames += 1;
+                assert_eq!(gb.cpu.mmu.read(PPU_LY), 144);
+                gb.screen();
+                assert!(!gb.frame_ready());
+            }
+        }
+        assert_eq!(frames, 3);
+
+        // Frames which are not consumed are coalesced
+        gb.run_frame();
+        assert!(!gb.frame_ready());
+        for _ in 0..2 * CYCLES_PER_FRAME / 4 {
+            gb.step();
+        }
+        assert!(gb.frame_ready());
+        gb.screen();
+        assert!(!gb.frame_ready());
+    }
+
+    /// Boot ROM program that prints the null-terminated string at 0x0020 over serial
+    /// and then loops forever
+    fn serial_print_program(msg: &str) -> Vec<u8> {
+        let mut program = vec![
+            0x21, 0x20, 0x00, // LD HL, $0020
+            0x2A, //             LD A, (HL+)
+            0xB7, //             OR A
+            0x28, 0xFE, //       JR Z, -2
+            0xE0, 0x01, //       LDH ($01), A
+            0x3E, 0x81, //       LD A, $81
+            0xE0, 0x02, //       LDH ($02), A
+            0x18, 0xF4, //       JR -12
+        ];
+        program.resize(0x20, 0);
+        program.extend_from_slice(msg.as_bytes());
+        program.push(0);
+        program
+    }
+
+    #[test]
+    fn run_until_serial() {
+        let mut gb = new_gameboy(&serial_print_program("cpu_instrs\n\nPassed all tests\n"));
+        assert_eq!(gb.run_until_serial(100_000), "cpu_instrs\n\nPassed");
+
+        let mut gb = new_gameboy(&serial_print_program("01:ok 02:Failed #3"));
+        assert_eq!(gb.run_until_serial(100_000), "01:ok 02:Failed");
+
+        // Only results printed after the call are detected
+        let mut gb = new_gameboy(&serial_print_program("Failed #1\nPassed"));
+        assert_eq!(gb.run_until_serial(100_000), "Failed");
+        assert_eq!(gb.run_until_serial(100_000), "Failed #1\nPassed");
+
+        // Without a result, the emulator runs for `max_cycles`
+        let mut gb = new_gameboy(&serial_print_program("01:ok"));
+        assert_eq!(gb.run_until_serial(100_000), "01:ok");
+    }
+
+    /// Run Blargg's `cpu_instrs.gb` test ROM, whose path is given by the
+    /// `QGB_CPU_INSTRS_ROM` environment variable
+    #[test]
+    #[ignore = "requires the cpu_instrs.gb test ROM"]
+    fn blargg_cpu_instrs() {
+        let path = std::env::var("QGB_CPU_INSTRS_ROM").unwrap();
+        let rom = std::fs::read(path).unwrap();
+        let mut gb = GameBoy::new_no_boot(&rom).unwrap();
+        let output = gb.run_until_serial(60 * TCycles::from(CLOCK_HZ));
+        assert!(output.contains("Passed"), "{}", output);
+    }
+
+    #[test]
+    fn reset() {
+        let mut gb = new_gameboy(&[
+            0x3E, 0x12, //       LD A, $12
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0xEA, 0x00, 0xC0, // LD ($C000), A
+            0xE0, 0x80, //       LDH ($80), A
+            0x3E, 0x80, //       LD A, $80
+            0xE0, 0x40, //       LDH ($40), A
+            0x18, 0xFE, //       JR -2
+        ]);
+        let initial = gb.trace_line();
+        for _ in 0..100 {
+            gb.step();
+        }
+        assert_eq!(gb.pc(), 0x000E);
+        assert_eq!(gb.cpu.mmu.read(0xC000), 0x12);
+
+        gb.reset();
+        assert_eq!(gb.trace_line(), initial);
+        assert_eq!(gb.cpu.sp, 0);
+        assert_eq!(gb.cpu.mmu.read(0xC000), 0x00);
+        assert_eq!(gb.cpu.mmu.read(0xFF80), 0x00);
+        assert_eq!(gb.cpu.mmu.read(0xFF40), 0x00);
+        assert_eq!(gb.cpu.mmu.frame_count(), 0);
+        gb.step();
+        assert_eq!(gb.cpu.a, 0x12);
+
+        // Without a boot ROM, the post-boot state is restored
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0100] = 0x3C; // INC A
+        let mut gb = GameBoy::new_no_boot(&rom).unwrap();
+        let initial = gb.trace_line();
+        gb.step();
+        assert_eq!(gb.cpu.a, 0x02);
+        gb.reset();
+        assert_eq!(gb.trace_line(), initial);
+        assert_eq!(gb.cpu.mmu.read(0xFF40), 0x91);
+    }
+
+    #[test]
+    fn clock_constants() {
+        assert_eq!(CLOCK_HZ, 4_194_304);
+        assert_eq!(CYCLES_PER_FRAME, 70224);
+        assert!((FRAME_RATE - 59.7275).abs() < 0.0001);
+    }
+
+    #[test]
+    fn run_frames() {
+        // Program which turns on the LCD and then sets SCX to a different value each
+        // frame from the VBlank interrupt handler
+        let mut boot_rom = vec![
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0x3E, 0x80, //       LD A, $80
+            0xE0, 0x40, //       LDH ($40), A
+            0x3E, 0x01, //       LD A, $01
+            0xE0, 0xFF, //       LDH ($FF), A
+            0xFB, //             EI
+            0x18, 0xFE, //       JR -2
+        ];
+        boot_rom.resize(0x40, 0);
+        boot_rom.extend_from_slice(&[
+            0x0C, //       INC C
+            0x79, //       LD A, C
+            0xE0, 0x43, // LDH ($43), A
+            0xD9, //       RETI
+        ]);
+        let mut gb = new_gameboy(&boot_rom);
+        gb.cpu.mmu.write(0x8010, 0xFF);
+        gb.cpu.mmu.write(0x9800, 0x01);
+        gb.cpu.mmu.write(0xFF47, 0xE4);
+
+        let screen = gb.run_frames(100).to_vec();
+        assert_eq!(gb.cpu.mmu.frame_count(), 100);
+        assert_eq!(gb.cpu.c, 99);
+        assert_eq!(screen, gb.screen());
+
+        // Only the last frame was rendered, with SCX = 99
+        let mut expected = new_gameboy(&boot_rom);
+        expected.cpu.mmu.write(0x8010, 0xFF);
+        expected.cpu.mmu.write(0x9800, 0x01);
+        expected.cpu.mmu.write(0xFF47, 0xE4);
+        for _ in 0..100 {
+            expected.run_frame();
+        }
+        assert_eq!(screen, expected.screen());
+        assert_eq!(gb.run_frames(0), screen);
+    }
+
+    #[test]
+    fn frame_rgba() {
+        let mut gb = new_gameboy(&[0x18, 0xFE]); // JR -2
+        gb.cpu.mmu.write(0x8010, 0xFF);
+        gb.cpu.mmu.write(0x9800, 0x01);
+        gb.cpu.mmu.write(0xFF47, 0xE4);
+        gb.cpu.mmu.write(0xFF40, 0x91);
+        gb.run_frame();
+
+        let rgba = gb.frame_rgba(&Palette::DMG_GREEN);
+        assert_eq!(rgba.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT * 4);
+        // The first tile is light gray, the rest of the scanline is white
+        assert_eq!(rgba[0..4], Palette::DMG_GREEN.colors[1]);
+        assert_eq!(rgba[8 * 4..9 * 4], Palette::DMG_GREEN.colors[0]);
+        assert_eq!(Palette::DMG_GREEN.rgba(&gb.screen()), rgba);
+    }
+
+    #[test]
+    fn instruction_metadata() {
+        // RLC (HL)
+        assert_eq!(
+            super::instruction_metadata(&[0xCB, 0x06]),
+            Some((2, 16, 16))
+        );
+        // CALL NZ, a16
+        assert_eq!(super::instruction_metadata(&[0xC4]), Some((3, 12, 24)));
+        assert_eq!(super::instruction_metadata(&[0xD3]), None);
+    }
+
+    /// Records the numeric fields of every span
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<Vec<(String, i128)>>>);
+
+    impl tracing::field::Visit for SpanFields {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            let mut fields = self.0.lock().unwrap();
+            fields.push((field.name().to_string(), value.into()));
+        }
+
+        fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+            let mut fields = self.0.lock().unwrap();
+            fields.push((field.name().to_string(), value.into()));
+        }
+
+        fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn Debug) {}
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanFields {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn frame_span() {
+        use tracing_subscriber::prelude::*;
+
+        let mut gb = new_gameboy(&[0x18, 0xFE]); // JR -2
+        gb.cpu.mmu.write(0xFF40, 0x80);
+        let fields = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            gb.run_frame();
+        });
+
+        let fields = fields.0.lock().unwrap();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| *value)
+        };
+        assert_eq!(field("frame"), Some(0));
+        // JR takes 12 cycles
+        let cycles = field("cycles").unwrap();
+        let instructions = field("instructions").unwrap();
+        assert!(cycles > 0);
+        assert_eq!(cycles, instructions * 12);
+    }
+
+    #[test]
+    fn pending_interrupts() {
+        let mut gb = new_gameboy(&[0x18, 0xFE]); // JR -2
+        gb.cpu.mmu.write(INTERRUPT_ENABLE_REG, 0x05);
+        gb.cpu.mmu.write(INTERRUPT_FLAG, 0x07);
+        assert_eq!(gb.pending_interrupts(), 0x05);
+    }
+
+    /// Program which calls a subroutine at $0010 which calls a nested subroutine at
+    /// $0020
+    fn subroutine_program() -> GameBoy {
+        let mut boot_rom = vec![
+            0x31, 0xFE, 0xFF, // LD SP, $FFFE
+            0xCD, 0x10, 0x00, // CALL $0010
+            0x3C, //             INC A
+            0x18, 0xFE, //       JR -2
+        ];
+        boot_rom.resize(0x10, 0);
+        boot_rom.extend_from_slice(&[
+            0x04, //             INC B
+            0xCD, 0x20, 0x00, // CALL $0020
+            0x04, //             INC B
+            0xC9, //             RET
+        ]);
+        boot_rom.resize(0x20, 0);
+        boot_rom.extend_from_slice(&[
+            0x0C, // INC C
+            0x0C, // INC C
+            0xC9, // RET
+        ]);
+        new_gameboy(&boot_rom)
+    }
+
+    #[test]
+    fn step_over() {
+        let mut gb = subroutine_program();
+        gb.step();
+        let instr = gb.peek_instruction();
+        assert_eq!(instr.display, "CALL $0010");
+        assert_eq!(instr.address, 0x0003);
+        assert_eq!(instr.bytes, [0xCD, 0x10, 0x00]);
+
+        // The entire subroutine (including the nested call) is executed
+        assert!(gb.step_over(1000));
+        assert_eq!(gb.pc(), 0x0006);
+        assert_eq!(gb.cpu.b, 2);
+        assert_eq!(gb.cpu.c, 2);
+        assert_eq!(gb.cpu.sp, 0xFFFE);
+
+        // Other instructions are executed normally
+        assert!(gb.step_over(1000));
+        assert_eq!(gb.pc(), 0x0007);
+        assert_eq!(gb.cpu.a, 1);
+    }
+
+    #[test]
+    fn step_out() {
+        let mut gb = subroutine_program();
+        for _ in 0..3 {
+            gb.step();
+        }
+        // Inside the subroutine at $0010, before the nested call
+        assert_eq!(gb.pc(), 0x0011);
+
+        // The nested subroutine's RET does not end the step
+        assert!(gb.step_out(1000));
+        assert_eq!(gb.pc(), 0x0006);
+        assert_eq!(gb.cpu.b, 2);
+        assert_eq!(gb.cpu.c, 2);
+
+        // The main program never returns
+        assert!(!gb.step_out(1000));
+    }
+
+    #[test]
+    fn run_until() {
+        let mut gb = new_gameboy(&[
+            0x06, 0x00, // LD B, $00
+            0x04, //       INC B
+            0x18, 0xFD, // JR -3
+            0x00, //       NOP (never reached)
+        ]);
+        assert!(gb.run_until(0x0003, 100));
+        assert_eq!(gb.pc(), 0x0003);
+        assert_eq!(gb.cpu.b, 1);
+
+        // Already at the target
+        assert!(gb.run_until(0x0003, 100));
+        assert_eq!(gb.cpu.b, 1);
+
+        // Each iteration of the loop takes 16 cycles
+        assert!(gb.run_until(0x0002, 100));
+        assert!(gb.run_until(0x0003, 100));
+        assert_eq!(gb.cpu.b, 2);
+
+        // The cycle cap is respected when the target is never reached
+        assert!(!gb.run_until(0x0005, 160));
+        assert_eq!(gb.cpu.b, 12);
+        assert!(gb.pc() < 0x0005);
+    }
+
+    #[test]
+    fn total_cycles() {
+        let mut gb = new_gameboy(&[
+            0x00, //             NOP (4 cycles)
+            0x3E, 0x12, //       LD A, $12 (8 cycles)
+            0x21, 0x00, 0xC0, // LD HL, $C000 (12 cycles)
+            0x77, //             LD (HL), A (8 cycles)
+            0x00, //             NOP (4 cycles)
+        ]);
+        assert_eq!(gb.total_cycles(), 0);
+        for _ in 0..4 {
+            gb.step();
+        }
+        assert_eq!(gb.total_cycles(), 4 + 8 + 12 + 8);
+
+        gb.reset_cycle_counter();
+        assert_eq!(gb.total_cycles(), 0);
+        gb.step();
+        assert_eq!(gb.total_cycles(), 4);
+
+        gb.reset();
+        assert_eq!(gb.total_cycles(), 0);
+    }
+
+    #[test]
+    fn lcd_off_screen() {
+        let mut gb = new_gameboy(&[
+            0x3E, 0xFF, // LD A, $FF
+            0xE0, 0x47, // LDH (BGP), A
+            0x3E, 0x91, // LD A, $91
+            0xE0, 0x40, // LDH (LCDC), A
+            0x18, 0xFE, // JR -2
+        ]);
+        let screen = gb.run_frame();
+        assert!(screen.iter().all(|&color| color == Color::Black));
+
+        gb.cpu.mmu.write(0xFF40, 0x11);
+        let screen = gb.screen();
+        assert_eq!(screen.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+        assert!(screen.iter().all(|&color| color == Color::White));
+    }
+
+    /// Boot ROM program that fills the screen with a tile pattern
+    const PATTERN_PROGRAM: [u8; 22] = [
+        0x21, 0x00, 0x80, // LD HL, $8000
+        0x06, 0x10, //       LD B, 16
+        0x3E, 0x3C, //       LD A, $3C
+        0x22, //             LD (HL+), A
+        0x2F, //             CPL
+        0x05, //             DEC B
+        0x20, 0xFB, //       JR NZ, -5
+        0x3E, 0xE4, //       LD A, $E4
+        0xE0, 0x47, //       LDH (BGP), A
+        0x3E, 0x91, //       LD A, $91
+        0xE0, 0x40, //       LDH (LCDC), A
+        0x18, 0xFE, //       JR -2
+    ];
+
+    #[test]
+    fn frame_hash() {
+        let mut gb = new_gameboy(&PATTERN_PROGRAM);
+
+        // The blank screen shown while the LCD is off
+        assert_eq!(gb.frame_hash(), 0xECA4_7F65_4990_2B25);
+
+        // Every tile on the screen uses the pattern written by the program
+        gb.run_frames(2);
+        let hash = gb.frame_hash();
+        assert_eq!(hash, 0x9B10_610A_7F1B_9425);
+        assert_eq!(gb.frame_hash(), hash);
+        let screen = gb.screen();
+        assert_eq!(screen[0], Color::DarkGray);
+        assert_eq!(screen[DISPLAY_WIDTH + 2], Color::LightGray);
+
+        let mut other = new_gameboy(&PATTERN_PROGRAM);
+        other.run_frames(3);
+        assert_eq!(other.frame_hash(), hash);
+    }
+
+    #[test]
+    fn snapshot_screen() {
+        let mut gb = new_gameboy(&PATTERN_PROGRAM);
+        gb.run_frames(2);
+        let snapshot = gb.snapshot();
+
+        // The restored screen is shown before the next frame is drawn
+        let mut restored = new_gameboy(&PATTERN_PROGRAM);
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.screen(), gb.screen());
+        assert_eq!(restored.frame_hash(), gb.frame_hash());
+    }
+
+    #[test]
+    fn cartridge_options() {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0147] = 0x02;
+        rom[0x0149] = 0x01;
+        assert!(GameBoy::new_no_boot(&rom).is_err());
+
+        let options = CartridgeOptions {
+            force_ram_banks: Some(1),
+        };
+        let mut gb = GameBoy::new_with_options(&rom, &[0; 0x0100], options).unwrap();
+        assert_eq!(gb.cpu.mmu.cartridge_header().ram_banks, 1);
+
+        // The options are kept when the cartridge is reloaded
+        gb.reset();
+        assert_eq!(gb.cpu.mmu.cartridge_header().ram_banks, 1);
+        let gb = GameBoy::new_no_boot_with_options(&rom, options).unwrap();
+        assert_eq!(gb.cpu.mmu.cartridge_header().ram_banks, 1);
+    }
+
+    /// Reset DIV and run the emulator for `lines` scanlines, returning the final DIV
+    fn div_after_scanlines(gb: &mut GameBoy, lines: u8) -> u8 {
+        gb.cpu.mmu.write(0xFF04, 0);
+        let target = (gb.cpu.mmu.read(0xFF44) + lines) % 154;
+        while gb.cpu.mmu.read(0xFF44) != target {
+            gb.step();
+        }
+        gb.cpu.mmu.read(0xFF04)
+    }
+
+    #[test]
+    fn double_speed() {
+        let mut gb = new_gameboy(&[
+            0x3E, 0x80, // LD A, $80
+            0xE0, 0x40, // LDH (LCDC), A
+            0x3E, 0x01, // LD A, $01
+            0xE0, 0x4D, // LDH (KEY1), A
+            0x18, 0xFE, // JR -2
+            0x10, 0x00, // STOP
+            0x18, 0xFE, // JR -2
+        ]);
+        gb.set_cgb_enabled(true);
+        for _ in 0..4 {
+            gb.step();
+        }
+        assert_eq!(gb.cpu.mmu.read(0xFF4D), 0x7F);
+        let normal_div = div_after_scanlines(&mut gb, 20);
+        assert_eq!(normal_div, 35);
+
+        // STOP performs the speed switch without entering stop mode
+        gb.cpu.pc = 0x000A;
+        gb.step();
+        assert!(gb.double_speed());
+        assert!(!gb.cpu.is_stopped());
+        assert_eq!(gb.pc(), 0x000C);
+        assert_eq!(gb.cpu.mmu.read(0xFF4D), 0xFE);
+
+        // The timers run twice as fast relative to the PPU
+        let double_div = div_after_scanlines(&mut gb, 20);
+        assert_eq!(double_div, 71);
+    }
+
+    #[test]
+    fn new_owned() {
+        let rom = vec![0; 32 * 1024];
+        let ptr = rom.as_ptr();
+        let gb = GameBoy::new_owned(rom, None, CartridgeOptions::default()).unwrap();
+        // The ROM buffer is moved into the cartridge without being copied
+        assert_eq!(gb.rom().as_ptr(), ptr);
+        assert_eq!(gb.pc(), 0x0100);
+        assert_eq!(gb.cpu.mmu.read(0xFF40), 0x91);
+
+        let mut boot_rom = vec![0; 0x0100];
+        boot_rom[0] = 0x3C;
+        let rom = vec![0; 32 * 1024];
+        let ptr = rom.as_ptr();
+        let gb = GameBoy::new_owned(rom, Some(&boot_rom), CartridgeOptions::default()).unwrap();
+        assert_eq!(gb.rom().as_ptr(), ptr);
+        assert_eq!(gb.pc(), 0x0000);
+        assert_eq!(gb.cpu.mmu.read(0x0000), 0x3C);
+
+        assert!(matches!(
+            GameBoy::new_owned(vec![0; 32 * 1024], Some(&[0; 16]), Default::default()),
+            Err(BootError::BootRomError(_))
+        ));
+    }
 }
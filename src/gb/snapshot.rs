@@ -0,0 +1,166 @@
+//! Save state snapshots
+//!
+//! A snapshot starts with a magic header and a version number, followed by the
+//! state of each component written in a fixed order.  All multi-byte values are
+//! stored in little endian order.
+use crate::SnapshotError;
+
+/// Magic bytes at the start of every snapshot
+pub(crate) const SNAPSHOT_MAGIC: [u8; 4] = *b"QGBS";
+/// Current snapshot format version
+pub(crate) const SNAPSHOT_VERSION: u8 = 4;
+
+/// The `Snapshot` trait is implemented by each component that contributes to a save
+/// state.
+pub(crate) trait Snapshot {
+    /// Append the state of the component to `writer`
+    fn save_snapshot(&self, writer: &mut SnapshotWriter);
+
+    /// Restore the state of the component from `reader`
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError>;
+}
+
+/// Serializes values into a snapshot
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotWriter {
+    data: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value.into());
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a length-prefixed byte slice
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// Deserializes values from a snapshot
+#[derive(Debug)]
+pub(crate) struct SnapshotReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Returns `true` if every byte of the snapshot has been read
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.data.len() < len {
+            return Err(SnapshotError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidValue("bool")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, SnapshotError> {
+        Ok(i64::from_le_bytes(self.take_array()?))
+    }
+
+    /// Read a length-prefixed byte slice
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len =
+            usize::try_from(self.read_u64()?).map_err(|_| SnapshotError::InvalidValue("length"))?;
+        self.take(len)
+    }
+
+    /// Read a length-prefixed byte slice into `dest`, which must have the same length
+    pub fn read_bytes_into(&mut self, dest: &mut [u8]) -> Result<(), SnapshotError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(SnapshotError::InvalidValue("length"));
+        }
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = SnapshotWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u64(0x789A_BCDE_F012_3456);
+        writer.write_i64(-2);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = SnapshotReader::new(&data);
+        assert_eq!(reader.read_u8().unwrap(), 0x12);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u16().unwrap(), 0x3456);
+        assert_eq!(reader.read_u64().unwrap(), 0x789A_BCDE_F012_3456);
+        assert_eq!(reader.read_i64().unwrap(), -2);
+        assert_eq!(reader.read_bytes().unwrap(), &[1, 2, 3]);
+        assert!(reader.is_empty());
+        assert!(matches!(
+            reader.read_u8(),
+            Err(SnapshotError::UnexpectedEnd)
+        ));
+    }
+}
@@ -2,7 +2,11 @@
 
 use std::{collections::HashMap, fmt::Debug, ops::Range};
 
-use crate::{cartridge::Header, components::mmu};
+use crate::{
+    cartridge::Header,
+    components::{mmu, ppu},
+    Color,
+};
 
 /// Default number of instructions to fetch each time the state of the emulator is polled
 const DEFAULT_INSTRUCTION_BUFFER_SIZE: usize = 50;
@@ -16,6 +20,7 @@ pub trait PollState {
 
 /// Emulation state provided to the debugger
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct State {
     /// Cartridge state
     pub cartridge: Option<CartridgeState>,
@@ -26,8 +31,21 @@ pub struct State {
     /// Number of instructions to fetch each time the debugger polls the state of the
     /// emulator
     pub(crate) instruction_buffer_size: usize,
+    /// Determines whether the instruction buffer is disassembled each time the
+    /// debugger polls the state of the emulator
+    pub(crate) disassembly_enabled: bool,
+    /// Determines whether the tile maps and tile data are rendered each time the
+    /// debugger polls the state of the emulator.  When disabled, the images from the
+    /// last poll are kept.
+    pub(crate) tile_images_enabled: bool,
+    /// Determines which instructions from the instruction buffer are decoded each time
+    /// the debugger polls the state of the emulator
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) instruction_decoding: InstructionDecoding,
     /// State of the joypad and serial transfer registers
     pub io: Option<IoState>,
+    /// PPU state
+    pub ppu: Option<PpuState>,
 }
 
 impl State {
@@ -36,11 +54,32 @@ impl State {
     pub const EXTERNAL_RAM_START: u16 = mmu::EXTERNAL_RAM_START;
     pub const HRAM_START: u16 = mmu::HRAM_START;
     pub const WRAM_START: u16 = mmu::WRAM_START;
+
+    /// Number of instructions fetched each time the state of the emulator is polled
+    pub fn instruction_buffer_size(&self) -> usize {
+        self.instruction_buffer_size
+    }
+}
+
+/// Decoding strategy for the instruction buffer
+#[derive(Default)]
+pub enum InstructionDecoding {
+    /// Decode every instruction in the buffer each time the state is polled
+    #[default]
+    Eager,
+    /// Only decode the instructions up to the end of the window returned by the callback.
+    ///
+    /// The callback returns the range of buffer entries that are currently visible
+    /// (e.g., in the debugger's disassembly view).  Since instructions have variable
+    /// length, the entries preceding the window are also decoded; entries after the
+    /// window are not.
+    Lazy(Box<dyn Fn() -> Range<usize> + Send + Sync>),
 }
 
 /// Cartridge state
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CartridgeState {
-    /// Cartridge title specified in the header
+    /// Cartridge header
     pub header: Header,
     /// Cartridge ROM
     pub rom: Box<[u8]>,
@@ -66,6 +105,7 @@ pub struct CartridgeState {
 
 /// MMU state
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MmuState {
     pub boot_mode: bool,
     pub boot_rom: Box<[u8]>,
@@ -73,8 +113,61 @@ pub struct MmuState {
     pub hram: Box<[u8]>,
 }
 
+/// PPU state
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PpuState {
+    /// LCD control register
+    pub lcdc: u8,
+    /// LCD status register
+    pub stat: u8,
+    /// Background viewport Y position
+    pub scy: u8,
+    /// Background viewport X position
+    pub scx: u8,
+    /// LCD Y coordinate
+    pub ly: u8,
+    /// LY compare
+    pub lyc: u8,
+    /// OAM DMA source address (upper byte)
+    pub dma: u8,
+    /// BG palette data
+    pub bgp: u8,
+    /// Object palette 0 data
+    pub obp0: u8,
+    /// Object palette 1 data
+    pub obp1: u8,
+    /// Window Y position
+    pub wy: u8,
+    /// Window X position plus 7
+    pub wx: u8,
+    /// Current PPU mode (0: HBlank, 1: VBlank, 2: OAM scan, 3: drawing)
+    pub mode: u8,
+    /// Current dot within the scanline
+    pub dot: usize,
+    /// Number of frames completed
+    pub frame_count: u64,
+    /// Video RAM
+    pub vram: Box<[u8]>,
+    /// Object attribute memory
+    pub oam: Box<[u8]>,
+    /// Tile maps at 0x9800 and 0x9C00, each rendered as a square image of
+    /// `TILE_MAP_IMAGE_SIZE` pixels (in row-major order)
+    pub tile_maps: [Vec<Color>; 2],
+    /// Tiles stored in VRAM, rendered as an image of `TILE_DATA_IMAGE_WIDTH` by
+    /// `TILE_DATA_IMAGE_HEIGHT` pixels (in row-major order)
+    pub tile_data: Vec<Color>,
+}
+
+impl PpuState {
+    pub const TILE_MAP_IMAGE_SIZE: usize = ppu::TILE_MAP_IMAGE_SIZE;
+    pub const TILE_DATA_IMAGE_WIDTH: usize = ppu::TILE_DATA_IMAGE_WIDTH;
+    pub const TILE_DATA_IMAGE_HEIGHT: usize = ppu::TILE_DATA_IMAGE_HEIGHT;
+}
+
 /// CPU state
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CpuState {
     /// 8-bit register A
     pub a: u8,
@@ -118,6 +211,7 @@ pub struct CpuState {
 
 /// Information concerning a fetched instruction
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InstructionInfo {
     /// Instruction mnemonic
     pub display: String,
@@ -129,6 +223,7 @@ pub struct InstructionInfo {
 
 /// State of the joypad and serial transfer registers
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IoState {
     /// 8-bit registers
     pub registers: HashMap<String, u8>,
@@ -143,7 +238,30 @@ impl Default for State {
             cpu: Default::default(),
             mmu: Default::default(),
             instruction_buffer_size: DEFAULT_INSTRUCTION_BUFFER_SIZE,
+            disassembly_enabled: true,
+            tile_images_enabled: true,
+            instruction_decoding: InstructionDecoding::default(),
             io: Default::default(),
+            ppu: Default::default(),
+        }
+    }
+}
+
+impl InstructionDecoding {
+    /// Number of instructions to decode from a buffer of the given size
+    pub(crate) fn decode_count(&self, instruction_buffer_size: usize) -> usize {
+        match self {
+            InstructionDecoding::Eager => instruction_buffer_size,
+            InstructionDecoding::Lazy(window) => window().end.min(instruction_buffer_size),
+        }
+    }
+}
+
+impl Debug for InstructionDecoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Eager => write!(f, "Eager"),
+            Self::Lazy(_) => write!(f, "Lazy"),
         }
     }
 }
@@ -174,6 +292,13 @@ impl Default for CartridgeState {
                 ram_banks: Default::default(),
                 checksum: Default::default(),
                 checksum_passed: Default::default(),
+                global_checksum: Default::default(),
+                global_checksum_passed: Default::default(),
+                cgb_flag: Default::default(),
+                cgb_mode: crate::cartridge::CgbMode::DmgOnly,
+                sgb_flag: Default::default(),
+                old_licensee_code: Default::default(),
+                new_licensee_code: Default::default(),
             },
             rom: Default::default(),
             rom_bank0: Default::default(),
@@ -197,6 +322,28 @@ impl Debug for MmuState {
     }
 }
 
+impl Debug for PpuState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PpuState")
+            .field("lcdc", &self.lcdc)
+            .field("stat", &self.stat)
+            .field("scy", &self.scy)
+            .field("scx", &self.scx)
+            .field("ly", &self.ly)
+            .field("lyc", &self.lyc)
+            .field("dma", &self.dma)
+            .field("bgp", &self.bgp)
+            .field("obp0", &self.obp0)
+            .field("obp1", &self.obp1)
+            .field("wy", &self.wy)
+            .field("wx", &self.wx)
+            .field("mode", &self.mode)
+            .field("dot", &self.dot)
+            .field("frame_count", &self.frame_count)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Debug for CpuState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("CpuState")
@@ -235,3 +382,52 @@ impl IoState {
         msg
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serde_round_trip() {
+        let mut state = State {
+            cpu: Some(CpuState {
+                a: 0x12,
+                pc: 0x0150,
+                instructions: vec![InstructionInfo {
+                    display: "NOP".to_string(),
+                    address: 0x0150,
+                    bytes: vec![0x00],
+                }],
+                ..Default::default()
+            }),
+            io: Some(IoState {
+                registers: HashMap::from([("SB".to_string(), 0x41)]),
+                transmitted_bytes: vec![0x41],
+            }),
+            ppu: Some(PpuState {
+                ly: 0x90,
+                tile_maps: [vec![Color::Black], vec![Color::LightGray]],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        state.disassembly_enabled = false;
+        state.instruction_decoding = InstructionDecoding::Lazy(Box::new(|| 0..1));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let decoded: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+
+        let cpu = decoded.cpu.unwrap();
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.instructions[0].display, "NOP");
+        assert_eq!(decoded.io.unwrap().registers["SB"], 0x41);
+        assert_eq!(decoded.ppu.unwrap().tile_maps[1], [Color::LightGray]);
+        assert!(!decoded.disassembly_enabled);
+        // The decoding strategy is not serialized
+        assert!(matches!(
+            decoded.instruction_decoding,
+            InstructionDecoding::Eager
+        ));
+    }
+}
@@ -4,9 +4,14 @@ mod components;
 mod cpu;
 pub mod gb;
 
-pub use components::io::JoypadButton;
-pub use components::ppu::Color;
-pub use components::ppu::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+pub use cartridge::{CartridgeOptions, CartridgeType, CgbMode, Header};
+pub use components::apu::{AUDIO_CHANNELS, DEFAULT_SAMPLE_RATE};
+pub use components::io::{JoypadButton, JoypadState, SerialSink};
+pub use components::mmu::ReadWriteMemory;
+pub use components::ppu::{Color, Palette};
+pub use components::ppu::{CYCLES_PER_FRAME, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+pub use cpu::instruction::MemoryByteStream;
 pub use gb::error::*;
+pub use gb::input::{InputPlayer, InputRecorder};
 pub use gb::state::State;
 pub use gb::*;
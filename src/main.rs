@@ -1,33 +1,42 @@
 mod debugger;
 
-use std::{fs, path::PathBuf, sync::mpsc::channel, time};
+use std::{collections::HashMap, fmt::Display, fs, path::PathBuf, sync::mpsc::channel, time};
 
 use clap::Parser;
 
 use debugger::Message;
-use qgb::{Color, TCycles, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use qgb::{state::InstructionDecoding, TCycles, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
 use tracing::metadata::LevelFilter;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-const RGBA_WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
-const RGBA_LIGHT_GRAY: [u8; 4] = [0x66, 0x66, 0x66, 0xFF];
-const RGBA_DARK_GRAY: [u8; 4] = [0xB2, 0xB2, 0xB2, 0xFF];
-const RGBA_BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
-
 const DEFAULT_SCREEN_SCALE: u32 = 5;
+/// Maximum number of cycles run when stepping over or out of a subroutine (one second
+/// of emulated time)
+const STEP_CYCLE_LIMIT: TCycles = qgb::CLOCK_HZ as TCycles;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// ROM program to run
     program: PathBuf,
-    /// Boot ROM
+    /// Boot ROM (if omitted, the cartridge is started with the post-boot register values)
     #[arg(short, long)]
-    boot_rom: PathBuf,
+    boot_rom: Option<PathBuf>,
     /// Console logger (for comparing logs with other emulators)
     #[arg(short, long)]
     console_log: bool,
+    /// Reference log to compare against the console log; emulation is paused at the
+    /// first line that differs
+    #[arg(short, long)]
+    reference_log: Option<PathBuf>,
+    /// Key bindings file, with lines of the form `A = Z, J` (keys are SDL key names)
+    #[arg(short, long)]
+    key_bindings: Option<PathBuf>,
+    /// Number of external RAM banks, overriding the RAM size given in the cartridge
+    /// header (for homebrew ROMs with an incorrect header).  Must be between 1 and 16
+    #[arg(long)]
+    ram_banks: Option<usize>,
 }
 
 fn main() {
@@ -35,23 +44,83 @@ fn main() {
     init_logger();
 
     let rom = fs::read(&cli.program).unwrap();
-    let boot_rom = fs::read(&cli.boot_rom).unwrap();
+    let options = qgb::CartridgeOptions {
+        force_ram_banks: cli.ram_banks,
+    };
+    let gb = match &cli.boot_rom {
+        Some(boot_rom_path) => match fs::read(boot_rom_path) {
+            Ok(boot_rom) => qgb::GameBoy::new_owned(rom, Some(&boot_rom), options),
+            Err(e) => {
+                eprintln!("'{}': {}", boot_rom_path.display(), e);
+                return;
+            }
+        },
+        None => qgb::GameBoy::new_owned(rom, None, options),
+    };
 
-    let gb = match qgb::GameBoy::new(&rom, &boot_rom) {
+    let mut gb = match gb {
         Ok(gb) => gb,
         Err(qgb::BootError::BootRomError(e)) => {
-            eprintln!("'{}': {}", cli.boot_rom.display(), e);
+            let boot_rom_path = cli.boot_rom.unwrap_or_default();
+            eprintln!("'{}': {}", boot_rom_path.display(), e);
             return;
         }
         Err(qgb::BootError::RomError(e)) => {
-            eprintln!("'{}': {}", cli.boot_rom.display(), e);
+            eprintln!("'{}': {}", cli.program.display(), e);
             return;
         }
     };
 
-    if let Err(msg) = run(gb, cli.console_log) {
+    let reference_log = match &cli.reference_log {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(log) => Some(ReferenceLog::new(&log)),
+            Err(e) => {
+                eprintln!("'{}': {}", path.display(), e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let key_bindings = match &cli.key_bindings {
+        Some(path) => match fs::read_to_string(path).map_err(|e| e.to_string()) {
+            Ok(config) => match KeyBindings::parse(&config) {
+                Ok(key_bindings) => key_bindings,
+                Err(e) => {
+                    eprintln!("'{}': {}", path.display(), e);
+                    return;
+                }
+            },
+            Err(e) => {
+                eprintln!("'{}': {}", path.display(), e);
+                return;
+            }
+        },
+        None => KeyBindings::default(),
+    };
+
+    // Battery-backed RAM is saved next to the ROM
+    let save_path = cli.program.with_extension("sav");
+    if gb.has_battery() && save_path.exists() {
+        match fs::read(&save_path) {
+            Ok(ram) => {
+                if let Err(e) = gb.load_ram(&ram) {
+                    eprintln!("'{}': {}", save_path.display(), e);
+                }
+            }
+            Err(e) => eprintln!("'{}': {}", save_path.display(), e),
+        }
+    }
+
+    if let Err(msg) = run(&mut gb, cli.console_log, reference_log, &key_bindings) {
         eprintln!("A fatal error occurred: {}", msg);
     }
+
+    if let Some(ram) = gb.save_ram() {
+        if let Err(e) = fs::write(&save_path, ram) {
+            eprintln!("'{}': {}", save_path.display(), e);
+        }
+    }
 }
 
 fn init_logger() {
@@ -76,10 +145,6 @@ fn init_logger() {
     }
 }
 
-const CYCLES_PER_SEC: TCycles = 4 * 1024 * 1024;
-const FRAMES_PER_SEC: TCycles = 60;
-const CYCLES_PER_FRAME: TCycles = CYCLES_PER_SEC / FRAMES_PER_SEC;
-
 #[derive(Debug)]
 enum EmulatorRunState {
     Pause,
@@ -87,7 +152,12 @@ enum EmulatorRunState {
     Step,
 }
 
-fn run(mut gb: qgb::GameBoy, console_log: bool) -> Result<(), String> {
+fn run(
+    gb: &mut qgb::GameBoy,
+    console_log: bool,
+    reference_log: Option<ReferenceLog>,
+    key_bindings: &KeyBindings,
+) -> Result<(), String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
@@ -120,12 +190,18 @@ fn run(mut gb: qgb::GameBoy, console_log: bool) -> Result<(), String> {
 
     let (msg_sender, msg_receiver) = channel::<Message>();
     let mut debugger = debugger::Debugger::new(msg_sender, gb.state());
-    let mut console_logger = DefaultConsoleLogger::new(console_log, false);
+    // Only decode the instructions shown in the debugger's disassembly view
+    gb.set_instruction_decoding(InstructionDecoding::Lazy(Box::new(
+        debugger.disassembly_window(),
+    )));
+    let mut console_logger = DefaultConsoleLogger::new(console_log, false, reference_log);
     debugger.update(gb.state());
-    console_logger.print_log(&mut gb);
     let mut run_state = EmulatorRunState::Pause;
+    if let Err(divergence) = console_logger.print_log(gb) {
+        eprintln!("{}", divergence);
+    }
     let mut cycle_count: TCycles = 0;
-    let mut clock = Clock::new(time::Duration::from_secs_f64(0.016));
+    let mut clock = Clock::new(time::Duration::from_secs_f64(1.0 / qgb::FRAME_RATE));
 
     'running: loop {
         for event in event_pump.poll_iter() {
@@ -138,14 +214,14 @@ fn run(mut gb: qgb::GameBoy, console_log: bool) -> Result<(), String> {
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
-                    if let Some(button) = key_map(key) {
+                    if let Some(button) = key_map(key_bindings, key) {
                         gb.button_pressed(button);
                     }
                 }
                 Event::KeyUp {
                     keycode: Some(key), ..
                 } => {
-                    if let Some(button) = key_map(key) {
+                    if let Some(button) = key_map(key_bindings, key) {
                         gb.button_released(button);
                     }
                 }
@@ -155,10 +231,36 @@ fn run(mut gb: qgb::GameBoy, console_log: bool) -> Result<(), String> {
 
         debugger.handle_events();
         match msg_receiver.try_recv() {
-            Ok(Message::Pause) => run_state = EmulatorRunState::Pause,
-            Ok(Message::Run) => run_state = EmulatorRunState::Run,
-            Ok(Message::Step) => run_state = EmulatorRunState::Step,
+            Ok(Message::Pause) => {
+                run_state = EmulatorRunState::Pause;
+                set_inspection_enabled(gb, true);
+                debugger.update(gb.state());
+            }
+            Ok(Message::Run) => {
+                run_state = EmulatorRunState::Run;
+                set_inspection_enabled(gb, false);
+            }
+            Ok(Message::Step) => {
+                run_state = EmulatorRunState::Step;
+                set_inspection_enabled(gb, true);
+            }
+            Ok(message @ (Message::StepOver | Message::StepOut)) => {
+                run_state = EmulatorRunState::Pause;
+                set_inspection_enabled(gb, true);
+                let completed = match message {
+                    Message::StepOver => gb.step_over(STEP_CYCLE_LIMIT),
+                    _ => gb.step_out(STEP_CYCLE_LIMIT),
+                };
+                if !completed {
+                    eprintln!("subroutine did not return within {STEP_CYCLE_LIMIT} cycles");
+                }
+                debugger.update(gb.state());
+            }
             Ok(Message::Quit) => break,
+            Ok(Message::InstructionBufferSize(size)) => {
+                gb.set_instruction_buffer_size(size);
+                debugger.update(gb.state());
+            }
             Err(_) => {}
         }
 
@@ -168,30 +270,47 @@ fn run(mut gb: qgb::GameBoy, console_log: bool) -> Result<(), String> {
             }
             EmulatorRunState::Run => {
                 let breakpoints = debugger.breakpoints();
-                cycle_count += CYCLES_PER_FRAME;
+                gb.set_watchpoints(debugger.watchpoints());
+                cycle_count += qgb::CYCLES_PER_FRAME;
                 while cycle_count > 0 {
                     cycle_count -= gb.step();
-                    console_logger.print_log(&mut gb);
+                    if let Err(divergence) = console_logger.print_log(gb) {
+                        eprintln!("{}", divergence);
+                        run_state = EmulatorRunState::Pause;
+                        break;
+                    }
                     if breakpoints.contains(&gb.pc()) {
                         run_state = EmulatorRunState::Pause;
                         break;
                     }
+                    if let Some((addr, value)) = gb.take_watchpoint_hit() {
+                        eprintln!("watchpoint: wrote ${value:02X} to ${addr:04X}");
+                        run_state = EmulatorRunState::Pause;
+                        break;
+                    }
+                }
+                if matches!(run_state, EmulatorRunState::Pause) {
+                    set_inspection_enabled(gb, true);
                 }
                 debugger.update(gb.state());
             }
             EmulatorRunState::Step => {
                 cycle_count = 0;
                 gb.step();
-                console_logger.print_log(&mut gb);
+                if let Err(divergence) = console_logger.print_log(gb) {
+                    eprintln!("{}", divergence);
+                }
                 run_state = EmulatorRunState::Pause;
                 debugger.update(gb.state());
             }
         }
 
-        let pixels = colors_to_rgba32(&gb.screen());
-        texture.with_lock(None, |buffer: &mut [u8], _: usize| {
-            buffer.copy_from_slice(&pixels);
-        })?;
+        if gb.frame_ready() {
+            let pixels = qgb::Palette::GRAYSCALE.rgba(&gb.screen());
+            texture.with_lock(None, |buffer: &mut [u8], _: usize| {
+                buffer.copy_from_slice(&pixels);
+            })?;
+        }
 
         canvas.clear();
         canvas.copy(&texture, None, None)?;
@@ -203,30 +322,83 @@ fn run(mut gb: qgb::GameBoy, console_log: bool) -> Result<(), String> {
     Ok(())
 }
 
-fn colors_to_rgba32(colors: &[Color]) -> Vec<u8> {
-    let mut rgba = Vec::with_capacity(colors.len() * 4);
-    for color in colors {
-        rgba.extend_from_slice(match color {
-            Color::White => &RGBA_WHITE,
-            Color::LightGray => &RGBA_LIGHT_GRAY,
-            Color::DarkGray => &RGBA_DARK_GRAY,
-            Color::Black => &RGBA_BLACK,
-        });
+/// Enable or disable disassembling the instruction buffer and rendering the tile
+/// images when polling the emulator state.  These are only needed while the emulator
+/// is paused, since the debugger's views cannot be inspected while it is running.
+fn set_inspection_enabled(gb: &mut qgb::GameBoy, enabled: bool) {
+    gb.set_disassembly_enabled(enabled);
+    gb.set_tile_images_enabled(enabled);
+}
+
+fn key_map(key_bindings: &KeyBindings, key: Keycode) -> Option<qgb::JoypadButton> {
+    key_bindings.keys.get(&key).copied()
+}
+
+/// Mapping from keyboard keys to joypad buttons
+#[derive(Debug)]
+struct KeyBindings {
+    keys: HashMap<Keycode, qgb::JoypadButton>,
+}
+
+impl KeyBindings {
+    /// Parse a key bindings config, starting from the default bindings.
+    ///
+    /// Each line has the form `button = key, key, ...`, where `button` is the name of a
+    /// joypad button (e.g., `Start`) and each `key` is an SDL key name (e.g., `Return`).
+    /// The listed keys replace the default keys for that button.  Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(config: &str) -> Result<Self, String> {
+        let mut key_bindings = Self::default();
+        for (line_number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |msg: String| format!("line {}: {}", line_number + 1, msg);
+
+            let (name, keys) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `button = key, ...`".into()))?;
+            let name = name.trim();
+            let button = qgb::JoypadButton::all()
+                .into_iter()
+                .find(|button| format!("{:?}", button).eq_ignore_ascii_case(name))
+                .ok_or_else(|| error(format!("unknown joypad button '{}'", name)))?;
+
+            key_bindings.keys.retain(|_, bound| *bound != button);
+            for key in keys.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+                let keycode = Keycode::from_name(key)
+                    .ok_or_else(|| error(format!("unknown key '{}'", key)))?;
+                key_bindings.keys.insert(keycode, button);
+            }
+        }
+        Ok(key_bindings)
     }
-    rgba
 }
 
-fn key_map(key: Keycode) -> Option<qgb::JoypadButton> {
-    match key {
-        Keycode::Up | Keycode::W => Some(qgb::JoypadButton::Up),
-        Keycode::Down | Keycode::S => Some(qgb::JoypadButton::Down),
-        Keycode::Right | Keycode::D => Some(qgb::JoypadButton::Right),
-        Keycode::Left | Keycode::A => Some(qgb::JoypadButton::Left),
-        Keycode::Space => Some(qgb::JoypadButton::Select),
-        Keycode::Return => Some(qgb::JoypadButton::Start),
-        Keycode::Z | Keycode::J => Some(qgb::JoypadButton::A),
-        Keycode::X | Keycode::K => Some(qgb::JoypadButton::B),
-        _ => None,
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use qgb::JoypadButton;
+
+        let keys = [
+            (Keycode::Up, JoypadButton::Up),
+            (Keycode::W, JoypadButton::Up),
+            (Keycode::Down, JoypadButton::Down),
+            (Keycode::S, JoypadButton::Down),
+            (Keycode::Right, JoypadButton::Right),
+            (Keycode::D, JoypadButton::Right),
+            (Keycode::Left, JoypadButton::Left),
+            (Keycode::A, JoypadButton::Left),
+            (Keycode::Space, JoypadButton::Select),
+            (Keycode::Return, JoypadButton::Start),
+            (Keycode::Z, JoypadButton::A),
+            (Keycode::J, JoypadButton::A),
+            (Keycode::X, JoypadButton::B),
+            (Keycode::K, JoypadButton::B),
+        ];
+        Self {
+            keys: keys.into_iter().collect(),
+        }
     }
 }
 
@@ -263,42 +435,264 @@ impl Clock {
 }
 
 trait ConsoleLogger {
-    fn print_log(&mut self, gb: &mut qgb::GameBoy);
+    /// Log the current state of the emulator.
+    ///
+    /// Returns `Err` if the logged state differs from the reference log (if any).
+    fn print_log(&mut self, gb: &mut qgb::GameBoy) -> Result<(), LogDivergence>;
 }
 
 #[derive(Debug)]
 struct DefaultConsoleLogger {
     enabled: bool,
     log_boot_rom: bool,
-    boot_rom_ended: bool,
+    reference_log: Option<ReferenceLog>,
 }
 
 impl DefaultConsoleLogger {
-    pub fn new(enabled: bool, log_boot_rom: bool) -> Self {
+    pub fn new(enabled: bool, log_boot_rom: bool, reference_log: Option<ReferenceLog>) -> Self {
         Self {
             enabled,
             log_boot_rom,
-            boot_rom_ended: false,
+            reference_log,
         }
     }
 }
 
 impl ConsoleLogger for DefaultConsoleLogger {
-    fn print_log(&mut self, gb: &mut qgb::GameBoy) {
-        if self.enabled {
-            let state = gb.state();
-            if let Some(cpu_state) = &state.cpu {
-                if cpu_state.pc == 0x101 {
-                    self.boot_rom_ended = true;
-                }
-                if self.log_boot_rom || self.boot_rom_ended {
-                    let mut memory = Vec::new();
-                    for i in 0..4 {
-                        memory.extend_from_slice(&cpu_state.instructions[i].bytes[..]);
-                    }
-                    println!("A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X})", cpu_state.a, cpu_state.f, cpu_state.b, cpu_state.c, cpu_state.d, cpu_state.e, cpu_state.h, cpu_state.l, cpu_state.sp, cpu_state.pc, memory[0], memory[1], memory[2], memory[3]);
-                }
+    fn print_log(&mut self, gb: &mut qgb::GameBoy) -> Result<(), LogDivergence> {
+        let logging = self.enabled || self.reference_log.is_some();
+        if logging && (self.log_boot_rom || !gb.boot_rom_mapped()) {
+            let line = gb.trace_line();
+            if self.enabled {
+                println!("{}", line);
+            }
+            if let Some(reference_log) = &mut self.reference_log {
+                reference_log.compare(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Log produced by a known-good emulator, compared line-by-line against the console log
+#[derive(Debug)]
+struct ReferenceLog {
+    lines: Vec<String>,
+    line_number: usize,
+}
+
+/// First difference found between the console log and the reference log
+#[derive(Debug)]
+struct LogDivergence {
+    /// Line number in the reference log (starting at 1)
+    line_number: usize,
+    /// Program counter when the divergence occurred
+    pc: u16,
+    /// Name of the first field that differs
+    field: String,
+    /// Value of the field in the reference log
+    expected: String,
+    /// Value of the field in the console log
+    found: String,
+}
+
+impl ReferenceLog {
+    pub fn new(log: &str) -> Self {
+        Self {
+            lines: log
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(String::from)
+                .collect(),
+            line_number: 0,
+        }
+    }
+
+    /// Compare the next line of the reference log with the given console log line.
+    ///
+    /// Once the end of the reference log has been reached, every line is accepted.
+    pub fn compare(&mut self, line: &str) -> Result<(), LogDivergence> {
+        let Some(expected_line) = self.lines.get(self.line_number) else {
+            return Ok(());
+        };
+        self.line_number += 1;
+
+        let found_fields = parse_log_fields(line);
+        let found_value = |field: &str| {
+            found_fields
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default()
+        };
+
+        for (field, expected) in parse_log_fields(expected_line) {
+            let found = found_value(&field);
+            if !expected.eq_ignore_ascii_case(&found) {
+                return Err(LogDivergence {
+                    line_number: self.line_number,
+                    pc: u16::from_str_radix(&found_value("PC"), 16).unwrap_or_default(),
+                    field,
+                    expected,
+                    found,
+                });
             }
         }
+        Ok(())
+    }
+}
+
+impl Display for LogDivergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "divergence from reference log at line {} (PC: ${:04X}): expected {} = {}, found {} = {}",
+            self.line_number, self.pc, self.field, self.expected, self.field, self.found
+        )
+    }
+}
+
+/// Split a log line into `(field, value)` pairs.
+///
+/// Both the console logger format (`A: 01 ... PC: 00:0100 (00 C3 13 02)`) and the
+/// Gameboy Doctor format (`A:01 ... PC:0100 PCMEM:00,C3,13,02`) are understood.  The
+/// bytes at the program counter are reported as the `MEM` field.
+fn parse_log_fields(line: &str) -> Vec<(String, String)> {
+    let (registers, mut memory) = match line.split_once('(') {
+        Some((registers, memory)) => (registers, Some(memory.trim_end_matches(')').to_string())),
+        None => (line, None),
+    };
+
+    let mut fields = Vec::new();
+    let mut tokens = registers.split_whitespace();
+    while let Some(token) = tokens.next() {
+        let (field, value) = match token.split_once(':') {
+            Some((field, "")) => (field, tokens.next().unwrap_or_default()),
+            Some((field, value)) => (field, value),
+            None => continue,
+        };
+        match field {
+            // Ignore the ROM bank prefix (e.g., `00:0100`)
+            "PC" => fields.push((
+                field.into(),
+                value.rsplit(':').next().unwrap_or_default().into(),
+            )),
+            "PCMEM" => memory = Some(value.replace(',', " ")),
+            _ => fields.push((field.into(), value.into())),
+        }
+    }
+    if let Some(memory) = memory {
+        fields.push((
+            "MEM".into(),
+            memory.split_whitespace().collect::<Vec<_>>().join(" "),
+        ));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use qgb::Color;
+
+    use super::*;
+
+    #[test]
+    fn reference_log() {
+        let line =
+            "A: 01 F: B0 B: 00 C: 13 D: 00 E: D8 H: 01 L: 4D SP: FFFE PC: 00:0100 (00 C3 13 02)";
+
+        let mut reference_log = ReferenceLog::new(
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02\n\
+             A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,13,02,00\n",
+        );
+        assert!(reference_log.compare(line).is_ok());
+
+        let divergence = reference_log.compare(line).unwrap_err();
+        assert_eq!(divergence.line_number, 2);
+        assert_eq!(divergence.pc, 0x0100);
+        assert_eq!(divergence.field, "PC");
+        assert_eq!(divergence.expected, "0101");
+        assert_eq!(divergence.found, "0100");
+
+        // The end of the reference log has been reached
+        assert!(reference_log.compare(line).is_ok());
+    }
+
+    #[test]
+    fn console_logger_no_boot() {
+        let mut rom = vec![0; 32 * 1024];
+        // NOP; JP $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // INC A
+        rom[0x150] = 0x3C;
+        let mut gb = qgb::GameBoy::new_no_boot(&rom).unwrap();
+
+        let reference_log = ReferenceLog::new(
+            "A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01\n\
+             A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00\n\
+             A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3C,00,00,00\n\
+             A:02 F:00 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0151 PCMEM:00,00,00,00\n",
+        );
+        let mut logger = DefaultConsoleLogger::new(false, false, Some(reference_log));
+
+        // The first line of the log is the state at 0x0100, before any instruction runs
+        assert!(logger.print_log(&mut gb).is_ok());
+        for _ in 0..3 {
+            gb.step();
+            assert!(logger.print_log(&mut gb).is_ok());
+        }
+        assert_eq!(logger.reference_log.unwrap().line_number, 4);
+    }
+
+    #[test]
+    fn rgba_brightness() {
+        let rgba = qgb::Palette::GRAYSCALE.rgba(&[
+            Color::White,
+            Color::LightGray,
+            Color::DarkGray,
+            Color::Black,
+        ]);
+        let brightness: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[0]).collect();
+        assert!(brightness.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(rgba.chunks(4).all(|pixel| pixel[3] == 0xFF));
+    }
+
+    #[test]
+    fn key_bindings() {
+        let default = KeyBindings::default();
+        assert_eq!(
+            key_map(&default, Keycode::Return),
+            Some(qgb::JoypadButton::Start)
+        );
+        assert_eq!(key_map(&default, Keycode::Q), None);
+        for button in qgb::JoypadButton::all() {
+            assert!(default.keys.values().any(|bound| *bound == button));
+        }
+
+        let key_bindings = KeyBindings::parse(
+            "# Use the numeric keypad\n\
+             \n\
+             start = Q\n\
+             A = Keypad 1, Keypad 2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            key_map(&key_bindings, Keycode::Q),
+            Some(qgb::JoypadButton::Start)
+        );
+        assert_eq!(key_map(&key_bindings, Keycode::Return), None);
+        assert_eq!(
+            key_map(&key_bindings, Keycode::Kp2),
+            Some(qgb::JoypadButton::A)
+        );
+        assert_eq!(key_map(&key_bindings, Keycode::Z), None);
+        assert_eq!(
+            key_map(&key_bindings, Keycode::X),
+            Some(qgb::JoypadButton::B)
+        );
+
+        assert!(KeyBindings::parse("Turbo = T").is_err());
+        assert!(KeyBindings::parse("A = NotAKey").is_err());
+        assert!(KeyBindings::parse("A").is_err());
     }
 }